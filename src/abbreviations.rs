@@ -0,0 +1,111 @@
+//! Abbreviations like in fish: typing `gco` and a space at the start of a command replaces it with
+//! `git checkout ` in the line, so the command can still be edited and the history records it
+//! as it ran.
+//!
+//! They are defined in the `[abbreviations]` table of the config or with the `abbr` builtin. A
+//! word is expanded when it is in command position and not quoted, as lexed from the line up to
+//! the cursor.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use anyhow::anyhow;
+
+use crate::lexer::{Lexer, Token};
+
+thread_local! {
+    static ABBREVIATIONS: RefCell<BTreeMap<String, String>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Defines an abbreviation, replacing the one with the same name
+pub fn set(name: &str, expansion: &str) -> anyhow::Result<()> {
+    if name.is_empty() || name.chars().any(char::is_whitespace) {
+        return Err(anyhow!("abbr: `{}`: invalid abbreviation name", name));
+    }
+    ABBREVIATIONS.with(|abbreviations| {
+        abbreviations
+            .borrow_mut()
+            .insert(name.to_string(), expansion.to_string())
+    });
+    Ok(())
+}
+
+/// Removes an abbreviation and returns whether it existed
+pub fn remove(name: &str) -> bool {
+    ABBREVIATIONS.with(|abbreviations| abbreviations.borrow_mut().remove(name).is_some())
+}
+
+/// Every abbreviation, sorted by name
+pub fn list() -> Vec<(String, String)> {
+    ABBREVIATIONS.with(|abbreviations| {
+        abbreviations
+            .borrow()
+            .iter()
+            .map(|(name, expansion)| (name.clone(), expansion.clone()))
+            .collect()
+    })
+}
+
+/// The abbreviation as the command that defines it
+pub fn definition(name: &str, expansion: &str) -> String {
+    format!("abbr {} '{}'", name, expansion.replace('\'', r"'\''"))
+}
+
+/// The range of the abbreviation that ends at `cursor` and what it expands to, if the word
+/// before the cursor is an abbreviation in command position
+pub fn expansion_at(text: &str, cursor: usize) -> Option<(Range<usize>, String)> {
+    let line = &text[..cursor];
+    let mut command_start = true;
+    let mut word = None;
+    for token in Lexer::tokenize(line) {
+        if token.token() == &Token::Eof {
+            break;
+        }
+        word = match token.content() {
+            Some(content) if command_start && token.token() == &Token::Item => {
+                Some((token.span(), content.to_string()))
+            }
+            _ => None,
+        };
+        command_start = token.starts_command(command_start);
+    }
+    let (span, word) = word?;
+    // the word has to end at the cursor and have no quotes
+    if span.end != cursor || line[span.clone()] != word {
+        return None;
+    }
+    let expansion =
+        ABBREVIATIONS.with(|abbreviations| abbreviations.borrow().get(&word).cloned())?;
+    Some((span, expansion))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{definition, expansion_at, set};
+
+    #[test]
+    fn expands_words_in_command_position() {
+        set("gco", "git checkout").unwrap();
+        let expansion = |text: &str| expansion_at(text, text.len());
+        assert_eq!(expansion("gco"), Some((0..3, "git checkout".to_string())));
+        assert_eq!(
+            expansion("make && gco"),
+            Some((8..11, "git checkout".to_string()))
+        );
+        assert_eq!(expansion("echo gco"), None);
+        assert_eq!(expansion("'gco'"), None);
+        assert_eq!(expansion("gco "), None);
+        assert_eq!(expansion("gc"), None);
+        assert_eq!(
+            expansion_at("gco x", 3),
+            Some((0..3, "git checkout".to_string()))
+        );
+        assert!(set("g o", "x").is_err());
+    }
+
+    #[test]
+    fn defines_abbreviations_as_commands() {
+        assert_eq!(definition("gco", "git checkout"), "abbr gco 'git checkout'");
+    }
+}
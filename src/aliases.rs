@@ -0,0 +1,184 @@
+//! Aliases defined with the `alias` builtin, like `alias ll='ls -la'`.
+//!
+//! They are expanded on the tokens of a line before it is parsed, so an alias can stand for
+//! several commands or operators and takes precedence over builtins. A word is expanded when it
+//! is in command position and not quoted. The value of an alias is expanded again, except for the
+//! aliases being expanded already, so `alias ls='ls -F'` runs the program `ls`. When the value
+//! ends with a space the word after the alias is expanded too, for aliases like `sudo='sudo '`.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use anyhow::anyhow;
+
+use crate::lexer::{Lexer, Token, TokenShush};
+
+thread_local! {
+    static ALIASES: RefCell<BTreeMap<String, String>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Defines an alias, replacing the one with the same name
+pub fn set(name: &str, value: &str) -> anyhow::Result<()> {
+    let valid = !name.is_empty()
+        && !name.chars().any(|char| {
+            char.is_whitespace()
+                || matches!(
+                    char,
+                    '/' | '=' | '\'' | '"' | '\\' | '$' | '|' | '&' | ';' | '(' | ')' | '>'
+                )
+        });
+    if !valid {
+        return Err(anyhow!("alias: `{}`: invalid alias name", name));
+    }
+    ALIASES.with(|aliases| {
+        aliases
+            .borrow_mut()
+            .insert(name.to_string(), value.to_string())
+    });
+    Ok(())
+}
+
+pub fn get(name: &str) -> Option<String> {
+    ALIASES.with(|aliases| aliases.borrow().get(name).cloned())
+}
+
+/// Removes an alias and returns whether it existed
+pub fn remove(name: &str) -> bool {
+    ALIASES.with(|aliases| aliases.borrow_mut().remove(name).is_some())
+}
+
+pub fn clear() {
+    ALIASES.with(|aliases| aliases.borrow_mut().clear());
+}
+
+/// Every alias, sorted by name
+pub fn list() -> Vec<(String, String)> {
+    ALIASES.with(|aliases| {
+        aliases
+            .borrow()
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    })
+}
+
+pub fn names() -> Vec<String> {
+    ALIASES.with(|aliases| aliases.borrow().keys().cloned().collect())
+}
+
+/// The alias as the command that defines it, which can be sourced from the rc file
+pub fn definition(name: &str, value: &str) -> String {
+    format!("alias {}='{}'", name, value.replace('\'', r"'\''"))
+}
+
+/// Replaces the aliases in the tokens lexed from `line` with the tokens of their values. The
+/// tokens of a value take the place of the alias in the line.
+pub fn expand(line: &str, tokens: Vec<TokenShush>) -> Vec<TokenShush> {
+    let mut expanded = Vec::with_capacity(tokens.len());
+    expand_tokens(line, tokens, &mut Vec::new(), &mut expanded);
+    expanded
+}
+
+/// Pushes the tokens to `expanded`, with their aliases expanded except for the ones in
+/// `expanding`. Returns whether the word after them has to be expanded too, because they end
+/// with an alias whose value ends with a space.
+fn expand_tokens(
+    line: &str,
+    tokens: Vec<TokenShush>,
+    expanding: &mut Vec<String>,
+    expanded: &mut Vec<TokenShush>,
+) -> bool {
+    let mut command_start = true;
+    let mut expand_next = false;
+    for token in tokens {
+        let alias = match token.content() {
+            Some(word)
+                if (command_start || expand_next)
+                    && token.token() == &Token::Item
+                    && line.get(token.span()) == Some(word)
+                    && !expanding.iter().any(|name| name == word) =>
+            {
+                get(word).map(|value| (word.to_string(), value))
+            }
+            _ => None,
+        };
+        let Some((name, value)) = alias else {
+            expand_next = false;
+            command_start = token.starts_command(command_start);
+            expanded.push(token);
+            continue;
+        };
+        let span = token.span();
+        let mut value_tokens = Lexer::tokenize(&value);
+        // the line goes on after the value
+        value_tokens.pop();
+        expanding.push(name);
+        let mut value_expanded = Vec::new();
+        let nested_expand_next =
+            expand_tokens(&value, value_tokens, expanding, &mut value_expanded);
+        expanding.pop();
+        command_start = value_expanded.iter().fold(true, |command_start, token| {
+            token.starts_command(command_start)
+        });
+        expand_next = nested_expand_next || value.ends_with([' ', '\t']);
+        expanded.extend(
+            value_expanded
+                .into_iter()
+                .map(|token| token.with_span(span.clone())),
+        );
+    }
+    expand_next
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clear, definition, expand, set};
+    use crate::lexer::Lexer;
+
+    fn words(line: &str) -> Vec<String> {
+        expand(line, Lexer::tokenize(line))
+            .iter()
+            .map(|token| token.content().unwrap_or("|").to_string())
+            .collect()
+    }
+
+    #[test]
+    fn expands_aliases_in_command_position() {
+        clear();
+        set("ll", "ls -la").unwrap();
+        set("ls", "ls --color").unwrap();
+        assert_eq!(words("ll x"), ["ls", "--color", "-la", "x", "|"]);
+        assert_eq!(
+            words("echo ll | ll"),
+            ["echo", "ll", "|", "ls", "--color", "-la", "|"]
+        );
+        assert_eq!(words("'ll'"), ["ll", "|"]);
+        assert!(set("a b", "x").is_err());
+    }
+
+    #[test]
+    fn stops_at_cycles() {
+        clear();
+        set("a", "b").unwrap();
+        set("b", "a x").unwrap();
+        assert_eq!(words("a"), ["a", "x", "|"]);
+        assert_eq!(words("b"), ["b", "x", "|"]);
+    }
+
+    #[test]
+    fn expands_the_next_word_after_a_trailing_space() {
+        clear();
+        set("ll", "ls -la").unwrap();
+        set("sudo", "sudo ").unwrap();
+        set("s", "sudo").unwrap();
+        assert_eq!(words("sudo ll"), ["sudo", "ls", "-la", "|"]);
+        assert_eq!(words("s ll"), ["sudo", "ls", "-la", "|"]);
+        assert_eq!(words("sudo x ll"), ["sudo", "x", "ll", "|"]);
+    }
+
+    #[test]
+    fn defines_aliases_as_commands() {
+        assert_eq!(definition("ll", "ls -la"), "alias ll='ls -la'");
+        assert_eq!(definition("q", "echo it's"), r"alias q='echo it'\''s'");
+    }
+}
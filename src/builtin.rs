@@ -1,47 +1,607 @@
-use std::ffi::OsStr;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
 
-use crate::evaluator::{FinishedShushCmd, ShushCmd};
-use crate::timestamps;
+use crate::abbreviations;
+use crate::aliases;
+use crate::completion::{self, Argument, Spec, SpecCommand};
+use crate::config::home_dir;
+use crate::dir_stack;
+use crate::frecency;
+use crate::evaluator::ShushCmd;
+use crate::keymap::{self, Action, KeymapName};
+use crate::path_cache;
+use crate::shell_options;
 use anyhow::anyhow;
 
+/// Names of the builtins, for completion
+pub const BUILTIN_NAMES: &[&str] = &[
+    "abbr", "alias", "bind", "cd", "complete", "dirs", "hash", "popd", "pushd", "set", "type",
+    "unalias", "which", "z",
+];
+
 #[derive(Debug)]
 pub enum BuiltInCommands {
+    Abbr,
+    Alias,
+    Unalias,
     CD,
+    Dirs,
+    Popd,
+    Pushd,
+    Set,
+    Bind,
+    Complete,
+    Hash,
+    Type,
+    Which,
+    Z,
 }
 
 impl BuiltInCommands {
-    pub fn run<'a>(&'a self, cmd: &'a ShushCmd) -> anyhow::Result<FinishedShushCmd>{
-        let exit = match self {
+    /// Runs the builtin in the current shell process and returns whether it succeeded.
+    pub fn run(&self, cmd: &ShushCmd) -> anyhow::Result<bool> {
+        match self {
+            Self::Abbr => abbr(cmd),
+            Self::Alias => alias(cmd),
+            Self::Unalias => unalias(cmd),
             Self::CD => change_dir(cmd),
+            Self::Dirs => dirs(cmd),
+            Self::Popd => popd(cmd),
+            Self::Pushd => pushd(cmd),
+            Self::Set => set(cmd),
+            Self::Bind => bind(cmd),
+            Self::Complete => complete(cmd),
+            Self::Hash => hash(cmd),
+            Self::Type => type_(cmd, false),
+            Self::Which => type_(cmd, true),
+            Self::Z => z(cmd),
+        }
+    }
+}
+
+/// `abbr name expansion` defines an abbreviation, `abbr -e name` removes it and `abbr` prints
+/// them all. The words after the name are the expansion, so it does not need quotes.
+fn abbr(cmd: &ShushCmd) -> anyhow::Result<bool> {
+    let args = cmd.args();
+    match args.as_slice() {
+        [] => {
+            for (name, expansion) in abbreviations::list() {
+                println!("{}", abbreviations::definition(&name, &expansion));
+            }
+            Ok(true)
+        }
+        [flag, names @ ..] if flag == "-e" => {
+            let mut found = true;
+            for name in names {
+                if !abbreviations::remove(name) {
+                    eprintln!("abbr: {}: not found", name);
+                    found = false;
+                }
+            }
+            Ok(found)
+        }
+        [name, expansion @ ..] if !expansion.is_empty() && !name.starts_with('-') => {
+            abbreviations::set(name, &expansion.join(" "))?;
+            Ok(true)
+        }
+        _ => Err(anyhow!("usage: abbr [name expansion...] [-e name...]")),
+    }
+}
+
+/// `alias name=value` defines an alias, `alias name` prints it and `alias` or `alias -p` prints
+/// them all, as commands that define them again from the rc file.
+fn alias(cmd: &ShushCmd) -> anyhow::Result<bool> {
+    let args = cmd.args();
+    if args.is_empty() || args == ["-p"] {
+        for (name, value) in aliases::list() {
+            println!("{}", aliases::definition(&name, &value));
+        }
+        return Ok(true);
+    }
+    let mut found = true;
+    for arg in args {
+        match arg.split_once('=') {
+            Some((name, value)) => aliases::set(name, value)?,
+            None => match aliases::get(&arg) {
+                Some(value) => println!("{}", aliases::definition(&arg, &value)),
+                None => {
+                    eprintln!("alias: {}: not found", arg);
+                    found = false;
+                }
+            },
+        }
+    }
+    Ok(found)
+}
+
+/// `unalias name` removes an alias and `unalias -a` removes them all
+fn unalias(cmd: &ShushCmd) -> anyhow::Result<bool> {
+    let args = cmd.args();
+    if args.is_empty() {
+        return Err(anyhow!("usage: unalias [-a] name [name ...]"));
+    }
+    let mut found = true;
+    for arg in args {
+        if arg == "-a" {
+            aliases::clear();
+        } else if !aliases::remove(&arg) {
+            eprintln!("unalias: {}: not found", arg);
+            found = false;
+        }
+    }
+    Ok(found)
+}
+
+/// `set -o name` turns an option on, `set +o name` turns it off and `set -o` lists them.
+fn set(cmd: &ShushCmd) -> anyhow::Result<bool> {
+    let args = cmd.args();
+    match args.as_slice() {
+        [] => list_options(),
+        [flag] if flag == "-o" => list_options(),
+        [flag, name] if flag == "-o" || flag == "+o" => {
+            shell_options::set(name, flag == "-o")?;
+            Ok(true)
+        }
+        _ => Err(anyhow!("usage: set [-o|+o option]")),
+    }
+}
+
+fn list_options() -> anyhow::Result<bool> {
+    for name in shell_options::NAMES {
+        let state = if shell_options::is_on(name) {
+            "on"
+        } else {
+            "off"
+        };
+        println!("{:<15} {}", name, state);
+    }
+    Ok(true)
+}
+
+/// Lists and changes key bindings, see `keymap` for the syntax of bindings.
+///
+/// `bind -m keymap` selects the keymap the other options act on, `-l` lists the function names,
+/// `-p` prints the bindings, `-q function` prints the keys bound to a function, `-r keyseq`
+/// removes a binding and `-f file` reads bindings from a file. Other arguments are read as a
+/// binding like `bind "\C-x": kill-line`.
+fn bind(cmd: &ShushCmd) -> anyhow::Result<bool> {
+    let args = cmd.args();
+    let mut args = args.iter();
+    let mut keymap = KeymapName::current();
+    let mut binding = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or(anyhow!("bind: {} requires an argument", arg))
+        };
+        match arg.as_str() {
+            "-m" => keymap = KeymapName::parse(value()?)?,
+            "-l" => keymap::function_names().for_each(|name| println!("{}", name)),
+            "-p" => keymap::with_keymap(keymap, |keymap| {
+                for (sequence, action) in keymap.bindings() {
+                    println!(
+                        "\"{}\": {}",
+                        keymap::sequence_to_string(sequence),
+                        action.name()
+                    );
+                }
+            }),
+            "-q" => {
+                let function = Action::parse(value()?)?;
+                let sequences: Vec<String> = keymap::with_keymap(keymap, |keymap| {
+                    keymap
+                        .bindings()
+                        .into_iter()
+                        .filter(|(_, action)| *action == function)
+                        .map(|(sequence, _)| {
+                            format!("\"{}\"", keymap::sequence_to_string(sequence))
+                        })
+                        .collect()
+                });
+                if sequences.is_empty() {
+                    println!("{} is not bound to any keys", function.name());
+                } else {
+                    println!(
+                        "{} can be invoked via {}",
+                        function.name(),
+                        sequences.join(", ")
+                    );
+                }
+            }
+            "-r" => {
+                let sequence = keymap::parse_sequence(value()?.trim_matches('"'))?;
+                keymap::with_keymap(keymap, |keymap| keymap.unbind(&sequence));
+            }
+            "-f" => {
+                let path = std::path::PathBuf::from(value()?);
+                let errors: Vec<String> = keymap::read_file(&path)?
+                    .into_iter()
+                    .map(|rc_error| {
+                        format!("{}:{}: {}", path.display(), rc_error.line, rc_error.error)
+                    })
+                    .collect();
+                if !errors.is_empty() {
+                    return Err(anyhow!(errors.join("\n")));
+                }
+            }
+            _ => binding.push(arg.as_str()),
+        }
+    }
+    if !binding.is_empty() {
+        keymap::read_line(&binding.join(" "), &mut keymap)?;
+    }
+    Ok(true)
+}
+
+/// Registers how the arguments of commands are completed, see `completion` for how specs are
+/// used.
+///
+/// `-W words` gives the subcommands and the flags, `-A type` what the other arguments are (file,
+/// directory, hostname, pid, command or none) and `-C command` a command that prints the
+/// candidates. `-p` prints the specs and `-r` removes the specs of the names.
+fn complete(cmd: &ShushCmd) -> anyhow::Result<bool> {
+    let args = cmd.args();
+    let mut args = args.iter();
+    let mut spec = Spec::default();
+    let mut names = Vec::new();
+    let mut print = false;
+    let mut remove = false;
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or(anyhow!("complete: {} requires an argument", arg))
         };
+        match arg.as_str() {
+            "-W" => {
+                let (flags, subcommands) = value()?
+                    .split_whitespace()
+                    .map(String::from)
+                    .partition(|word| word.starts_with('-'));
+                spec.subcommands.extend::<Vec<String>>(subcommands);
+                spec.flags.extend::<Vec<String>>(flags);
+            }
+            "-A" => spec.argument = Argument::parse(value()?)?,
+            "-C" => spec.command = Some(value()?.to_string()),
+            "-F" => {
+                return Err(anyhow!(
+                    "complete: shell functions are not supported, use -C with a command"
+                ))
+            }
+            "-p" => print = true,
+            "-r" => remove = true,
+            _ if arg.starts_with('-') => return Err(anyhow!("complete: unknown option {}", arg)),
+            _ => names.push(arg.as_str()),
+        }
+    }
+    if remove {
+        let missing: Vec<&str> = names
+            .into_iter()
+            .filter(|name| !completion::unregister(name))
+            .collect();
+        if !missing.is_empty() {
+            return Err(anyhow!("complete: no spec for {}", missing.join(", ")));
+        }
+        return Ok(true);
+    }
+    if print || names.is_empty() {
+        for (name, spec) in completion::specs() {
+            if names.is_empty() || names.contains(&name.as_str()) {
+                println!("{}", SpecCommand(&name, &spec));
+            }
+        }
+        return Ok(true);
+    }
+    for name in names {
+        completion::register(name, spec.clone());
+    }
+    Ok(true)
+}
+
+/// `hash` lists the programs whose path is remembered, `hash name` looks a program up and
+/// remembers it, and `hash -r` forgets every path, after installing a program that is earlier in
+/// `PATH` for example.
+fn hash(cmd: &ShushCmd) -> anyhow::Result<bool> {
+    let mut names = Vec::new();
+    for arg in cmd.args() {
+        match arg.as_str() {
+            "-r" => path_cache::clear(),
+            _ if arg.starts_with('-') => return Err(anyhow!("hash: unknown option {}", arg)),
+            _ => names.push(arg),
+        }
+    }
+    if cmd.n_args() == 0 {
+        let hashed = path_cache::list();
+        if hashed.is_empty() {
+            println!("hash: hash table empty");
+        } else {
+            println!("hits    command");
+            for (_, hashed) in hashed {
+                println!("{:>4}    {}", hashed.hits, hashed.path.display());
+            }
+        }
+    }
+    let mut found = true;
+    for name in names {
+        if !BUILTIN_NAMES.contains(&name.as_str()) && path_cache::remember(&name).is_none() {
+            eprintln!("hash: {}: not found", name);
+            found = false;
+        }
+    }
+    Ok(found)
+}
 
-        match exit {
-          Ok(true) => Ok(FinishedShushCmd::new(cmd, true, timestamps::get())),
-          Ok(false) => Err(anyhow!("unknown")),
-          Err(e) => Err(e),
+/// `type name` says what runs for a name, `which name` prints the path of the program. With
+/// `-a` they show every program of that name in `PATH`, not just the one that runs.
+fn type_(cmd: &ShushCmd, which: bool) -> anyhow::Result<bool> {
+    let builtin_name = if which { "which" } else { "type" };
+    let mut all = false;
+    let mut names = Vec::new();
+    for arg in cmd.args() {
+        match arg.as_str() {
+            "-a" => all = true,
+            _ if arg.starts_with('-') => {
+                return Err(anyhow!("{}: unknown option {}", builtin_name, arg))
+            }
+            _ => names.push(arg),
         }
     }
+    let mut found = true;
+    for name in names {
+        let mut lines = Vec::new();
+        if let Some(value) = aliases::get(&name) {
+            lines.push(if which {
+                format!("{}: aliased to {}", name, value)
+            } else {
+                format!("{} is aliased to `{}'", name, value)
+            });
+        }
+        if BUILTIN_NAMES.contains(&name.as_str()) {
+            lines.push(if which {
+                format!("{}: shell builtin", name)
+            } else {
+                format!("{} is a shell builtin", name)
+            });
+        }
+        let paths = if name.contains('/') {
+            let path = PathBuf::from(&name);
+            if path_cache::is_executable(&path) {
+                vec![path]
+            } else {
+                Vec::new()
+            }
+        } else {
+            path_cache::find_all(&name)
+        };
+        let hashed = path_cache::hashed(&name);
+        for path in paths {
+            lines.push(match &hashed {
+                _ if which => path.display().to_string(),
+                Some(hashed) if *hashed == path && lines.is_empty() => {
+                    format!("{} is hashed ({})", name, path.display())
+                }
+                _ => format!("{} is {}", name, path.display()),
+            });
+        }
+        if lines.is_empty() {
+            eprintln!("{}: {}: not found", builtin_name, name);
+            found = false;
+        }
+        let shown = if all { lines.len() } else { 1 };
+        for line in lines.into_iter().take(shown) {
+            println!("{}", line);
+        }
+    }
+    Ok(found)
 }
 
 fn change_dir(cmd: &ShushCmd) -> anyhow::Result<bool> {
     if cmd.n_args() > 1 {
         return Err(anyhow!("cd accepts one or no arguments"));
     };
-    match cmd.args().first() {
-        Some(arg) => {
-            let path = std::path::Path::new(arg);
-            std::env::set_var("OLDPWD", std::env::current_dir()?);
-            std::env::set_current_dir(path)?;
-            std::env::set_var("PWD", path);
-            Ok(true)
+    let path = match cmd.args().first() {
+        Some(arg) => PathBuf::from(arg),
+        None => home_dir(),
+    };
+    let previous = go_to(&path)?;
+    if shell_options::auto_pushd() {
+        dir_stack::push(previous);
+    }
+    Ok(true)
+}
+
+/// Changes the current directory, `PWD` and `OLDPWD`, and returns the directory it left. The
+/// visit is recorded for `z`.
+fn go_to(path: &Path) -> anyhow::Result<PathBuf> {
+    let previous = std::env::current_dir()?;
+    std::env::set_current_dir(path)?;
+    let current = std::env::current_dir()?;
+    std::env::set_var("OLDPWD", &previous);
+    std::env::set_var("PWD", &current);
+    if let Err(error) = frecency::record(&current) {
+        eprintln!("shush: error while saving the visited directory: {}", error);
+    }
+    Ok(previous)
+}
+
+/// `z terms` changes to the directory visited most often and recently that matches the terms,
+/// see `frecency::matches`. `z -l terms` lists the matches with their scores, the best last, and
+/// `z -i terms` asks which one to change to.
+fn z(cmd: &ShushCmd) -> anyhow::Result<bool> {
+    let mut args = cmd.args();
+    let flag = match args.first().map(String::as_str) {
+        Some(flag @ ("-l" | "-i")) => {
+            let flag = flag.to_string();
+            args.remove(0);
+            Some(flag)
         }
-        None => {
-            let home_path = std::env::var_os("HOME").unwrap();
-            let path = std::path::Path::new::<OsStr>(home_path.as_ref());
-            std::env::set_var("OLDPWD", std::env::current_dir()?);
-            std::env::set_current_dir(path)?;
-            std::env::set_var("PWD", path);
+        Some(flag) if flag.starts_with('-') => return Err(anyhow!("z: unknown option {}", flag)),
+        None => Some("-l".to_string()),
+        Some(_) => None,
+    };
+    let entries = frecency::load()?;
+    let now = frecency::now();
+    let found = frecency::matches(&entries, &args, now);
+    if flag.as_deref() == Some("-l") {
+        for entry in found.iter().rev() {
+            println!("{:<10.1} {}", entry.score(now), entry.path.display());
+        }
+        return Ok(true);
+    }
+    let chosen = match flag.as_deref() {
+        Some(_) => choose(&found, &mut io::stdin().lock())?,
+        None => found.first().copied(),
+    };
+    match chosen {
+        Some(entry) => {
+            go_to(&entry.path)?;
             Ok(true)
         }
+        None if found.is_empty() => Err(anyhow!("z: no directory matches {}", args.join(" "))),
+        None => Ok(false),
+    }
+}
+
+/// Lists the best matches numbered from 1 and reads a line with the number of one. Any other
+/// line changes to none of them.
+fn choose<'a>(
+    found: &[&'a frecency::Entry],
+    input: &mut impl BufRead,
+) -> anyhow::Result<Option<&'a frecency::Entry>> {
+    let shown = &found[..found.len().min(9)];
+    for (index, entry) in shown.iter().enumerate() {
+        println!("{}  {}", index + 1, dir_stack::display(&entry.path, false));
+    }
+    if shown.is_empty() {
+        return Ok(None);
+    }
+    print!("z: 1-{}? ", shown.len());
+    io::stdout().flush()?;
+    // the whole line is read, so that its newline is not left for the next prompt
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        println!();
+    }
+    let choice = line
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| shown.get(number.checked_sub(1)?));
+    Ok(choice.copied())
+}
+
+/// `pushd dir` saves the current directory on the stack and changes to `dir`, `pushd` swaps the
+/// first two entries and `pushd +N` or `-N` rotates the stack to bring that entry to the top.
+/// The stack is printed after it changes.
+fn pushd(cmd: &ShushCmd) -> anyhow::Result<bool> {
+    let args = cmd.args();
+    let mut entries = dir_stack::entries();
+    match args.as_slice() {
+        [] if entries.len() < 2 => return Err(anyhow!("pushd: no other directory")),
+        [] => entries.swap(0, 1),
+        [arg] => match dir_stack::position(arg, entries.len()) {
+            Some(index) => entries.rotate_left(index?),
+            None => {
+                dir_stack::push(go_to(Path::new(arg))?);
+                print_dirs(&dir_stack::entries(), false, false, false);
+                return Ok(true);
+            }
+        },
+        _ => return Err(anyhow!("usage: pushd [dir | +N | -N]")),
+    }
+    go_to(&entries[0])?;
+    dir_stack::set_entries(&entries);
+    print_dirs(&entries, false, false, false);
+    Ok(true)
+}
+
+/// `popd` removes the top of the stack and changes to the next entry, `popd +N` or `-N` removes
+/// that entry
+fn popd(cmd: &ShushCmd) -> anyhow::Result<bool> {
+    let args = cmd.args();
+    let mut entries = dir_stack::entries();
+    if entries.len() < 2 {
+        return Err(anyhow!("popd: directory stack empty"));
+    }
+    let index = match args.as_slice() {
+        [] => 0,
+        [arg] => dir_stack::position(arg, entries.len())
+            .ok_or(anyhow!("usage: popd [+N | -N]"))??,
+        _ => return Err(anyhow!("usage: popd [+N | -N]")),
+    };
+    entries.remove(index);
+    if index == 0 {
+        go_to(&entries[0])?;
+    }
+    dir_stack::set_entries(&entries);
+    print_dirs(&entries, false, false, false);
+    Ok(true)
+}
+
+/// Prints the directory stack. `-v` numbers the entries, `-p` prints one per line, `-l` does not
+/// abbreviate the home directory to `~`, `+N` or `-N` prints that entry and `-c` clears the
+/// stack.
+fn dirs(cmd: &ShushCmd) -> anyhow::Result<bool> {
+    let entries = dir_stack::entries();
+    let (mut long, mut per_line, mut numbered) = (false, false, false);
+    let mut index = None;
+    for arg in cmd.args() {
+        match arg.as_str() {
+            "-c" => {
+                dir_stack::clear();
+                return Ok(true);
+            }
+            "-l" => long = true,
+            "-p" => per_line = true,
+            "-v" => numbered = true,
+            _ => match dir_stack::position(&arg, entries.len()) {
+                Some(position) => index = Some(position?),
+                None => return Err(anyhow!("usage: dirs [-clpv] [+N | -N]")),
+            },
+        }
+    }
+    match index {
+        Some(index) => println!("{}", dir_stack::display(&entries[index], long)),
+        None => print_dirs(&entries, long, per_line, numbered),
+    }
+    Ok(true)
+}
+
+fn print_dirs(entries: &[PathBuf], long: bool, per_line: bool, numbered: bool) {
+    let shown = entries.iter().map(|dir| dir_stack::display(dir, long));
+    if numbered {
+        for (index, dir) in shown.enumerate() {
+            println!("{:>2}  {}", index, dir);
+        }
+    } else if per_line {
+        shown.for_each(|dir| println!("{}", dir));
+    } else {
+        println!("{}", shown.collect::<Vec<_>>().join(" "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufRead;
+    use std::path::PathBuf;
+
+    use super::choose;
+    use crate::frecency::Entry;
+
+    #[test]
+    fn chooses_a_directory_by_the_number_on_a_line() {
+        let entry = |path: &str| Entry {
+            path: PathBuf::from(path),
+            rank: 1.0,
+            time: 0,
+        };
+        let entries = [entry("/src"), entry("/tmp")];
+        let found: Vec<&Entry> = entries.iter().collect();
+        let mut input = &b"2\nls\n"[..];
+        let chosen = choose(&found, &mut input).unwrap();
+        assert_eq!(chosen.map(|entry| &entry.path), Some(&entries[1].path));
+        // the rest of the input is left for the line editor
+        assert_eq!(input.lines().next().unwrap().unwrap(), "ls");
+        assert!(choose(&found, &mut &b"x\n"[..]).unwrap().is_none());
+        assert!(choose(&found, &mut &b"3\n"[..]).unwrap().is_none());
     }
 }
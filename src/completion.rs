@@ -0,0 +1,793 @@
+//! Tab completion of the word before the cursor.
+//!
+//! The first word of a command completes to builtins and programs in `PATH`. The words after it
+//! complete with the spec registered for the command with the `complete` builtin, or to file
+//! names if it has none. A spec lists the subcommands and flags of the command and what its other
+//! arguments are, or names a command that prints the candidates:
+//!
+//! ```text
+//! complete -W "status commit push --help" -A file git
+//! complete -A directory cd
+//! complete -C "make -qp | awk -F: '/^[a-z]/ {print $1}'" make
+//! ```
+//!
+//! The command of `-C` runs with `sh -c`, gets the line in `COMP_LINE` and the word being
+//! completed in `COMP_WORD`, and is killed if it has not finished after a second. Generators are
+//! commands rather than shell functions like the `-F` of bash, because the shell has no
+//! functions to call, and `sh` can run a pipeline or a script all the same.
+//!
+//! Specs for `cd`, `git`, `cargo`, `ssh` and `kill` are registered when the shell starts. Flags
+//! are also read from the options found in man pages by `shush --generate-completions`, see
+//! `manpages`, which have descriptions that the menu shows next to them.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::anyhow;
+
+use crate::aliases;
+use crate::builtin::BUILTIN_NAMES;
+use crate::config::{expand_tilde, home_dir};
+use crate::editor::text_width;
+use crate::manpages;
+use crate::matcher::{self, MatchMode};
+use crate::path_cache;
+
+/// A word that the word before the cursor can be completed to
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Candidate {
+    pub text: String,
+    /// Shown next to the candidate in the menu, like the name of the process of a PID
+    pub description: Option<String>,
+    /// Byte offsets of the chars that matched the word, highlighted in the menu
+    pub matched: Vec<usize>,
+}
+
+impl Candidate {
+    pub fn new(text: impl Into<String>) -> Candidate {
+        Candidate {
+            text: text.into(),
+            description: None,
+            matched: Vec::new(),
+        }
+    }
+}
+
+/// What the arguments of a command that are not subcommands or flags are, named like the
+/// actions of `complete -A` in bash
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Argument {
+    #[default]
+    File,
+    Directory,
+    Hostname,
+    Pid,
+    Command,
+    /// The command takes no other arguments
+    None,
+}
+
+const ARGUMENTS: &[(&str, Argument)] = &[
+    ("file", Argument::File),
+    ("directory", Argument::Directory),
+    ("hostname", Argument::Hostname),
+    ("pid", Argument::Pid),
+    ("command", Argument::Command),
+    ("none", Argument::None),
+];
+
+impl Argument {
+    pub fn parse(name: &str) -> anyhow::Result<Argument> {
+        ARGUMENTS
+            .iter()
+            .find(|(argument_name, _)| *argument_name == name)
+            .map(|(_, argument)| *argument)
+            .ok_or(anyhow!("unknown argument type `{}`", name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        ARGUMENTS
+            .iter()
+            .find(|(_, argument)| argument == self)
+            .map(|(name, _)| *name)
+            .unwrap_or_default()
+    }
+}
+
+/// How the arguments of a command are completed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Spec {
+    /// Words accepted as the first argument, like `commit` for `git`
+    pub subcommands: Vec<String>,
+    /// Options, offered when the word starts with `-`
+    pub flags: Vec<String>,
+    pub argument: Argument,
+    /// Command whose output lines are the candidates, instead of the subcommands and the
+    /// argument type. It gets the line in `COMP_LINE`, the word in `COMP_WORD` and the word
+    /// before it in `COMP_PREVIOUS`.
+    pub command: Option<String>,
+}
+
+impl Spec {
+    fn new(subcommands: &str, flags: &str, argument: Argument) -> Spec {
+        Spec {
+            subcommands: subcommands.split_whitespace().map(String::from).collect(),
+            flags: flags.split_whitespace().map(String::from).collect(),
+            argument,
+            command: None,
+        }
+    }
+}
+
+/// The spec as the `complete` command that registers it, for `complete -p`
+pub struct SpecCommand<'a>(pub &'a str, pub &'a Spec);
+
+impl fmt::Display for SpecCommand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let SpecCommand(name, spec) = self;
+        write!(f, "complete")?;
+        let words: Vec<&str> = spec
+            .subcommands
+            .iter()
+            .chain(&spec.flags)
+            .map(String::as_str)
+            .collect();
+        if !words.is_empty() {
+            write!(f, " -W \"{}\"", words.join(" "))?;
+        }
+        if spec.argument != Argument::File {
+            write!(f, " -A {}", spec.argument.name())?;
+        }
+        if let Some(command) = &spec.command {
+            write!(f, " -C \"{}\"", command.replace('"', "\\\""))?;
+        }
+        write!(f, " {}", name)
+    }
+}
+
+thread_local! {
+    static SPECS: RefCell<BTreeMap<String, Spec>> = RefCell::new(builtin_specs());
+}
+
+fn builtin_specs() -> BTreeMap<String, Spec> {
+    let mut specs = BTreeMap::new();
+    specs.insert("cd".to_string(), Spec::new("", "", Argument::Directory));
+    specs.insert(
+        "git".to_string(),
+        Spec::new(
+            "add bisect blame branch checkout cherry-pick clean clone commit config diff fetch \
+             grep init log merge mv pull push rebase remote reset restore revert rm show stash \
+             status switch tag",
+            "--help --version --no-pager -C -c",
+            Argument::File,
+        ),
+    );
+    specs.insert(
+        "cargo".to_string(),
+        Spec::new(
+            "add bench build check clean clippy doc fetch fix fmt init install metadata new \
+             publish remove run search test tree uninstall update",
+            "--help --version --verbose --quiet --release --workspace --all-targets --all-features \
+             --features --package --bin --example --target --offline --locked",
+            Argument::File,
+        ),
+    );
+    specs.insert(
+        "ssh".to_string(),
+        Spec::new(
+            "",
+            "-4 -6 -A -C -D -F -i -J -L -l -N -o -p -R -T -t -v -X -Y",
+            Argument::Hostname,
+        ),
+    );
+    specs.insert(
+        "kill".to_string(),
+        Spec::new(
+            "",
+            "-l -s -HUP -INT -QUIT -KILL -USR1 -USR2 -TERM -CONT -STOP",
+            Argument::Pid,
+        ),
+    );
+    specs
+}
+
+pub fn register(name: &str, spec: Spec) {
+    SPECS.with(|specs| specs.borrow_mut().insert(name.to_string(), spec));
+}
+
+/// Removes the spec of a command, returning whether it had one
+pub fn unregister(name: &str) -> bool {
+    SPECS.with(|specs| specs.borrow_mut().remove(name).is_some())
+}
+
+pub fn specs() -> Vec<(String, Spec)> {
+    SPECS.with(|specs| {
+        specs
+            .borrow()
+            .iter()
+            .map(|(name, spec)| (name.clone(), spec.clone()))
+            .collect()
+    })
+}
+
+fn spec(name: &str) -> Option<Spec> {
+    SPECS.with(|specs| specs.borrow().get(name).cloned())
+}
+
+/// Candidates for the word that starts at `start`, best first and without duplicates
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Completions {
+    pub start: usize,
+    /// The word without quotes
+    pub word: String,
+    pub candidates: Vec<Candidate>,
+}
+
+impl Completions {
+    /// What the word becomes: a single candidate with a space after it, unless it is a
+    /// directory, or what all the candidates start with if it is longer than the word
+    pub fn replacement(&self) -> Option<String> {
+        match self.candidates.as_slice() {
+            [] => None,
+            [candidate] if candidate.text.ends_with('/') => Some(escape(&candidate.text)),
+            [candidate] => Some(escape(&candidate.text) + " "),
+            [first, rest @ ..] => {
+                let mut prefix = first.text.as_str();
+                for candidate in rest {
+                    let common = prefix
+                        .char_indices()
+                        .zip(candidate.text.chars())
+                        .find(|((_, a), b)| a != b)
+                        .map_or(prefix.len().min(candidate.text.len()), |((index, _), _)| {
+                            index
+                        });
+                    prefix = &prefix[..common];
+                }
+                // fuzzy matches can have less in common than the word
+                (prefix.len() > self.word.len() && prefix.starts_with(&self.word))
+                    .then(|| escape(prefix))
+            }
+        }
+    }
+
+    /// What completing does to `typed`, the word as it is in the line: it is replaced when the
+    /// candidates have more in common than it, and they are listed when there are several
+    pub fn completion(&self, typed: &str) -> Completion {
+        match self.replacement() {
+            Some(replacement) if replacement != typed => Completion::Replace(replacement),
+            _ if self.candidates.len() > 1 => Completion::List,
+            _ => Completion::Nothing,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Completion {
+    Replace(String),
+    List,
+    Nothing,
+}
+
+/// Completes the last word of `text`, the line up to the cursor. Only what is after the last `/`
+/// of the word is matched, as candidates in a directory all start with it.
+pub fn complete(text: &str, mode: MatchMode) -> Completions {
+    let line = split_line(text);
+    let mut candidates = match line.words.first() {
+        None if line.word.contains('/') => files(&line.word, false),
+        None => commands(),
+        Some(command) => {
+            let name = command.rsplit('/').next().unwrap_or(command);
+            arguments(name, &line, text)
+        }
+    };
+    candidates.sort();
+    // a flag of a spec can also be in the man page, with a description
+    candidates.dedup_by(|next, previous| {
+        if next.text != previous.text {
+            return false;
+        }
+        if previous.description.is_none() {
+            previous.description = next.description.take();
+        }
+        true
+    });
+    let dir = line.word.rfind('/').map_or(0, |index| index + 1);
+    let (dir, pattern) = line.word.split_at(dir);
+    let mut scored: Vec<(i64, Candidate)> = candidates
+        .into_iter()
+        .filter_map(|mut candidate| {
+            let name = candidate.text.strip_prefix(dir)?;
+            let found = matcher::find(mode, pattern, name)?;
+            candidate.matched = found
+                .positions
+                .iter()
+                .map(|position| dir.len() + position)
+                .collect();
+            Some((found.score, candidate))
+        })
+        .collect();
+    // the sort is stable, so candidates with the same score stay sorted by name
+    scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    Completions {
+        start: line.start,
+        word: line.word,
+        candidates: scored.into_iter().map(|(_, candidate)| candidate).collect(),
+    }
+}
+
+fn arguments(name: &str, line: &Line, text: &str) -> Vec<Candidate> {
+    let spec = spec(name).unwrap_or_default();
+    if let Some(command) = &spec.command {
+        let previous = line.words.last().map_or("", String::as_str);
+        return run_generator(command, &line.word, previous, text);
+    }
+    if line.word.starts_with('-') {
+        let mut flags: Vec<Candidate> = spec.flags.iter().map(Candidate::new).collect();
+        flags.extend(manpages::options(name));
+        if !flags.is_empty() {
+            return flags;
+        }
+    }
+    if line.words.len() == 1 && !spec.subcommands.is_empty() {
+        return spec.subcommands.iter().map(Candidate::new).collect();
+    }
+    match spec.argument {
+        Argument::File => files(&line.word, false),
+        Argument::Directory => files(&line.word, true),
+        Argument::Hostname => hosts(&line.word),
+        Argument::Pid => pids(),
+        Argument::Command => commands(),
+        Argument::None => Vec::new(),
+    }
+}
+
+/// The words of the command that the cursor is in, without quotes
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Line {
+    /// Words before the one being completed
+    words: Vec<String>,
+    /// Byte offset where the word being completed starts, with its quotes
+    start: usize,
+    word: String,
+}
+
+/// Splits the line up to the cursor in words, starting over after operators like `|` or `&&`
+fn split_line(text: &str) -> Line {
+    let mut line = Line::default();
+    let mut start = None;
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, char) in text.char_indices() {
+        if escaped {
+            line.word.push(char);
+            escaped = false;
+            continue;
+        }
+        match (quote, char) {
+            (Some(open), _) if char == open => quote = None,
+            (Some('"'), '\\') => escaped = true,
+            (Some(_), _) => line.word.push(char),
+            (None, '\\') => escaped = true,
+            (None, '\'' | '"') => quote = Some(char),
+            (None, ' ' | '\t' | '\n' | '|' | '&' | ';' | '(' | ')' | '<' | '>') => {
+                if start.take().is_some() {
+                    line.words.push(std::mem::take(&mut line.word));
+                }
+                if !char.is_whitespace() && !matches!(char, '<' | '>') {
+                    line.words.clear();
+                }
+                continue;
+            }
+            (None, _) => line.word.push(char),
+        }
+        start.get_or_insert(index);
+    }
+    line.start = start.unwrap_or(text.len());
+    line
+}
+
+/// Escapes the chars that the lexer would take as quotes or operators
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for char in text.chars() {
+        if matches!(
+            char,
+            ' ' | '\t' | '\'' | '"' | '\\' | '|' | '&' | ';' | '(' | ')' | '<' | '>' | '$'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(char);
+    }
+    escaped
+}
+
+/// Aliases, builtins and programs in `PATH`
+fn commands() -> Vec<Candidate> {
+    aliases::names()
+        .into_iter()
+        .map(Candidate::new)
+        .chain(BUILTIN_NAMES.iter().copied().map(Candidate::new))
+        .chain(path_cache::names().into_iter().map(Candidate::new))
+        .collect()
+}
+
+/// Entries of the directory of the word, with a `/` after directories. Hidden files are only
+/// offered when the name in the word starts with a dot.
+fn files(word: &str, directories_only: bool) -> Vec<Candidate> {
+    let (dir, prefix) = match word.rfind('/') {
+        Some(index) => word.split_at(index + 1),
+        None => ("", word),
+    };
+    let path = match dir {
+        "" => PathBuf::from("."),
+        dir => expand_tilde(Path::new(dir)),
+    };
+    let Ok(entries) = fs::read_dir(path) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if name.starts_with('.') && !prefix.starts_with('.') {
+                return None;
+            }
+            // symlinks to directories count as directories
+            let is_dir = entry.path().is_dir();
+            if directories_only && !is_dir {
+                return None;
+            }
+            let slash = if is_dir { "/" } else { "" };
+            Some(Candidate::new(format!("{}{}{}", dir, name, slash)))
+        })
+        .collect()
+}
+
+/// Hosts named in `~/.ssh/config`, after the user in words like `user@host`
+fn hosts(word: &str) -> Vec<Candidate> {
+    let user = word.rfind('@').map_or("", |index| &word[..=index]);
+    let config = fs::read_to_string(home_dir().join(".ssh").join("config")).unwrap_or_default();
+    ssh_hosts(&config)
+        .into_iter()
+        .map(|host| Candidate::new(format!("{}{}", user, host)))
+        .collect()
+}
+
+/// Names of the `Host` entries of an ssh config, without patterns
+fn ssh_hosts(config: &str) -> Vec<&str> {
+    config
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            let (keyword, hosts) =
+                line.split_once(|char: char| char == '=' || char.is_whitespace())?;
+            keyword.eq_ignore_ascii_case("host").then_some(hosts)
+        })
+        .flat_map(|hosts| hosts.split(|char: char| char == '=' || char.is_whitespace()))
+        .filter(|host| !host.is_empty() && !host.contains(['*', '?', '!']))
+        .collect()
+}
+
+/// Running processes, described by their name
+fn pids() -> Vec<Candidate> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let pid = entry.file_name().into_string().ok()?;
+            if !pid.bytes().all(|byte| byte.is_ascii_digit()) {
+                return None;
+            }
+            let name = fs::read_to_string(entry.path().join("comm")).ok();
+            let mut candidate = Candidate::new(pid);
+            candidate.description = name.map(|name| name.trim_end().to_string());
+            Some(candidate)
+        })
+        .collect()
+}
+
+/// Time the command of a spec has to print its candidates before it is killed
+const GENERATOR_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Lines printed by the command of a spec. Errors are ignored, as there is no place to show
+/// them while typing, and a command that takes too long gives no candidates.
+fn run_generator(command: &str, word: &str, previous: &str, line: &str) -> Vec<Candidate> {
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("COMP_LINE", line)
+        .env("COMP_POINT", line.len().to_string())
+        .env("COMP_WORD", word)
+        .env("COMP_PREVIOUS", previous)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        // in a group of its own, so that the whole pipeline can be killed
+        .process_group(0)
+        .spawn();
+    let Ok(mut child) = child else {
+        return Vec::new();
+    };
+    let Some(mut stdout) = child.stdout.take() else {
+        return Vec::new();
+    };
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = stdout.read_to_end(&mut output);
+        let _ = sender.send(output);
+    });
+    let output = receiver.recv_timeout(GENERATOR_TIMEOUT);
+    if output.is_err() {
+        unsafe { libc::kill(-(child.id() as i32), libc::SIGKILL) };
+    }
+    let _ = child.wait();
+    match output {
+        Ok(output) => String::from_utf8_lossy(&output)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(Candidate::new)
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Style of the chars of the candidates that matched the word in the menu
+pub const MATCH_STYLE: &str = "\x1b[1m";
+const MATCH_STYLE_END: &str = "\x1b[22m";
+
+/// Candidates listed below the line, in columns, or one per row with their descriptions
+pub fn menu(candidates: &[Candidate], columns: usize) -> String {
+    let width = candidates
+        .iter()
+        .map(|candidate| text_width(label(&candidate.text)))
+        .max()
+        .unwrap_or(0)
+        + 2;
+    let mut output = String::new();
+    if candidates
+        .iter()
+        .any(|candidate| candidate.description.is_some())
+    {
+        for candidate in candidates {
+            let mut row = label(&candidate.text).to_string();
+            if let Some(description) = &candidate.description {
+                row.push_str(&" ".repeat(width - text_width(&row)));
+                row.push_str(&format!("({})", description));
+            }
+            output.push_str(&highlight(candidate, &truncate(&row, columns)));
+            output.push_str("\r\n");
+        }
+        return output;
+    }
+    let per_row = std::cmp::max(columns / width, 1);
+    for row in candidates.chunks(per_row) {
+        for (index, candidate) in row.iter().enumerate() {
+            let label = label(&candidate.text);
+            output.push_str(&highlight(candidate, label));
+            if index + 1 < row.len() {
+                output.push_str(&" ".repeat(width - text_width(label)));
+            }
+        }
+        output.push_str("\r\n");
+    }
+    output
+}
+
+/// Makes the chars of a row of the menu that matched the word bold. The row starts with the
+/// label of the candidate.
+fn highlight(candidate: &Candidate, row: &str) -> String {
+    let offset = candidate.text.len() - label(&candidate.text).len();
+    let label_end = candidate.text.len() - offset;
+    let mut highlighted = String::with_capacity(row.len());
+    for (index, char) in row.char_indices() {
+        if index < label_end && candidate.matched.contains(&(offset + index)) {
+            highlighted.push_str(MATCH_STYLE);
+            highlighted.push(char);
+            highlighted.push_str(MATCH_STYLE_END);
+        } else {
+            highlighted.push(char);
+        }
+    }
+    highlighted
+}
+
+/// How a candidate is listed: files without the directory they are in, as they all share it
+fn label(text: &str) -> &str {
+    let name = text.strip_suffix('/').unwrap_or(text);
+    match name.rfind('/') {
+        Some(index) => &text[index + 1..],
+        None => text,
+    }
+}
+
+/// Cuts text to fit in `columns`
+fn truncate(text: &str, columns: usize) -> String {
+    let mut width = 0;
+    text.chars()
+        .take_while(|char| {
+            width += text_width(char.encode_utf8(&mut [0; 4]));
+            width <= columns
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::{
+        complete, escape, menu, run_generator, split_line, ssh_hosts, Candidate, Completion,
+        Completions, Line, GENERATOR_TIMEOUT,
+    };
+    use crate::matcher::MatchMode;
+
+    fn candidates(texts: &[&str]) -> Vec<Candidate> {
+        texts.iter().map(|text| Candidate::new(*text)).collect()
+    }
+
+    #[test]
+    fn splits_the_command_under_the_cursor() {
+        assert_eq!(
+            split_line("ls | git com"),
+            Line {
+                words: vec!["git".to_string()],
+                start: 9,
+                word: "com".to_string(),
+            }
+        );
+        assert_eq!(
+            split_line("cd \"My Doc"),
+            Line {
+                words: vec!["cd".to_string()],
+                start: 3,
+                word: "My Doc".to_string(),
+            }
+        );
+        assert_eq!(split_line("echo a\\ b ").words, ["echo", "a b"]);
+        assert_eq!(split_line("true && ").start, 8);
+    }
+
+    #[test]
+    fn replaces_the_word_with_what_candidates_share() {
+        let completions = |word: &str, texts: &[&str]| Completions {
+            start: 0,
+            word: word.to_string(),
+            candidates: candidates(texts),
+        };
+        assert_eq!(completions("x", &[]).replacement(), None);
+        assert_eq!(
+            completions("st", &["status"]).replacement(),
+            Some("status ".to_string())
+        );
+        assert_eq!(
+            completions("My", &["My Doc/"]).replacement(),
+            Some("My\\ Doc/".to_string())
+        );
+        assert_eq!(
+            completions("ch", &["checkout", "cherry-pick"]).replacement(),
+            Some("che".to_string())
+        );
+        assert_eq!(
+            completions("che", &["checkout", "cherry-pick"]).replacement(),
+            None
+        );
+        assert_eq!(
+            completions("mn", &["main.rs", "mini.rs"]).replacement(),
+            None
+        );
+    }
+
+    #[test]
+    fn lists_candidates_with_nothing_more_in_common() {
+        let completion = |word: &str, texts: &[&str]| {
+            Completions {
+                start: 0,
+                word: word.to_string(),
+                candidates: candidates(texts),
+            }
+            .completion(word)
+        };
+        assert_eq!(
+            completion("c", &["checkout", "cherry-pick"]),
+            Completion::Replace("che".to_string())
+        );
+        assert_eq!(
+            completion("che", &["checkout", "cherry-pick"]),
+            Completion::List
+        );
+        assert_eq!(completion("", &["add", "status"]), Completion::List);
+        assert_eq!(completion("mn", &["main.rs", "mini.rs"]), Completion::List);
+        assert_eq!(completion("x", &[]), Completion::Nothing);
+    }
+
+    #[test]
+    fn completes_subcommands_flags_and_directories() {
+        let texts = |completions: Completions| -> Vec<String> {
+            completions
+                .candidates
+                .into_iter()
+                .map(|candidate| candidate.text)
+                .collect()
+        };
+        assert_eq!(
+            texts(complete("git sta", MatchMode::Prefix)),
+            ["stash", "status"]
+        );
+        assert_eq!(
+            texts(complete("cargo build --rel", MatchMode::Prefix)),
+            ["--release"]
+        );
+        let dir = std::env::temp_dir().join("shush_complete_test");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("file"), "").unwrap();
+        let line = format!("cd {}/", dir.display());
+        let completions = complete(&line, MatchMode::Prefix);
+        assert_eq!(completions.start, 3);
+        assert_eq!(texts(completions), [format!("{}/sub/", dir.display())]);
+        let line = format!("cd {}/sb", dir.display());
+        let completions = complete(&line, MatchMode::Fuzzy);
+        let prefix = format!("cd {}/", dir.display()).len();
+        assert_eq!(completions.candidates[0].matched, [prefix - 3, prefix - 1]);
+    }
+
+    #[test]
+    fn reads_hosts_of_ssh_config() {
+        let config = "Host web db\n  HostName 10.0.0.1\nHost *.internal\nhost=backup\n";
+        assert_eq!(ssh_hosts(config), ["web", "db", "backup"]);
+    }
+
+    #[test]
+    fn escapes_special_chars() {
+        assert_eq!(escape("a b&c'$"), "a\\ b\\&c\\'\\$");
+    }
+
+    #[test]
+    fn lists_candidates_in_columns() {
+        let list = candidates(&["add", "branch", "commit", "diff"]);
+        assert_eq!(menu(&list, 20), "add     branch\r\ncommit  diff\r\n");
+        let mut described = candidates(&["1", "42"]);
+        described[1].description = Some("shush".to_string());
+        assert_eq!(menu(&described, 80), "1\r\n42  (shush)\r\n");
+        let files = candidates(&["src/main.rs", "src/bin/"]);
+        assert_eq!(menu(&files, 80), "main.rs  bin/\r\n");
+        let mut matched = candidates(&["src/main.rs"]);
+        matched[0].matched = vec![4, 7];
+        assert_eq!(
+            menu(&matched, 80),
+            "\x1b[1mm\x1b[22mai\x1b[1mn\x1b[22m.rs\r\n"
+        );
+    }
+
+    #[test]
+    fn gives_the_words_to_the_generator_in_variables() {
+        let command =
+            "printf 'main\\nmaster\\ndev\\n' | grep \"^$COMP_WORD\"; echo \"$COMP_PREVIOUS\"";
+        assert_eq!(
+            run_generator(command, "ma", "checkout", "git checkout ma"),
+            candidates(&["main", "master", "checkout"])
+        );
+        assert_eq!(
+            run_generator("echo \"$COMP_LINE\"", "", "", "git log "),
+            candidates(&["git log "])
+        );
+    }
+
+    #[test]
+    fn kills_a_slow_generator() {
+        let start = Instant::now();
+        assert_eq!(run_generator("echo early; sleep 10", "", "", "x "), []);
+        assert!(start.elapsed() < GENERATOR_TIMEOUT * 5);
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use serde::Deserialize;
+
+use crate::matcher::MatchMode;
+use crate::parser::{self, SyntaxTree};
+
+pub const DEFAULT_PROMPT: &str = "🤫> ";
+pub const DEFAULT_CONTINUATION_PROMPT: &str = "> ";
+
+/// Settings read from `config.toml` in the config directory. Every key is optional and falls
+/// back to its default.
+///
+/// ```toml
+/// prompt = "🤫> "
+/// continuation_prompt = "> "
+/// right_prompt = "\\t"
+/// matching = "fuzzy"
+/// command_not_found_handler = "pkgfile --search"
+///
+/// [history]
+/// path = "~/.shush_hist"
+/// size = 10000
+///
+/// [abbreviations]
+/// gco = "git checkout"
+///
+/// [theme]
+/// suggestion = "dim"
+/// unknown_command = "bold red"
+///
+/// [key_bindings.emacs]
+/// "\\C-t" = "transpose-chars"
+/// Meta-Rubout = "backward-kill-word"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Template for the prompt, see `prompt` for the escapes it accepts. `PS1` overrides it.
+    pub prompt: String,
+    /// Template for the prompt of the lines after the first one of a command. `PS2` overrides it.
+    pub continuation_prompt: String,
+    /// Template for the prompt shown on the right side of the line. `RPROMPT` overrides it.
+    pub right_prompt: Option<String>,
+    /// How completion and history search match what was typed: `prefix`, `substring` or `fuzzy`
+    pub matching: MatchMode,
+    /// Command run with the name and arguments of a command that is not found, see `not_found`
+    pub command_not_found_handler: Option<String>,
+    pub history: HistoryConfig,
+    /// Words expanded as they are typed at the start of a command, see `abbreviations`
+    pub abbreviations: BTreeMap<String, String>,
+    pub theme: Theme,
+    /// Bindings of each keymap, `emacs` or `vi-insert`, from keys to functions. The keys are
+    /// written like in `inputrc`, see `keymap`. The `inputrc` file is read after them.
+    pub key_bindings: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Styles of what the line editor shows besides the typed text
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    /// Suggestion from the history after the cursor
+    pub suggestion: Style,
+    /// Chars that matched a history search
+    pub highlight: Style,
+    /// Commands that are not builtins, aliases or programs in `PATH`
+    pub unknown_command: Style,
+}
+
+/// A text style written as words like `bold red`, or as the numbers of an SGR escape sequence
+/// like `38;5;245`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Style {
+    escape: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub path: PathBuf,
+    /// Maximum number of commands kept in the history file
+    pub size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            prompt: DEFAULT_PROMPT.to_string(),
+            continuation_prompt: DEFAULT_CONTINUATION_PROMPT.to_string(),
+            right_prompt: None,
+            matching: MatchMode::default(),
+            command_not_found_handler: None,
+            history: HistoryConfig::default(),
+            abbreviations: BTreeMap::new(),
+            theme: Theme::default(),
+            key_bindings: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            suggestion: Style::from_codes("2"),
+            highlight: Style::from_codes("1"),
+            unknown_command: Style::from_codes("31"),
+        }
+    }
+}
+
+const STYLE_NAMES: &[(&str, &str)] = &[
+    ("bold", "1"),
+    ("dim", "2"),
+    ("italic", "3"),
+    ("underline", "4"),
+    ("reverse", "7"),
+    ("black", "30"),
+    ("red", "31"),
+    ("green", "32"),
+    ("yellow", "33"),
+    ("blue", "34"),
+    ("magenta", "35"),
+    ("cyan", "36"),
+    ("white", "37"),
+];
+
+impl Style {
+    fn from_codes(codes: &str) -> Style {
+        Style {
+            escape: format!("\x1b[{}m", codes),
+        }
+    }
+
+    /// The escape sequence that starts the style
+    pub fn escape(&self) -> &str {
+        &self.escape
+    }
+}
+
+impl TryFrom<String> for Style {
+    type Error = anyhow::Error;
+
+    fn try_from(text: String) -> anyhow::Result<Style> {
+        let codes = text
+            .split_whitespace()
+            .map(|word| {
+                let is_code = word
+                    .chars()
+                    .all(|char| char.is_ascii_digit() || char == ';');
+                match STYLE_NAMES.iter().find(|(name, _)| *name == word) {
+                    Some((_, code)) => Ok(*code),
+                    None if is_code => Ok(word),
+                    None => Err(anyhow!("unknown style `{}`", word)),
+                }
+            })
+            .collect::<anyhow::Result<Vec<&str>>>()?;
+        Ok(Style::from_codes(&codes.join(";")))
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            path: home_dir().join(".shush_hist"),
+            size: 10000,
+        }
+    }
+}
+
+impl Config {
+    /// Loads `config.toml` from the config directory, or the defaults if it does not exist.
+    pub fn load() -> anyhow::Result<Config> {
+        let path = config_dir().join("config.toml");
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        Config::load_from(&path)
+    }
+
+    pub fn load_from(path: &Path) -> anyhow::Result<Config> {
+        let contents =
+            fs::read_to_string(path).map_err(|error| anyhow!("{}: {}", path.display(), error))?;
+        let mut config: Config =
+            toml::from_str(&contents).map_err(|error| anyhow!("{}: {}", path.display(), error))?;
+        config.history.path = expand_tilde(&config.history.path);
+        Ok(config)
+    }
+}
+
+/// Command line flags that change how the shell starts.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    /// `--norc`: do not source any startup file
+    pub norc: bool,
+    /// `--rcfile <path>`: source this file instead of the default `shushrc`
+    pub rcfile: Option<PathBuf>,
+    /// `--generate-completions`: write completions read from man pages to the cache and exit
+    pub generate_completions: bool,
+}
+
+impl Options {
+    pub fn parse(args: impl Iterator<Item = String>) -> anyhow::Result<Options> {
+        let mut options = Options::default();
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--norc" => options.norc = true,
+                "--generate-completions" => options.generate_completions = true,
+                "--rcfile" => match args.next() {
+                    Some(path) => options.rcfile = Some(PathBuf::from(path)),
+                    None => return Err(anyhow!("--rcfile requires a path")),
+                },
+                _ => return Err(anyhow!("unknown option `{}`", arg)),
+            }
+        }
+        Ok(options)
+    }
+
+    /// The startup file to source, if any.
+    pub fn rcfile(&self) -> Option<PathBuf> {
+        if self.norc {
+            return None;
+        }
+        match &self.rcfile {
+            Some(path) => Some(path.clone()),
+            None => Some(config_dir().join("shushrc")),
+        }
+    }
+}
+
+/// An error found while sourcing a startup file, with the line it happened in.
+#[derive(Debug)]
+pub struct RcError {
+    pub line: usize,
+    pub error: anyhow::Error,
+}
+
+/// Executes every command of a startup file in the current shell. A command takes the lines up to
+/// the one that completes it, like a group or an `if` spread over several lines. A command that
+/// fails to parse or execute does not stop the rest of the file from running, all errors are
+/// returned at the end with the line the command starts in.
+pub fn source(path: &Path) -> anyhow::Result<Vec<RcError>> {
+    let contents =
+        fs::read_to_string(path).map_err(|error| anyhow!("{}: {}", path.display(), error))?;
+    let mut errors = Vec::new();
+    let mut command = String::new();
+    let mut start = 0;
+    for (index, line) in contents.lines().enumerate() {
+        if command.is_empty() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            start = index + 1;
+            command.push_str(line);
+        } else {
+            command.push('\n');
+            command.push_str(line);
+        }
+        if parser::needs_more_input(&command) {
+            continue;
+        }
+        if let Err(error) = run_command(&std::mem::take(&mut command)) {
+            errors.push(RcError { line: start, error });
+        }
+    }
+    // a command still open at the end of the file is a syntax error
+    if !command.is_empty() {
+        if let Err(error) = run_command(&command) {
+            errors.push(RcError { line: start, error });
+        }
+    }
+    Ok(errors)
+}
+
+fn run_command(command: &str) -> anyhow::Result<()> {
+    match SyntaxTree::parse(command)? {
+        Some(tree) => match tree.execute() {
+            Ok(finished_cmd) if !finished_cmd.success() => {
+                Err(anyhow!("`{}` exited with an error", command))
+            }
+            Ok(_) => Ok(()),
+            Err(error) => Err(error),
+        },
+        None => Ok(()),
+    }
+}
+
+/// `$XDG_CONFIG_HOME/shush`, or `~/.config/shush` if it is not set.
+pub fn config_dir() -> PathBuf {
+    match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("shush"),
+        _ => home_dir().join(".config").join("shush"),
+    }
+}
+
+/// Directory for files that shush can generate again, like completions read from man pages
+pub fn cache_dir() -> PathBuf {
+    match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("shush"),
+        _ => home_dir().join(".cache").join("shush"),
+    }
+}
+
+/// Directory for data that shush keeps between sessions, like the directories visited with `cd`
+pub fn data_dir() -> PathBuf {
+    match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("shush"),
+        _ => home_dir().join(".local").join("share").join("shush"),
+    }
+}
+
+pub fn home_dir() -> PathBuf {
+    PathBuf::from(std::env::var_os("HOME").unwrap_or_default())
+}
+
+pub fn expand_tilde(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) => home_dir().join(rest),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{source, Config, Options};
+    use crate::aliases;
+    use crate::matcher::MatchMode;
+
+    #[test]
+    fn parses_options() {
+        let args = ["--norc", "--rcfile", "/tmp/rc"].map(String::from);
+        let options = Options::parse(args.into_iter()).unwrap();
+        assert!(options.norc);
+        assert_eq!(options.rcfile(), None);
+
+        assert!(Options::parse(["--rcfile".to_string()].into_iter()).is_err());
+        assert!(Options::parse(["--nope".to_string()].into_iter()).is_err());
+    }
+
+    #[test]
+    fn loads_partial_config() {
+        let path = std::env::temp_dir().join("shush_config_test.toml");
+        let contents = "prompt = '$ '\nmatching = 'fuzzy'\n[history]\nsize = 10\n\
+                        [abbreviations]\ngco = 'git checkout'\n";
+        std::fs::write(&path, contents).unwrap();
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.prompt, "$ ");
+        assert_eq!(config.matching, MatchMode::Fuzzy);
+        assert_eq!(config.history.size, 10);
+        assert_eq!(config.abbreviations["gco"], "git checkout");
+        assert!(config.history.path.ends_with(".shush_hist"));
+    }
+
+    #[test]
+    fn loads_theme_and_key_bindings() {
+        let path = std::env::temp_dir().join("shush_theme_config_test.toml");
+        let contents = "[theme]\nsuggestion = 'bold 38;5;245'\n\
+                        [key_bindings.emacs]\n'\\C-t' = 'transpose-chars'\n";
+        std::fs::write(&path, contents).unwrap();
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.theme.suggestion.escape(), "\x1b[1;38;5;245m");
+        assert_eq!(config.theme.highlight.escape(), "\x1b[1m");
+        assert_eq!(config.key_bindings["emacs"]["\\C-t"], "transpose-chars");
+
+        std::fs::write(&path, "[theme]\nhighlight = 'blinking'\n").unwrap();
+        let error = Config::load_from(&path).unwrap_err().to_string();
+        assert!(error.contains("unknown style `blinking`"), "{}", error);
+    }
+
+    #[test]
+    fn reports_config_error_line() {
+        let path = std::env::temp_dir().join("shush_bad_config_test.toml");
+        std::fs::write(&path, "prompt = '$ '\n\n[history]\nsize = 'ten'\n").unwrap();
+        let error = Config::load_from(&path).unwrap_err().to_string();
+        assert!(error.contains("line 4"), "{}", error);
+    }
+
+    #[test]
+    fn reports_rc_error_lines() {
+        let path = std::env::temp_dir().join("shush_rc_test");
+        std::fs::write(
+            &path,
+            "# comment\ntrue\n\n{ true\nfalse; }\nfalse\n{ true\n",
+        )
+        .unwrap();
+        let errors = source(&path).unwrap();
+        let lines: Vec<usize> = errors.iter().map(|error| error.line).collect();
+        assert_eq!(lines, vec![4, 6, 7]);
+    }
+
+    #[test]
+    fn runs_commands_spread_over_lines() {
+        let path = std::env::temp_dir().join("shush_rc_multiline_test");
+        let rc = "{\n  alias rc_group=a\n  alias rc_last=b\n}\nalias \\\n  rc_continued=c\n";
+        std::fs::write(&path, rc).unwrap();
+        let errors = source(&path).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(aliases::get("rc_group").as_deref(), Some("a"));
+        assert_eq!(aliases::get("rc_last").as_deref(), Some("b"));
+        assert_eq!(aliases::get("rc_continued").as_deref(), Some("c"));
+        aliases::clear();
+    }
+}
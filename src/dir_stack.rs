@@ -0,0 +1,125 @@
+//! The directory stack of `pushd`, `popd` and `dirs`.
+//!
+//! The current directory is the entry 0 of the stack and the directories saved by `pushd` follow
+//! it, the most recent first. `+N` counts entries from the left of the list printed by `dirs`,
+//! starting at 0, and `-N` from the right. `~N`, `~+N` and `~-N` in a word expand to the entries.
+
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+
+use crate::config::home_dir;
+
+thread_local! {
+    /// Saved directories, without the current one
+    static STACK: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The current directory followed by the saved ones
+pub fn entries() -> Vec<PathBuf> {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    STACK.with(|stack| {
+        std::iter::once(cwd)
+            .chain(stack.borrow().iter().cloned())
+            .collect()
+    })
+}
+
+/// Replaces the saved directories with the entries after the first one
+pub fn set_entries(entries: &[PathBuf]) {
+    STACK.with(|stack| *stack.borrow_mut() = entries.iter().skip(1).cloned().collect());
+}
+
+/// Saves a directory on top of the stack
+pub fn push(dir: PathBuf) {
+    STACK.with(|stack| stack.borrow_mut().insert(0, dir));
+}
+
+pub fn clear() {
+    STACK.with(|stack| stack.borrow_mut().clear());
+}
+
+/// Position in a stack of `len` entries of `+N` or `-N`, or `None` if `arg` is not one of them
+pub fn position(arg: &str, len: usize) -> Option<anyhow::Result<usize>> {
+    let (from_left, digits) = match arg.split_at_checked(1)? {
+        ("+", digits) => (true, digits),
+        ("-", digits) => (false, digits),
+        _ => return None,
+    };
+    let n: usize = digits.parse().ok()?;
+    if n >= len {
+        return Some(Err(anyhow!("{}: directory stack index out of range", arg)));
+    }
+    Some(Ok(if from_left { n } else { len - 1 - n }))
+}
+
+/// Expands what comes after `~` in a word: nothing is the home directory, `+` the current
+/// directory, `-` the previous one and `N`, `+N` or `-N` an entry of the stack
+pub fn expand_tilde(spec: &str) -> Option<PathBuf> {
+    match spec {
+        "" => return Some(home_dir()),
+        "+" => return std::env::var_os("PWD").map(PathBuf::from),
+        "-" => return std::env::var_os("OLDPWD").map(PathBuf::from),
+        _ => (),
+    }
+    let spec = if spec.starts_with(['+', '-']) {
+        spec.to_string()
+    } else {
+        format!("+{}", spec)
+    };
+    let saved = STACK.with(|stack| stack.borrow().clone());
+    let len = saved.len() + 1;
+    match position(&spec, len)?.ok()? {
+        0 => std::env::current_dir().ok(),
+        index => saved.get(index - 1).cloned(),
+    }
+}
+
+/// A directory as `dirs` shows it, with `~` for the home directory unless `long` is set
+pub fn display(dir: &Path, long: bool) -> String {
+    let home = home_dir();
+    match dir.strip_prefix(&home) {
+        Ok(rest) if !long && !home.as_os_str().is_empty() => {
+            if rest.as_os_str().is_empty() {
+                "~".to_string()
+            } else {
+                format!("~/{}", rest.display())
+            }
+        }
+        _ => dir.display().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{clear, expand_tilde, position, push};
+
+    #[test]
+    fn counts_positions_from_both_ends() {
+        assert_eq!(position("+0", 3).unwrap().unwrap(), 0);
+        assert_eq!(position("+2", 3).unwrap().unwrap(), 2);
+        assert_eq!(position("-0", 3).unwrap().unwrap(), 2);
+        assert_eq!(position("-2", 3).unwrap().unwrap(), 0);
+        assert!(position("+3", 3).unwrap().is_err());
+        assert!(position("dir", 3).is_none());
+        assert!(position("-", 3).is_none());
+        assert!(position("-L", 3).is_none());
+    }
+
+    #[test]
+    fn expands_tildes_to_entries() {
+        clear();
+        push(PathBuf::from("/b"));
+        push(PathBuf::from("/a"));
+        assert_eq!(expand_tilde("1"), Some(PathBuf::from("/a")));
+        assert_eq!(expand_tilde("+2"), Some(PathBuf::from("/b")));
+        assert_eq!(expand_tilde("-0"), Some(PathBuf::from("/b")));
+        assert_eq!(expand_tilde("-1"), Some(PathBuf::from("/a")));
+        assert_eq!(expand_tilde("3"), None);
+        assert_eq!(expand_tilde("user"), None);
+        clear();
+    }
+}
@@ -1,89 +1,1123 @@
+use std::cmp;
+use std::io;
+use std::ops::Range;
+
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
+use unicode_width::UnicodeWidthStr;
+
+use crate::config::{Theme, DEFAULT_CONTINUATION_PROMPT};
 use crate::gap_buffer::GapBuffer;
+use crate::history;
+use crate::lexer::{Lexer, Token, RESERVED_WORDS};
+use crate::path_cache;
+use crate::prompt::Prompt;
 use crate::read_input::IO;
+use crate::render::{Frame, Renderer};
 
+/// Line editor. The cursor is a byte offset into the text of the buffer that always sits at a
+/// grapheme cluster boundary, so that moving and deleting treat `é` or `🤫` as a single unit.
+///
+/// The buffer can hold several lines, each one after the first starts with the continuation
+/// prompt, and lines longer than the terminal wrap to the next row.
 #[derive(Debug)]
 pub struct Editor {
     buffer: GapBuffer,
     cursor: usize,
+    prompt: Prompt,
+    /// Prompt at the start of each line after the first, like `PS2`
+    continuation_prompt: Prompt,
+    rprompt: Option<Prompt>,
+    renderer: Renderer,
+    kill_ring: KillRing,
+    last_action: Option<EditAction>,
+    /// Range of the text inserted by the last yank, replaced by a yank pop
+    last_yank: Option<(usize, usize)>,
+    undo_list: UndoList,
+    /// Rest of a command from the history that starts like the line, shown after the cursor
+    /// while it is at the end of the line
+    suggestion: Option<String>,
+    /// Byte offsets of the chars that matched a history search, highlighted while searching
+    highlight: Option<Vec<usize>>,
+    /// Styles of the suggestion, the highlighted chars and the unknown commands
+    theme: Theme,
+}
+
+/// Editing commands that can be bound to keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditAction {
+    BackwardChar,
+    ForwardChar,
+    BackwardWord,
+    ForwardWord,
+    BeginningOfLine,
+    EndOfLine,
+    /// Moves to the line above in a buffer with several lines
+    PreviousLine,
+    NextLine,
+    DeleteBackwardChar,
+    DeleteForwardChar,
+    /// Kills from the cursor to the end of the line
+    KillLine,
+    /// Kills from the start of the line to the cursor
+    UnixLineDiscard,
+    /// Kills the whitespace separated word before the cursor
+    UnixWordRubout,
+    BackwardKillWord,
+    KillWord,
+    Yank,
+    /// Replaces the text just yanked with the previous entry of the kill ring
+    YankPop,
+    TransposeChars,
+    ClearScreen,
+    /// Reverts the last group of changes to the line
+    Undo,
+    /// Makes again the last change undone, until the line changes in another way
+    Redo,
+}
+
+/// Killed text, most recent last. Kills in a row are joined in a single entry.
+#[derive(Debug, Default)]
+struct KillRing {
+    entries: Vec<String>,
+    /// Entry that the next yank pop inserts, counting from the most recent one
+    yank_index: usize,
+}
+
+const KILL_RING_SIZE: usize = 16;
+
+impl KillRing {
+    fn push(&mut self, text: String) {
+        if self.entries.len() == KILL_RING_SIZE {
+            self.entries.remove(0);
+        }
+        self.entries.push(text);
+    }
+
+    /// Adds text to the last entry, at the front if it was killed backwards
+    fn extend(&mut self, text: &str, backwards: bool) {
+        match self.entries.last_mut() {
+            Some(last) if backwards => last.insert_str(0, text),
+            Some(last) => last.push_str(text),
+            None => self.push(text.to_string()),
+        }
+    }
+
+    fn yank(&mut self) -> Option<&str> {
+        self.yank_index = 0;
+        self.entries.last().map(String::as_str)
+    }
+
+    fn yank_pop(&mut self) -> Option<&str> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        self.yank_index = (self.yank_index + 1) % self.entries.len();
+        let index = self.entries.len() - 1 - self.yank_index;
+        Some(&self.entries[index])
+    }
+}
+
+/// A change to the text of the buffer at a byte offset
+#[derive(Debug, Clone)]
+enum Change {
+    Insert(usize, String),
+    Delete(usize, String),
+}
+
+/// Changes undone and redone together, and where the cursor was before them
+#[derive(Debug)]
+struct UndoGroup {
+    changes: Vec<Change>,
+    cursor: usize,
+}
+
+/// Kinds of edits that are undone together when they come in a row, like typed chars
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UndoKind {
+    Typing,
+    DeleteBackward,
+    DeleteForward,
+}
+
+/// Changes made to the line since it was started, in groups
+#[derive(Debug, Default)]
+struct UndoList {
+    groups: Vec<UndoGroup>,
+    /// Groups undone, most recent last, that can be redone until the line changes again
+    undone: Vec<UndoGroup>,
+    /// Kind of the edit that made the last group, while it can still grow
+    kind: Option<UndoKind>,
+    /// The next change starts a new group
+    split: bool,
+}
+
+impl UndoList {
+    /// Starts an edit, which goes to the last group if it is of the same kind
+    fn begin(&mut self, kind: Option<UndoKind>) {
+        self.split = kind.is_none() || kind != self.kind;
+        self.kind = kind;
+    }
+
+    fn record(&mut self, change: Change, cursor: usize) {
+        self.undone.clear();
+        match self.groups.last_mut() {
+            Some(group) if !self.split => group.changes.push(change),
+            _ => self.groups.push(UndoGroup {
+                changes: vec![change],
+                cursor,
+            }),
+        }
+        self.split = false;
+    }
 }
 
 impl Editor {
-    pub fn new() -> Self {
+    pub fn new(prompt: Prompt) -> Self {
         Self {
             buffer: GapBuffer::new(),
             cursor: 0,
+            prompt,
+            continuation_prompt: Prompt {
+                text: DEFAULT_CONTINUATION_PROMPT.to_string(),
+                width: DEFAULT_CONTINUATION_PROMPT.len(),
+            },
+            rprompt: None,
+            renderer: Renderer::default(),
+            kill_ring: KillRing::default(),
+            last_action: None,
+            last_yank: None,
+            undo_list: UndoList::default(),
+            suggestion: None,
+            highlight: None,
+            theme: Theme::default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Runs an editing command and updates the terminal
+    pub fn edit(&mut self, action: EditAction, io: &mut IO) -> io::Result<()> {
+        let mut output = String::new();
+        if action == EditAction::ClearScreen {
+            output.push_str("\x1b[H\x1b[2J");
+            self.renderer.reset();
+        }
+        // the suggestion only depends on the text, a motion keeps it
+        if self.apply(action) {
+            self.update_suggestion();
+        }
+        // the renderer only writes what changed, so after a motion it just moves the cursor
+        let frame = self.frame(io.columns());
+        output.push_str(&self.renderer.render(&frame));
+        io.write_to_stdout(output.as_bytes())
+    }
+
+    /// Changes the buffer or the cursor. Returns whether the text changed, so the suggestion has
+    /// to be looked up again.
+    fn apply(&mut self, action: EditAction) -> bool {
+        let text = self.get_buffer_text();
+        let last_action = self.last_action.replace(action);
+        match action {
+            EditAction::DeleteBackwardChar => self.begin_undo(Some(UndoKind::DeleteBackward)),
+            EditAction::DeleteForwardChar => self.begin_undo(Some(UndoKind::DeleteForward)),
+            // a yank pop is undone with the yank it replaces
+            EditAction::YankPop => (),
+            _ => self.begin_undo(None),
+        }
+        match action {
+            // at the end of the line, moving forward takes the suggestion
+            EditAction::ForwardChar | EditAction::EndOfLine if self.at_suggestion(&text) => {
+                self.accept_suggestion(false)
+            }
+            EditAction::ForwardWord if self.at_suggestion(&text) => self.accept_suggestion(true),
+            EditAction::BackwardChar => {
+                self.move_cursor_left();
+                false
+            }
+            EditAction::ForwardChar => {
+                self.move_cursor_right();
+                false
+            }
+            EditAction::BackwardWord => {
+                self.cursor = word_start_before(&text, self.cursor);
+                false
+            }
+            EditAction::ForwardWord => {
+                self.cursor = word_end_after(&text, self.cursor);
+                false
+            }
+            EditAction::BeginningOfLine => {
+                self.cursor = line_start(&self.buffer, self.cursor);
+                false
+            }
+            EditAction::EndOfLine => {
+                self.cursor = line_end(&self.buffer, self.cursor);
+                false
+            }
+            EditAction::PreviousLine => {
+                let start = line_start(&self.buffer, self.cursor);
+                if start > 0 {
+                    let column = text_width(&text[start..self.cursor]);
+                    let previous = line_start(&self.buffer, start - 1);
+                    self.cursor = offset_at_column(&self.buffer, previous, column);
+                }
+                false
+            }
+            EditAction::NextLine => {
+                let end = line_end(&self.buffer, self.cursor);
+                if end < text.len() {
+                    let start = line_start(&self.buffer, self.cursor);
+                    let column = text_width(&text[start..self.cursor]);
+                    self.cursor = offset_at_column(&self.buffer, end + 1, column);
+                }
+                false
+            }
+            EditAction::DeleteBackwardChar => self.delete_grapheme_backwards(),
+            EditAction::DeleteForwardChar => match next_grapheme_boundary(&text, self.cursor) {
+                Some(next) => {
+                    self.delete_range(self.cursor, next);
+                    true
+                }
+                None => false,
+            },
+            EditAction::KillLine => {
+                // at the end of a line the newline is killed, joining it with the next one
+                let end = match line_end(&self.buffer, self.cursor) {
+                    end if end == self.cursor && end < text.len() => end + 1,
+                    end => end,
+                };
+                self.kill(self.cursor, end, last_action)
+            }
+            EditAction::UnixLineDiscard => {
+                let start = line_start(&self.buffer, self.cursor);
+                self.kill(start, self.cursor, last_action)
+            }
+            EditAction::UnixWordRubout => {
+                let start = whitespace_word_start_before(&text, self.cursor);
+                self.kill(start, self.cursor, last_action)
+            }
+            EditAction::BackwardKillWord => {
+                let start = word_start_before(&text, self.cursor);
+                self.kill(start, self.cursor, last_action)
+            }
+            EditAction::KillWord => {
+                let end = word_end_after(&text, self.cursor);
+                self.kill(self.cursor, end, last_action)
+            }
+            EditAction::Yank => match self.kill_ring.yank() {
+                Some(killed) => {
+                    let killed = killed.to_string();
+                    let start = self.cursor;
+                    self.insert(&killed);
+                    self.last_yank = Some((start, self.cursor));
+                    true
+                }
+                None => false,
+            },
+            EditAction::YankPop => {
+                let yanked_last = matches!(last_action, Some(EditAction::Yank | EditAction::YankPop));
+                match (self.last_yank, yanked_last) {
+                    (Some((start, end)), true) => {
+                        let killed = self.kill_ring.yank_pop().unwrap_or_default().to_string();
+                        self.delete_range(start, end);
+                        self.insert(&killed);
+                        self.last_yank = Some((start, self.cursor));
+                        true
+                    }
+                    _ => {
+                        // yank pop only works right after a yank
+                        self.last_action = last_action;
+                        false
+                    }
+                }
+            }
+            EditAction::TransposeChars => self.transpose_chars(&text),
+            EditAction::ClearScreen => true,
+            EditAction::Undo => self.undo(),
+            EditAction::Redo => self.redo(),
         }
     }
 
-    pub fn move_cursor_left(&mut self) -> Option<&str> {
-        if self.cursor > 0 {
-            self.cursor -= 1;
-            Some("\x1B[D")
+    fn at_suggestion(&self, text: &str) -> bool {
+        self.cursor == text.len() && self.suggestion.is_some()
+    }
+
+    /// Inserts the suggestion, or its first word
+    fn accept_suggestion(&mut self, word: bool) -> bool {
+        let Some(suggestion) = self.suggestion.take() else {
+            return false;
+        };
+        let end = if word {
+            word_end_after(&suggestion, 0)
         } else {
+            suggestion.len()
+        };
+        self.insert(&suggestion[..end]);
+        true
+    }
+
+    /// Looks for a suggestion for the line in the history. It is only shown while the cursor is
+    /// at the end of the line.
+    fn update_suggestion(&mut self) {
+        let text = self.get_buffer_text();
+        // a search shows what it found, not what the history suggests
+        self.suggestion = if self.highlight.is_some() {
             None
+        } else if !text.trim().is_empty() {
+            history::suggest(&text)
+        } else {
+            None
+        };
+    }
+
+    /// Highlights the chars at the byte offsets, or stops highlighting them with `None`
+    pub fn set_highlight(&mut self, positions: Option<Vec<usize>>) {
+        self.highlight = positions;
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    /// Sets the prompts shown from the next time the line is written
+    pub fn set_prompt(
+        &mut self,
+        prompt: Prompt,
+        continuation_prompt: Prompt,
+        rprompt: Option<Prompt>,
+    ) {
+        self.prompt = prompt;
+        self.continuation_prompt = continuation_prompt;
+        self.rprompt = rprompt;
+    }
+
+    /// Moves the cursor one grapheme to the left, unless it is at the start
+    pub fn move_cursor_left(&mut self) -> bool {
+        let text = self.get_buffer_text();
+        match previous_grapheme_boundary(&text, self.cursor) {
+            Some(previous) => {
+                self.cursor = previous;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the cursor one grapheme to the right, unless it is at the end
+    pub fn move_cursor_right(&mut self) -> bool {
+        let text = self.get_buffer_text();
+        match next_grapheme_boundary(&text, self.cursor) {
+            Some(next) => {
+                self.cursor = next;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Moves the cursor to a byte offset, which has to be at a grapheme boundary
+    pub fn set_cursor(&mut self, cursor: usize) {
+        self.cursor = cursor;
+    }
+
+    /// Replaces the whole text of the buffer
+    pub fn set_text(&mut self, text: &str, cursor: usize) {
+        self.delete_range(0, self.buffer.text_len());
+        self.insert(text);
+        self.cursor = cursor;
+    }
+
+    /// Removes `text[start..end]` from the buffer and leaves the cursor at `start`
+    pub fn delete_range(&mut self, start: usize, end: usize) -> String {
+        let deleted = self.buffer.slice(start..end).into_owned();
+        if !deleted.is_empty() {
+            let change = Change::Delete(start, deleted.clone());
+            self.undo_list.record(change, self.cursor);
+        }
+        self.buffer.replace_range(start..end, "");
+        self.cursor = start;
+        deleted
+    }
+
+    /// Starts an edit. Edits of the same kind in a row are undone together, and other edits are
+    /// undone one by one.
+    pub fn begin_undo(&mut self, kind: Option<UndoKind>) {
+        self.undo_list.begin(kind);
+    }
+
+    /// Ends the group of the current edit, so the next change starts a new group even if it
+    /// comes without `begin_undo`, like the text typed in vi insert mode after a command
+    pub fn end_undo(&mut self) {
+        self.undo_list.kind = None;
+        self.undo_list.split = true;
+    }
+
+    /// Reverts the last group of changes and puts the cursor back where it was before them.
+    /// Returns whether there was anything to undo.
+    pub fn undo(&mut self) -> bool {
+        let Some(group) = self.undo_list.groups.pop() else {
+            return false;
+        };
+        for change in group.changes.iter().rev() {
+            match change {
+                Change::Insert(offset, text) => {
+                    self.buffer.move_gap_to_cursor(*offset);
+                    self.buffer.delete_forwards(text.chars().count());
+                }
+                Change::Delete(offset, text) => {
+                    self.buffer.move_gap_to_cursor(*offset);
+                    self.buffer.insert(text);
+                }
+            }
+        }
+        self.cursor = group.cursor;
+        self.undo_list.undone.push(group);
+        true
+    }
+
+    /// Makes the changes of the last undone group again and leaves the cursor after them
+    pub fn redo(&mut self) -> bool {
+        let Some(group) = self.undo_list.undone.pop() else {
+            return false;
+        };
+        for change in &group.changes {
+            match change {
+                Change::Insert(offset, text) => {
+                    self.buffer.move_gap_to_cursor(*offset);
+                    self.buffer.insert(text);
+                    self.cursor = offset + text.len();
+                }
+                Change::Delete(offset, text) => {
+                    self.buffer.move_gap_to_cursor(*offset);
+                    self.buffer.delete_forwards(text.chars().count());
+                    self.cursor = *offset;
+                }
+            }
         }
+        self.undo_list.groups.push(group);
+        true
     }
 
-    pub fn move_cursor_right(&mut self) -> Option<&str> {
-        if self.cursor < self.buffer.text_len() {
-            self.cursor += 1;
-            Some("\x1B[C")
+    /// Deletes the text between `start` and `end` and saves it in the kill ring. Kills right after
+    /// another kill are appended to the same entry. Returns whether any text was killed.
+    fn kill(&mut self, start: usize, end: usize, last_action: Option<EditAction>) -> bool {
+        if start == end {
+            return false;
+        }
+        let backwards = end == self.cursor;
+        let killed = self.delete_range(start, end);
+        let killed_last = matches!(
+            last_action,
+            Some(
+                EditAction::KillLine
+                    | EditAction::UnixLineDiscard
+                    | EditAction::UnixWordRubout
+                    | EditAction::BackwardKillWord
+                    | EditAction::KillWord
+            )
+        );
+        if killed_last {
+            self.kill_ring.extend(&killed, backwards);
         } else {
-            None
+            self.kill_ring.push(killed);
         }
+        true
+    }
+
+    /// Swaps the grapheme before the cursor with the one under it and moves the cursor forward.
+    /// At the end of the line the two graphemes before the cursor are swapped.
+    fn transpose_chars(&mut self, text: &str) -> bool {
+        let end = next_grapheme_boundary(text, self.cursor).unwrap_or(self.cursor);
+        let Some(middle) = previous_grapheme_boundary(text, end) else {
+            return false;
+        };
+        let Some(start) = previous_grapheme_boundary(text, middle) else {
+            return false;
+        };
+        let swapped = format!("{}{}", &text[middle..end], &text[start..middle]);
+        self.delete_range(start, end);
+        self.insert(&swapped);
+        true
     }
 
-    pub fn move_cursor_to_start(&mut self) -> &str {
+    /// Returns whether there was anything to delete
+    pub fn delete_grapheme_backwards(&mut self) -> bool {
+        let text = self.get_buffer_text();
+        let Some(previous) = previous_grapheme_boundary(&text, self.cursor) else {
+            return false;
+        };
+        self.delete_range(previous, self.cursor);
+        true
+    }
+
+    pub fn new_line(&mut self, io: &mut IO) -> io::Result<()> {
         self.cursor = 0;
-        "\x1B[5G"
+        io.write_to_stdout(b"\n")?;
+        self.renderer.reset();
+        self.write_line(io)
     }
 
-    pub fn delete_backwards(&mut self, io: &mut IO) {
-        if self.cursor == 0 {
-            return
-        }
-        self.buffer.move_gap_to_cursor(self.cursor);
-        self.buffer.delete_backwards(1);
-        self.cursor -= 1;
-        self.clear_line(io);
-        self.write_line(io);
+    /// Moves the terminal cursor below the last row of the buffer, so the output of a command
+    /// does not overwrite it
+    pub fn finish_line(&mut self, io: &mut IO) -> io::Result<()> {
+        let output = self.finished(io.columns());
+        io.write_to_stdout(output.as_bytes())
+    }
+
+    /// Draws the line as it is run and leaves it. The suggestion is not part of the command, so
+    /// it is erased, and the line can have changed without being drawn, like when an
+    /// abbreviation is expanded on Enter.
+    fn finished(&mut self, columns: usize) -> String {
+        self.suggestion = None;
+        let mut output = self.renderer.render(&self.frame(columns));
+        output.push_str(&self.renderer.finish());
+        output
     }
 
-    pub fn new_line(&mut self, io: &mut IO) {
-        io.write_to_stdout("\n🤫> ".as_bytes());
-        io.write_to_stdout(self.move_cursor_to_start().as_bytes());
+    /// Writes text below the line, like a list of completions, and the line again after it
+    pub fn print_below(&mut self, text: &str, io: &mut IO) -> io::Result<()> {
+        self.suggestion = None;
+        let mut output = self.renderer.render(&self.frame(io.columns()));
+        output.push_str(&self.renderer.finish());
+        output.push_str(text);
+        io.write_to_stdout(output.as_bytes())?;
+        self.write_line(io)
     }
 
     pub fn clean_buffer(&mut self) {
         self.buffer.clear_buffer_text();
+        self.suggestion = None;
+        self.last_action = None;
+        self.undo_list = UndoList::default();
     }
 
-    fn write_line(&mut self, io: &mut IO) {
-        io.write_to_stdout("🤫> ".as_bytes());
-        io.write_to_stdout(self.get_buffer_text().expect("buffer text wrong").as_bytes());
-        io.write_to_stdout(format!("\x1b[{}G", self.cursor + 5).as_bytes()); // move cursor
-        io.write_to_stdout(format!("\x1b[?25h").as_bytes()); // show cursor
-        // io.write_to_stderr(format!("{:?}\n", self.buffer).as_bytes());
-        // io.write_to_stderr(format!("{:?}", self.cursor).as_bytes());
-    } 
+    /// Writes the prompt and the buffer, and the right prompt if the buffer fits in one row with
+    /// room for it. Only what changed since the last time is written.
+    pub fn write_line(&mut self, io: &mut IO) -> io::Result<()> {
+        self.update_suggestion();
+        let frame = self.frame(io.columns());
+        let output = self.renderer.render(&frame);
+        io.write_to_stdout(output.as_bytes())
+    }
+
+    /// Writes the line again after the terminal changed size
+    pub fn resize(&mut self, io: &mut IO) -> io::Result<()> {
+        let output = self.resized(io.columns());
+        io.write_to_stdout(output.as_bytes())
+    }
 
-    fn clear_line(&self, io: &mut IO) {
-        io.write_to_stdout(format!("\x1b[?25l").as_bytes()); // hide cursor
-        io.write_to_stdout("\x1B[2K\r".as_bytes());
+    /// Erases the line and draws it again for a terminal `columns` wide
+    fn resized(&mut self, columns: usize) -> String {
+        // most terminals rewrap the rows to the new width, so the cursor is where it would be
+        // drawn with the new width
+        let frame = self.frame(columns);
+        let mut output = self.renderer.clear(frame.cursor().row);
+        output.push_str(&self.renderer.render(&frame));
+        output
     }
 
-    /// In this function we have to re-render the line. Send ANSI code for clear line, then
-    /// show the text stored in the buffer. The cursor stays in place.
-    pub fn write_to_buffer(&mut self, slice: &[u8], io: &mut IO) {
-        self.clear_line(io);
+    /// What the prompts and the buffer look like on a terminal with `columns` columns. Each line
+    /// after the first starts with the continuation prompt.
+    fn frame(&self, columns: usize) -> Frame {
+        let text = self.get_buffer_text();
+        let unknown = unknown_commands(&text);
+        let mut frame = Frame::new(columns);
+        frame.push_prompt(&self.prompt.text);
+        for (index, grapheme) in text.grapheme_indices(true) {
+            if index == self.cursor {
+                frame.set_cursor();
+            }
+            if grapheme == "\n" {
+                frame.push_prompt("\n");
+                frame.push_prompt(&self.continuation_prompt.text);
+            } else if self.highlighted(index, grapheme) {
+                frame.push_styled(grapheme, self.theme.highlight.escape());
+            } else if unknown.iter().any(|range| range.contains(&index)) {
+                frame.push_styled(grapheme, self.theme.unknown_command.escape());
+            } else {
+                frame.push_text(grapheme);
+            }
+        }
+        if self.cursor == text.len() {
+            frame.set_cursor();
+            if let Some(suggestion) = &self.suggestion {
+                frame.push_styled(suggestion, self.theme.suggestion.escape());
+            }
+        }
+        if let Some(rprompt) = &self.rprompt {
+            frame.push_right_prompt(&rprompt.text, rprompt.width);
+        }
+        frame
+    }
+
+    /// Whether any char of the grapheme at `index` is highlighted
+    fn highlighted(&self, index: usize, grapheme: &str) -> bool {
+        self.highlight.as_ref().is_some_and(|positions| {
+            positions
+                .iter()
+                .any(|position| (index..index + grapheme.len()).contains(position))
+        })
+    }
+
+    /// Inserts typed text at the cursor, undone together with the text typed right before it,
+    /// and writes the line again
+    pub fn write_to_buffer(&mut self, text: &str, io: &mut IO) -> io::Result<()> {
+        self.last_action = None;
+        self.begin_undo(Some(UndoKind::Typing));
+        self.insert(text);
+        self.write_line(io)
+    }
+
+    /// Inserts pasted text as is, without running the actions bound to the keys in it, so a
+    /// newline in the middle of a paste does not execute the line
+    pub fn paste(&mut self, text: &str, io: &mut IO) -> io::Result<()> {
+        self.last_action = None;
+        self.begin_undo(None);
+        self.insert(&normalize_newlines(text));
+        self.write_line(io)
+    }
+
+    /// Inserts text at the cursor and moves the cursor after it
+    pub fn insert(&mut self, text: &str) {
+        if !text.is_empty() {
+            let change = Change::Insert(self.cursor, text.to_string());
+            self.undo_list.record(change, self.cursor);
+        }
         self.buffer.move_gap_to_cursor(self.cursor);
-        self.buffer.insert(slice);
-        self.cursor += slice.len();
-        self.write_line(io);
+        self.buffer.insert(text);
+        self.cursor += text.len();
+    }
+
+    pub fn get_buffer_text(&self) -> String {
+        self.buffer.get_text()
+    }
+}
+
+/// Words in command position that are not a builtin, a program in `PATH` or a reserved word
+fn unknown_commands(text: &str) -> Vec<Range<usize>> {
+    let mut unknown = Vec::new();
+    let mut command_start = true;
+    for token in Lexer::tokenize(text) {
+        if let (true, Token::Item, Some(word)) = (command_start, token.token(), token.content()) {
+            if !RESERVED_WORDS.contains(&word) && !path_cache::is_command(word) {
+                unknown.push(token.span());
+            }
+        }
+        command_start = token.starts_command(command_start);
+    }
+    unknown
+}
+
+/// Start of the line of the buffer that `offset` is in
+fn line_start(buffer: &GapBuffer, offset: usize) -> usize {
+    buffer.rfind("\n", offset).map_or(0, |index| index + 1)
+}
+
+/// End of the line of the buffer that `offset` is in, before its newline
+fn line_end(buffer: &GapBuffer, offset: usize) -> usize {
+    buffer.find("\n", offset).unwrap_or(buffer.text_len())
+}
+
+/// Offset of the grapheme that is `column` columns into the line starting at `start`, or the end
+/// of the line if it is shorter
+fn offset_at_column(buffer: &GapBuffer, start: usize, column: usize) -> usize {
+    let end = line_end(buffer, start);
+    let mut width = 0;
+    for (index, grapheme) in buffer.slice(start..end).grapheme_indices(true) {
+        width += cmp::min(grapheme.width(), 2);
+        if width > column {
+            return start + index;
+        }
+    }
+    end
+}
+
+/// Terminals send Enter as `\r` in pasted text
+pub fn normalize_newlines(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// Columns taken by `text` on the terminal. Each grapheme cluster takes at most two columns, as
+/// terminals draw emoji sequences joined with ZWJ as a single wide char.
+pub fn text_width(text: &str) -> usize {
+    text.graphemes(true)
+        .map(|grapheme| cmp::min(grapheme.width(), 2))
+        .sum()
+}
+
+fn is_word_grapheme(grapheme: &str) -> bool {
+    grapheme.chars().next().is_some_and(char::is_alphanumeric)
+}
+
+/// Start of the word before `offset`, skipping any non word chars right before it
+fn word_start_before(text: &str, offset: usize) -> usize {
+    let mut graphemes = text[..offset].grapheme_indices(true).rev().peekable();
+    while graphemes.next_if(|(_, grapheme)| !is_word_grapheme(grapheme)).is_some() {}
+    let mut start = offset;
+    while let Some((index, _)) = graphemes.next_if(|(_, grapheme)| is_word_grapheme(grapheme)) {
+        start = index;
+    }
+    if start == offset {
+        0
+    } else {
+        start
+    }
+}
+
+/// End of the word after `offset`, skipping any non word chars right after it
+fn word_end_after(text: &str, offset: usize) -> usize {
+    let mut graphemes = text[offset..].grapheme_indices(true).peekable();
+    while graphemes.next_if(|(_, grapheme)| !is_word_grapheme(grapheme)).is_some() {}
+    let mut end = text.len();
+    for (index, grapheme) in graphemes {
+        if !is_word_grapheme(grapheme) {
+            end = offset + index;
+            break;
+        }
+    }
+    end
+}
+
+/// Start of the whitespace separated word before `offset`, like `Ctrl-W` in a terminal
+fn whitespace_word_start_before(text: &str, offset: usize) -> usize {
+    let before = text[..offset].trim_end();
+    match before.rfind(char::is_whitespace) {
+        Some(index) => index + before[index..].chars().next().map_or(1, char::len_utf8),
+        None => 0,
+    }
+}
+
+fn previous_grapheme_boundary(text: &str, offset: usize) -> Option<usize> {
+    GraphemeCursor::new(offset, text.len(), true)
+        .prev_boundary(text, 0)
+        .ok()
+        .flatten()
+}
+
+fn next_grapheme_boundary(text: &str, offset: usize) -> Option<usize> {
+    GraphemeCursor::new(offset, text.len(), true)
+        .next_boundary(text, 0)
+        .ok()
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_newlines, text_width, EditAction, Editor, UndoKind};
+    use crate::prompt::{Prompt, PromptContext};
+    use crate::render::Position;
+
+    fn editor() -> Editor {
+        Editor::new(Prompt::new("> ", &PromptContext::default()))
+    }
+
+    #[test]
+    fn deletes_grapheme_clusters() {
+        let mut editor = editor();
+        editor.insert("e\u{301}🤫a👨‍👩‍👧");
+        assert!(editor.delete_grapheme_backwards());
+        assert_eq!(editor.get_buffer_text(), "e\u{301}🤫a");
+        editor.move_cursor_left();
+        assert!(editor.delete_grapheme_backwards());
+        assert!(editor.delete_grapheme_backwards());
+        assert_eq!(editor.get_buffer_text(), "a");
+        assert!(!editor.delete_grapheme_backwards());
+    }
+
+    #[test]
+    fn moves_over_wide_chars() {
+        let mut editor = editor();
+        editor.insert("日本");
+        let column = |editor: &Editor| editor.frame(80).cursor().column;
+        assert!(editor.move_cursor_left());
+        assert_eq!(column(&editor), 4);
+        assert!(editor.move_cursor_left());
+        assert_eq!(column(&editor), 2);
+        assert!(!editor.move_cursor_left());
+        assert!(editor.move_cursor_right());
+        assert_eq!(column(&editor), 4);
+    }
+
+    #[test]
+    fn kills_and_yanks() {
+        let mut editor = editor();
+        editor.insert("git commit --amend");
+        editor.apply(EditAction::BackwardKillWord);
+        editor.apply(EditAction::BackwardKillWord);
+        assert_eq!(editor.get_buffer_text(), "git ");
+        // kills in a row go to the same entry
+        editor.apply(EditAction::Yank);
+        assert_eq!(editor.get_buffer_text(), "git commit --amend");
+
+        editor.apply(EditAction::BeginningOfLine);
+        editor.apply(EditAction::KillWord);
+        assert_eq!(editor.get_buffer_text(), " commit --amend");
+        editor.apply(EditAction::EndOfLine);
+        editor.apply(EditAction::Yank);
+        assert_eq!(editor.get_buffer_text(), " commit --amendgit");
+        editor.apply(EditAction::YankPop);
+        assert_eq!(editor.get_buffer_text(), " commit --amendcommit --amend");
+    }
+
+    #[test]
+    fn kills_whitespace_words_and_lines() {
+        let mut editor = editor();
+        editor.insert("ls ../some-dir/ ");
+        editor.apply(EditAction::UnixWordRubout);
+        assert_eq!(editor.get_buffer_text(), "ls ");
+        editor.apply(EditAction::BackwardChar);
+        editor.apply(EditAction::KillLine);
+        assert_eq!(editor.get_buffer_text(), "ls");
+        editor.apply(EditAction::UnixLineDiscard);
+        assert!(editor.is_empty());
+    }
+
+    #[test]
+    fn moves_by_words() {
+        let mut editor = editor();
+        editor.insert("cd ~/dev/shush");
+        editor.apply(EditAction::BackwardWord);
+        assert_eq!(editor.cursor, 9);
+        editor.apply(EditAction::BackwardWord);
+        editor.apply(EditAction::BackwardWord);
+        assert_eq!(editor.cursor, 0);
+        editor.apply(EditAction::ForwardWord);
+        assert_eq!(editor.cursor, 2);
+        editor.apply(EditAction::ForwardWord);
+        assert_eq!(editor.cursor, 8);
+    }
+
+    #[test]
+    fn transposes_chars() {
+        let mut editor = editor();
+        editor.insert("sl");
+        editor.apply(EditAction::TransposeChars);
+        assert_eq!(editor.get_buffer_text(), "ls");
+        editor.apply(EditAction::BeginningOfLine);
+        editor.apply(EditAction::ForwardChar);
+        editor.apply(EditAction::TransposeChars);
+        assert_eq!(editor.get_buffer_text(), "sl");
+        assert_eq!(editor.cursor, 2);
+    }
+
+    #[test]
+    fn deletes_forwards() {
+        let mut editor = editor();
+        editor.insert("a🤫b");
+        editor.apply(EditAction::BeginningOfLine);
+        editor.apply(EditAction::ForwardChar);
+        editor.apply(EditAction::DeleteForwardChar);
+        assert_eq!(editor.get_buffer_text(), "ab");
+    }
+
+    #[test]
+    fn normalizes_pasted_newlines() {
+        assert_eq!(normalize_newlines("a\r\nb\rc\n"), "a\nb\nc\n");
+    }
+
+    #[test]
+    fn computes_display_width() {
+        assert_eq!(text_width("abc"), 3);
+        assert_eq!(text_width("日本"), 4);
+        assert_eq!(text_width("e\u{301}"), 1);
+        assert_eq!(text_width("👨‍👩‍👧"), 2);
+    }
+
+    #[test]
+    fn moves_between_lines() {
+        let mut editor = editor();
+        editor.insert("for f in *\ndo\n  echo $f");
+        editor.apply(EditAction::PreviousLine);
+        assert_eq!(editor.cursor, 13);
+        editor.apply(EditAction::BeginningOfLine);
+        assert_eq!(editor.cursor, 11);
+        editor.apply(EditAction::PreviousLine);
+        editor.apply(EditAction::EndOfLine);
+        assert_eq!(editor.cursor, 10);
+        editor.apply(EditAction::PreviousLine);
+        assert_eq!(editor.cursor, 10);
+        editor.apply(EditAction::NextLine);
+        editor.apply(EditAction::NextLine);
+        assert_eq!(editor.cursor, 16);
+        editor.apply(EditAction::KillLine);
+        assert_eq!(editor.get_buffer_text(), "for f in *\ndo\n  ");
+        editor.apply(EditAction::UnixLineDiscard);
+        assert_eq!(editor.get_buffer_text(), "for f in *\ndo\n");
+    }
+
+    #[test]
+    fn kills_newline_at_end_of_line() {
+        let mut editor = editor();
+        editor.insert("a\nb");
+        editor.set_cursor(1);
+        editor.apply(EditAction::KillLine);
+        assert_eq!(editor.get_buffer_text(), "ab");
+    }
+
+    #[test]
+    fn places_cursor_in_lines() {
+        let mut editor = editor();
+        editor.insert("a\nb");
+        // lines after the first start with the continuation prompt
+        assert_eq!(editor.frame(80).cursor(), Position { row: 1, column: 3 });
+        editor.set_cursor(1);
+        assert_eq!(editor.frame(80).cursor(), Position { row: 0, column: 3 });
+        // long lines wrap
+        editor.set_text("abcdef", 3);
+        assert_eq!(editor.frame(5).cursor(), Position { row: 1, column: 0 });
+    }
+
+    #[test]
+    fn places_cursor_after_prompts_with_several_lines() {
+        let mut editor = editor();
+        let context = PromptContext::default();
+        editor.set_prompt(
+            Prompt::new("\\w\\n$ ", &context),
+            Prompt::new("... ", &context),
+            None,
+        );
+        editor.insert("ls\nx");
+        assert_eq!(editor.frame(80).cursor(), Position { row: 2, column: 5 });
+    }
+
+    fn type_text(editor: &mut Editor, text: &str) {
+        editor.last_action = None;
+        editor.begin_undo(Some(UndoKind::Typing));
+        editor.insert(text);
+    }
+
+    #[test]
+    fn undoes_groups_of_edits() {
+        let mut editor = editor();
+        for char in ["l", "s", " ", "-"] {
+            type_text(&mut editor, char);
+        }
+        editor.apply(EditAction::DeleteBackwardChar);
+        editor.apply(EditAction::DeleteBackwardChar);
+        editor.apply(EditAction::BackwardWord);
+        editor.apply(EditAction::KillWord);
+        assert!(editor.is_empty());
+
+        editor.apply(EditAction::Undo);
+        assert_eq!(editor.get_buffer_text(), "ls");
+        assert_eq!(editor.cursor, 0);
+        editor.apply(EditAction::Undo);
+        assert_eq!(editor.get_buffer_text(), "ls -");
+        assert_eq!(editor.cursor, 4);
+        editor.apply(EditAction::Undo);
+        assert!(editor.is_empty());
+        assert!(!editor.apply(EditAction::Undo));
+
+        editor.apply(EditAction::Redo);
+        editor.apply(EditAction::Redo);
+        assert_eq!(editor.get_buffer_text(), "ls");
+        assert_eq!(editor.cursor, 2);
+        // a new change drops the changes left to redo
+        type_text(&mut editor, "!");
+        assert!(!editor.apply(EditAction::Redo));
+    }
+
+    #[test]
+    fn undoes_yank_pop_with_its_yank() {
+        let mut editor = editor();
+        type_text(&mut editor, "a b");
+        editor.apply(EditAction::UnixWordRubout);
+        editor.apply(EditAction::UnixWordRubout);
+        type_text(&mut editor, "c ");
+        editor.apply(EditAction::UnixWordRubout);
+        editor.apply(EditAction::Yank);
+        editor.apply(EditAction::YankPop);
+        assert_eq!(editor.get_buffer_text(), "a b");
+        editor.apply(EditAction::Undo);
+        assert!(editor.is_empty());
+    }
+
+    #[test]
+    fn forgets_changes_of_previous_lines() {
+        let mut editor = editor();
+        type_text(&mut editor, "ls");
+        editor.clean_buffer();
+        editor.cursor = 0;
+        assert!(!editor.apply(EditAction::Undo));
+    }
+
+    #[test]
+    fn accepts_suggestions_at_the_end_of_the_line() {
+        let mut editor = editor();
+        type_text(&mut editor, "git");
+        editor.suggestion = Some(" commit -m fix".to_string());
+        editor.apply(EditAction::ForwardWord);
+        assert_eq!(editor.get_buffer_text(), "git commit");
+        editor.suggestion = Some(" -m fix".to_string());
+        editor.apply(EditAction::BackwardChar);
+        editor.apply(EditAction::EndOfLine);
+        assert_eq!(editor.get_buffer_text(), "git commit");
+        editor.apply(EditAction::EndOfLine);
+        assert_eq!(editor.get_buffer_text(), "git commit -m fix");
+        editor.apply(EditAction::Undo);
+        assert_eq!(editor.get_buffer_text(), "git commit");
+    }
+
+    #[test]
+    fn shows_suggestions_dim_after_the_cursor() {
+        let mut editor = editor();
+        type_text(&mut editor, "ls");
+        editor.suggestion = Some(" -l".to_string());
+        let frame = editor.frame(80);
+        assert_eq!(frame.cursor(), Position { row: 0, column: 4 });
+        let output = crate::render::Renderer::default().render(&frame);
+        assert!(output.contains("ls\x1b[2m -l"));
+    }
+
+    #[test]
+    fn shows_what_a_search_matched_in_bold() {
+        let mut editor = editor();
+        type_text(&mut editor, "git checkout");
+        editor.suggestion = Some(" main".to_string());
+        editor.set_highlight(Some(vec![4, 5]));
+        editor.update_suggestion();
+        assert_eq!(editor.suggestion, None);
+        let output = crate::render::Renderer::default().render(&editor.frame(80));
+        assert!(output.contains("git \x1b[1mch\x1b[0meckout"));
+    }
+
+    #[test]
+    fn shows_unknown_commands_in_red() {
+        let mut editor = editor();
+        type_text(&mut editor, "cd /; shush-no-such-program -x | if true");
+        let output = crate::render::Renderer::default().render(&editor.frame(80));
+        assert!(output.contains("cd /; \x1b[31mshush-no-such-program\x1b[0m -x | if"));
+    }
+
+    #[test]
+    fn draws_the_line_again_after_a_resize() {
+        let mut editor = editor();
+        type_text(&mut editor, "echo abcdefghij");
+        editor.renderer.render(&editor.frame(80));
+        // at 6 columns `> echo abcdefghij` takes 3 rows and the cursor is on the last one
+        let output = editor.resized(6);
+        assert!(output.starts_with("\x1b[2A\r\x1b[J"), "{:?}", output);
+        assert!(output.contains("> echo\r\n abcde\r\nfghij"), "{:?}", output);
+        let output = editor.resized(80);
+        assert!(output.starts_with("\r\x1b[J\x1b[?25l> echo abcdefghij\x1b[?25h"));
     }
 
-    pub fn get_buffer_text(&self) -> Result<String, std::string::FromUtf8Error> {
-        let string = self.buffer.get_text()?;
-        Ok(string)
+    #[test]
+    fn draws_a_line_changed_on_enter_before_leaving_it() {
+        let mut editor = editor();
+        type_text(&mut editor, "gco");
+        editor.renderer.render(&editor.frame(80));
+        // what expanding an abbreviation does
+        editor.delete_range(0, 3);
+        editor.insert("git checkout");
+        let output = editor.finished(80);
+        assert!(output.contains("git checkout"), "{:?}", output);
+        assert!(output.ends_with("\r\n"), "{:?}", output);
     }
 }
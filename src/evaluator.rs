@@ -1,56 +1,227 @@
 use crate::builtin::BuiltInCommands;
+use crate::dir_stack;
+use crate::history;
+use crate::not_found;
+use crate::parser::{Redirect, SyntaxTree, Word};
+use crate::path_cache;
 use crate::timestamps;
 
 use std::fmt;
 use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::Write;
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
-/// A `FinishedShushCmd` has a lifetime that begins when a `ShushCmd` finishes execution and ends
-/// when it is serialized to the history file.
-pub struct FinishedShushCmd<'a> {
-    shush_cmd: &'a ShushCmd,
-    exit_code: bool,
+use anyhow::anyhow;
+
+/// Finish status of a `SyntaxTree`, kept until its line is saved to the history. The line is
+/// saved as it was typed rather than rebuilt from the tree.
+pub struct FinishedShushCmd {
+    exit_code: i32,
     timestamp: u128,
+    /// Directory the command started in, unless it was removed
+    cwd: Option<PathBuf>,
 }
 
-impl FinishedShushCmd<'_> {
-    pub fn new<'a>(cmd: &'a ShushCmd, exit_code: bool, timestamp: u128) -> FinishedShushCmd<'a> {
+impl FinishedShushCmd {
+    pub fn new(exit_code: i32, timestamp: u128, cwd: Option<PathBuf>) -> FinishedShushCmd {
         FinishedShushCmd {
-            shush_cmd: cmd,
             exit_code,
             timestamp,
+            cwd,
         }
     }
 
-    pub fn append_to_histfile(self) -> io::Result<()> {
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code
+    }
+
+    pub fn success(&self) -> bool {
+        self.exit_code == 0
+    }
+
+    /// History entry of `line`, the text that was parsed into the finished command
+    pub fn history_entry(&self, line: &str) -> history::Entry {
+        history::Entry {
+            command: line.to_string(),
+            exit_code: self.exit_code,
+            cwd: self.cwd.clone(),
+        }
+    }
+
+    pub fn append_to_histfile(self, line: &str, histfile: &Path) -> io::Result<()> {
         let mut file = OpenOptions::new()
             .append(true)
-            .open("/Users/victor/.shush_hist")?; // TODO: load from config and set default
+            .create(true)
+            .open(histfile)?;
 
-        writeln!(file, "{}", self)?;
+        writeln!(file, "{}", self.history_entry(line).to_line(self.timestamp))?;
         Ok(())
     }
 }
 
-impl fmt::Display for FinishedShushCmd<'_> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{};{};{}",
-            &self.timestamp.to_string(),
-            &self.shush_cmd.to_string(),
-            &self.exit_code.to_string(),
-        )
+impl SyntaxTree {
+    pub fn execute(&self) -> anyhow::Result<FinishedShushCmd> {
+        let cwd = std::env::current_dir().ok();
+        let exit_code = self.evaluate()?;
+        Ok(FinishedShushCmd::new(exit_code, timestamps::get(), cwd))
+    }
+
+    /// Runs the tree in the current shell and returns its exit status.
+    fn evaluate(&self) -> anyhow::Result<i32> {
+        match self {
+            SyntaxTree::Command(cmd) => Ok(cmd.execute_command().unwrap_or_else(failed)),
+            SyntaxTree::Subshell(body, redirects) => {
+                let _redirections = match Redirection::apply(redirects) {
+                    Ok(redirections) => redirections,
+                    Err(error) => return Ok(failed(error)),
+                };
+                run_in_subshell(body)
+            }
+            SyntaxTree::Group(body, redirects) => {
+                let _redirections = match Redirection::apply(redirects) {
+                    Ok(redirections) => redirections,
+                    Err(error) => return Ok(failed(error)),
+                };
+                body.evaluate()
+            }
+            SyntaxTree::Sequence(commands) => {
+                let mut exit_code = 0;
+                for cmd in commands {
+                    exit_code = cmd.evaluate()?;
+                }
+                Ok(exit_code)
+            }
+            SyntaxTree::And(left, right) => match left.evaluate()? {
+                0 => right.evaluate(),
+                exit_code => Ok(exit_code),
+            },
+            SyntaxTree::Or(left, right) => match left.evaluate()? {
+                0 => Ok(0),
+                _ => right.evaluate(),
+            },
+        }
+    }
+}
+
+/// Reports the error of a command or redirection and returns the exit status of a failed
+/// command, so that `&&`, `||` and `;` go on with the rest of the line
+fn failed(error: anyhow::Error) -> i32 {
+    eprintln!("Command failed: {:?}", error);
+    1
+}
+
+/// Forks the shell and evaluates `body` in the child, so that `cd` or variable assignments inside
+/// it do not change the state of the parent.
+fn run_in_subshell(body: &SyntaxTree) -> anyhow::Result<i32> {
+    io::stdout().flush()?;
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error().into()),
+        0 => {
+            let exit_code = match body.evaluate() {
+                Ok(exit_code) => exit_code,
+                Err(error) => {
+                    eprintln!("Command failed: {:?}", error);
+                    1
+                }
+            };
+            let _ = io::stdout().flush();
+            unsafe { libc::_exit(exit_code) }
+        }
+        pid => {
+            let mut status = 0;
+            if unsafe { libc::waitpid(pid, &mut status, 0) } == -1 {
+                return Err(io::Error::last_os_error().into());
+            }
+            Ok(wait_status_to_exit_code(status))
+        }
+    }
+}
+
+/// Exit code of a waited process following the shell convention: the code it exited with, or 128
+/// plus the number of the signal that killed it.
+fn wait_status_to_exit_code(status: libc::c_int) -> i32 {
+    if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        1
+    }
+}
+
+/// Redirects the standard output of the shell itself while it is alive, so builtins, groups and
+/// child processes all write to the same file. The original stdout is restored on drop.
+struct Redirection {
+    saved_stdout: Option<RawFd>,
+}
+
+impl Redirection {
+    fn apply(redirects: &[Redirect]) -> anyhow::Result<Redirection> {
+        let mut redirection = Redirection { saved_stdout: None };
+        for redirect in redirects {
+            let path = expand_word(&redirect.path);
+            let file = File::options()
+                .create(true)
+                .write(true)
+                .append(redirect.append)
+                .truncate(!redirect.append)
+                .open(&path)
+                .map_err(|error| anyhow!("{}: {}", path, error))?;
+            io::stdout().flush()?;
+            if redirection.saved_stdout.is_none() {
+                let saved = unsafe { libc::dup(libc::STDOUT_FILENO) };
+                if saved == -1 {
+                    return Err(io::Error::last_os_error().into());
+                }
+                redirection.saved_stdout = Some(saved);
+            }
+            if unsafe { libc::dup2(file.as_raw_fd(), libc::STDOUT_FILENO) } == -1 {
+                return Err(io::Error::last_os_error().into());
+            }
+        }
+        Ok(redirection)
+    }
+}
+
+impl Drop for Redirection {
+    fn drop(&mut self) {
+        if let Some(saved) = self.saved_stdout {
+            let _ = io::stdout().flush();
+            unsafe {
+                libc::dup2(saved, libc::STDOUT_FILENO);
+                libc::close(saved);
+            }
+        }
+    }
+}
+
+/// Expands a word starting with `$` to the value of the environment variable it names, and a `~`
+/// at the start of a word to a directory, see `dir_stack::expand_tilde`. Words are expanded right
+/// before execution, so `cd dir; echo $PWD` sees the new directory. Quotes keep them as they
+/// are, except for a variable in double quotes like `"$HOME"`.
+fn expand_word(word: &Word) -> String {
+    let text = word.text.as_str();
+    if let (None | Some('"'), Some(var)) = (word.quote, text.strip_prefix('$')) {
+        return std::env::var(var).unwrap_or(text.to_string());
+    }
+    if let (None, Some(rest)) = (word.quote, text.strip_prefix('~')) {
+        let (spec, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        if let Some(dir) = dir_stack::expand_tilde(spec) {
+            return format!("{}{}", dir.display(), path);
+        }
     }
+    text.to_string()
 }
 
 /// Executable command with arguments. It can be any of the builtin commands or an external
 /// program.
 ///
-/// It is used to parse input into a `ShushCmd`, execute it, and create a `FinishedShushCmd` struct
-/// to hold its finish status.
+/// It is built by the parser from the words of a simple command, executed as part of a
+/// `SyntaxTree`, which creates a `FinishedShushCmd` struct to hold its finish status.
 ///
 /// ## Piping commands
 ///
@@ -58,54 +229,44 @@ impl fmt::Display for FinishedShushCmd<'_> {
 #[derive(Debug)]
 pub struct ShushCmd {
     program: String,
-    arguments: Vec<String>,
+    arguments: Vec<Word>,
     builtin: Option<BuiltInCommands>,
-    output: Option<File>,
+    redirects: Vec<Redirect>,
 }
 
 impl ShushCmd {
-    pub fn parse_command(line: String) -> Option<Self> {
-        let output_path = line.split(">").last();
-        let output = match output_path {
-            Some(path) => File::options()
-                .append(true)
-                .create(true)
-                .write(true)
-                .open(path)
-                .ok(),
-            None => None,
-        };
-        let words: Vec<String> = line
-            .split_whitespace()
-            .map(|word| word.to_string())
-            .collect();
-        let program = words.first()?.to_string();
+    pub fn parse_command(words: Vec<Word>, redirects: Vec<Redirect>) -> Option<Self> {
+        let program = words.first()?.text.clone();
         let builtin: Option<BuiltInCommands> = match program.as_str() {
+            "abbr" => Some(BuiltInCommands::Abbr),
+            "alias" => Some(BuiltInCommands::Alias),
+            "unalias" => Some(BuiltInCommands::Unalias),
             "cd" => Some(BuiltInCommands::CD),
+            "dirs" => Some(BuiltInCommands::Dirs),
+            "popd" => Some(BuiltInCommands::Popd),
+            "pushd" => Some(BuiltInCommands::Pushd),
+            "set" => Some(BuiltInCommands::Set),
+            "bind" => Some(BuiltInCommands::Bind),
+            "complete" => Some(BuiltInCommands::Complete),
+            "hash" => Some(BuiltInCommands::Hash),
+            "type" => Some(BuiltInCommands::Type),
+            "which" => Some(BuiltInCommands::Which),
+            "z" => Some(BuiltInCommands::Z),
             _ => None,
         };
-        let arguments = words[1..]
-            .to_vec()
-            .iter()
-            .map(|arg| -> String {
-                if arg.starts_with("$") {
-                    std::env::var(arg.split("$").last().unwrap_or("")).unwrap_or(arg.to_string())
-                } else {
-                    arg.to_string()
-                }
-            })
-            .collect();
+        let arguments = words[1..].to_vec();
         Some(Self {
             program,
             arguments,
             builtin,
-            output,
+            redirects,
         })
     }
 
-    pub fn execute_command(&self) -> anyhow::Result<FinishedShushCmd> {
+    pub fn execute_command(&self) -> anyhow::Result<i32> {
+        let _redirections = Redirection::apply(&self.redirects)?;
         match &self.builtin {
-            Some(builtin) => builtin.run(self),
+            Some(builtin) => Ok(if builtin.run(self)? { 0 } else { 1 }),
             None => self.execute_program(),
         }
     }
@@ -114,20 +275,35 @@ impl ShushCmd {
         self.arguments.len()
     }
 
+    /// Arguments with environment variables expanded
     pub fn args(&self) -> Vec<String> {
-        self.arguments.clone()
+        self.arguments.iter().map(expand_word).collect()
     }
 
-    fn execute_program(&self) -> anyhow::Result<FinishedShushCmd> {
-        let mut cmd_result = Command::new(&self.program).args(&self.arguments).spawn()?;
+    /// Runs the program found in `PATH` for the command. A program that is not found is handed
+    /// to `not_found`, one that cannot be executed exits with 126 like in other shells.
+    fn execute_program(&self) -> anyhow::Result<i32> {
+        let Some(path) = path_cache::resolve(&self.program) else {
+            return not_found::handle(&self.program, &self.args());
+        };
+        let spawned = Command::new(path)
+            .arg0(&self.program)
+            .args(self.args())
+            .spawn();
+        let mut cmd_result = match spawned {
+            Ok(child) => child,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                return not_found::handle(&self.program, &self.args())
+            }
+            Err(error) if error.kind() == io::ErrorKind::PermissionDenied => {
+                eprintln!("shush: {}: {}", self.program, error);
+                return Ok(126);
+            }
+            Err(error) => return Err(error.into()),
+        };
         let exit_status = cmd_result.wait()?;
-        Ok(FinishedShushCmd {
-            shush_cmd: self,
-            exit_code: exit_status.success(),
-            timestamp: timestamps::get(),
-        })
+        Ok(wait_status_to_exit_code(exit_status.into_raw()))
     }
-
 }
 
 impl fmt::Display for ShushCmd {
@@ -139,30 +315,142 @@ impl fmt::Display for ShushCmd {
             write!(f, " {}", arg)?;
         }
 
+        for redirect in &self.redirects {
+            write!(f, " {}", redirect)?;
+        }
+
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::FinishedShushCmd;
-    use super::ShushCmd;
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+
+    use crate::aliases;
+    use crate::parser::SyntaxTree;
+
+    /// Held by the tests that run commands, as they change the current directory or the
+    /// standard output of the whole test process
+    static PROCESS: Mutex<()> = Mutex::new(());
+
+    fn lock() -> MutexGuard<'static, ()> {
+        PROCESS.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn run(input: &str) -> i32 {
+        let tree = SyntaxTree::parse(input).unwrap().unwrap();
+        let finished_cmd = tree.execute().unwrap();
+        finished_cmd.exit_code
+    }
 
     #[test]
     fn executes_builtin_command() {
+        let _process = lock();
+        let cwd = std::env::current_dir().unwrap();
         let input = "cd /";
-        let cmd = ShushCmd::parse_command(input.to_string()).unwrap();
-        let finished_cmd = cmd.execute_command().unwrap();
+        let tree = SyntaxTree::parse(input).unwrap().unwrap();
+        let finished_cmd = tree.execute().unwrap();
+        std::env::set_current_dir(cwd).unwrap();
 
-        assert!(finished_cmd.exit_code == true);
+        assert_eq!(finished_cmd.exit_code, 0);
     }
 
     #[test]
     fn executes_program() {
+        let _process = lock();
         let input = "echo e";
-        let cmd = ShushCmd::parse_command(input.to_string()).unwrap();
-        let finished_cmd = cmd.execute_command().unwrap();
+        let tree = SyntaxTree::parse(input).unwrap().unwrap();
+        let finished_cmd = tree.execute().unwrap();
+
+        assert_eq!(finished_cmd.exit_code, 0);
+    }
 
-        assert!(finished_cmd.exit_code == true);
+    #[test]
+    fn exits_with_127_when_the_program_is_not_found() {
+        let _process = lock();
+        assert_eq!(run("shush-no-such-program"), 127);
+        assert_eq!(run("./shush-no-such-program || true"), 0);
+    }
+
+    /// Arguments of the simple command of `line`, expanded
+    fn args(line: &str) -> Vec<String> {
+        match SyntaxTree::parse(line).unwrap().unwrap() {
+            SyntaxTree::Command(cmd) => cmd.args(),
+            tree => panic!("not a simple command: {}", tree),
+        }
+    }
+
+    #[test]
+    fn expands_tildes() {
+        let home = std::env::var("HOME").unwrap();
+        assert_eq!(
+            args("echo ~ ~/src"),
+            [home.clone(), format!("{}/src", home)]
+        );
+        assert_eq!(args("echo ~nobody-here/x a~"), ["~nobody-here/x", "a~"]);
+        assert_eq!(args("echo ~/'my dir'"), [format!("{}/my dir", home)]);
+    }
+
+    #[test]
+    fn keeps_quoted_words_as_they_are() {
+        let home = std::env::var("HOME").unwrap();
+        assert_eq!(args("echo $HOME"), [home.as_str()]);
+        assert_eq!(args("echo '~' \"~/x\" \\~ '~1'"), ["~", "~/x", "~", "~1"]);
+        assert_eq!(
+            args("echo '$HOME' \\$HOME \"$HOME\""),
+            ["$HOME", "$HOME", &home]
+        );
+    }
+
+    #[test]
+    fn short_circuits_and_or() {
+        let _process = lock();
+        assert_eq!(run("false && true"), 1);
+        assert_eq!(run("false || true"), 0);
+        assert_eq!(run("true && true"), 0);
+        assert_eq!(run("( false ) || false && true"), 1);
+    }
+
+    #[test]
+    fn subshell_does_not_leak_cd() {
+        let _process = lock();
+        let dir = std::env::temp_dir().join("shush_subshell_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let out = dir.join("out");
+        let line = format!(
+            "( cd {dir} && pwd > out ) ; {{ echo a ; echo b ; }} >> {out}",
+            dir = dir.display(),
+            out = out.display()
+        );
+        assert_eq!(run(&line), 0);
+        assert_ne!(std::env::current_dir().unwrap(), dir);
+        let written = std::fs::read_to_string(&out).unwrap();
+        assert_eq!(written, format!("{}\na\nb\n", dir.display()));
+    }
+
+    #[test]
+    fn goes_on_after_a_failed_builtin() {
+        let _process = lock();
+        let out = std::env::temp_dir().join("shush_failed_builtin_test");
+        let line = format!(
+            "cd /shush-nope || echo ok > {out}; cd /shush-nope; echo ok >> {out}",
+            out = out.display()
+        );
+        assert_eq!(run(&line), 0);
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "ok\nok\n");
+        assert_eq!(run("cd /shush-nope && true"), 1);
+        assert_eq!(run("true > /shush-nope/out || true"), 0);
+    }
+
+    #[test]
+    fn saves_aliases_unexpanded_to_the_history() {
+        let _process = lock();
+        aliases::set("ll", "true -l").unwrap();
+        let tree = SyntaxTree::parse("ll").unwrap().unwrap();
+        assert_eq!(tree.to_string(), "true -l");
+        let finished_cmd = tree.execute().unwrap();
+        assert_eq!(finished_cmd.history_entry("ll").command, "ll");
+        aliases::clear();
     }
 }
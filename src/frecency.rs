@@ -0,0 +1,248 @@
+//! Directories visited with `cd`, ranked by frecency for the `z` builtin, like z or zoxide.
+//!
+//! Each visit adds 1 to the rank of a directory, and its score is the rank weighted by how long
+//! ago it was last visited. When the ranks add up to more than `MAX_TOTAL_RANK` they are all
+//! aged by `AGING`, and the directories whose rank drops below 1 are forgotten. The database is a
+//! file with a `path|rank|time` line per directory, in the data directory.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::home_dir;
+use crate::timestamps;
+
+/// Sum of the ranks above which they are aged
+const MAX_TOTAL_RANK: f64 = 9000.0;
+const AGING: f64 = 0.99;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub rank: f64,
+    /// Seconds since the epoch of the last visit
+    pub time: u64,
+}
+
+impl Entry {
+    fn parse(line: &str) -> Option<Entry> {
+        // the path can contain `|`, so the other fields are split from the end
+        let mut fields = line.rsplitn(3, '|');
+        let time = fields.next()?.parse().ok()?;
+        let rank = fields.next()?.parse().ok()?;
+        let path = PathBuf::from(fields.next()?);
+        Some(Entry { path, rank, time })
+    }
+
+    /// The rank weighted by the time since the last visit
+    pub fn score(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.time);
+        let weight = if age < HOUR {
+            4.0
+        } else if age < DAY {
+            2.0
+        } else if age < WEEK {
+            0.5
+        } else {
+            0.25
+        };
+        self.rank * weight
+    }
+}
+
+thread_local! {
+    /// File of the database, directories are not recorded without one
+    static DATABASE: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+pub fn set_database(path: Option<PathBuf>) {
+    DATABASE.with(|database| *database.borrow_mut() = path);
+}
+
+fn database() -> Option<PathBuf> {
+    DATABASE.with(|database| database.borrow().clone())
+}
+
+/// Reads the directories of the database. A missing file has none.
+pub fn load() -> io::Result<Vec<Entry>> {
+    let Some(path) = database() else {
+        return Ok(Vec::new());
+    };
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents.lines().filter_map(Entry::parse).collect()),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(error) => Err(error),
+    }
+}
+
+/// Writes the database to a new file first, so that a shell reading it does not see half of it
+fn save(entries: &[Entry]) -> io::Result<()> {
+    let Some(path) = database() else {
+        return Ok(());
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let contents: String = entries
+        .iter()
+        .map(|entry| format!("{}|{}|{}\n", entry.path.display(), entry.rank, entry.time))
+        .collect();
+    let temporary = path.with_extension(format!("{}", std::process::id()));
+    fs::write(&temporary, contents)?;
+    fs::rename(temporary, path)
+}
+
+/// Records a visit to a directory. The home directory is not recorded, it is one `cd` away.
+pub fn record(dir: &Path) -> io::Result<()> {
+    if database().is_none() || dir == home_dir() {
+        return Ok(());
+    }
+    let mut entries = load()?;
+    visit(&mut entries, dir, now());
+    save(&entries)
+}
+
+pub fn now() -> u64 {
+    (timestamps::get() / 1000) as u64
+}
+
+fn visit(entries: &mut Vec<Entry>, dir: &Path, now: u64) {
+    match entries.iter_mut().find(|entry| entry.path == dir) {
+        Some(entry) => {
+            entry.rank += 1.0;
+            entry.time = now;
+        }
+        None => entries.push(Entry {
+            path: dir.to_path_buf(),
+            rank: 1.0,
+            time: now,
+        }),
+    }
+    if entries.iter().map(|entry| entry.rank).sum::<f64>() > MAX_TOTAL_RANK {
+        for entry in entries.iter_mut() {
+            entry.rank *= AGING;
+        }
+        entries.retain(|entry| entry.rank >= 1.0);
+    }
+}
+
+/// Directories that match all the terms, the best scoring first. The terms have to appear in the
+/// path in order, and the last one in its last component, so `z src` goes to `~/src` and not to
+/// `~/src/shush`. Case is ignored unless a term has uppercase letters. Directories that do not
+/// exist anymore are left out.
+pub fn matches<'a>(entries: &'a [Entry], terms: &[String], now: u64) -> Vec<&'a Entry> {
+    let ignore_case = !terms
+        .iter()
+        .any(|term| term.chars().any(char::is_uppercase));
+    let fold = |text: &str| {
+        if ignore_case {
+            text.to_lowercase()
+        } else {
+            text.to_string()
+        }
+    };
+    let terms: Vec<String> = terms.iter().map(|term| fold(term)).collect();
+    let mut found: Vec<&Entry> = entries
+        .iter()
+        .filter(|entry| {
+            let path = fold(&entry.path.to_string_lossy());
+            let mut rest = path.as_str();
+            for term in &terms {
+                match rest.find(term.as_str()) {
+                    Some(index) => rest = &rest[index + term.len()..],
+                    None => return false,
+                }
+            }
+            let last = terms.last().map_or("", String::as_str);
+            let name = path.rsplit('/').next().unwrap_or_default();
+            name.contains(last)
+        })
+        .filter(|entry| entry.path.is_dir())
+        .collect();
+    found.sort_by(|a, b| b.score(now).total_cmp(&a.score(now)));
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{matches, visit, Entry, DAY, HOUR, MAX_TOTAL_RANK, WEEK};
+
+    fn entry(path: &Path, rank: f64, time: u64) -> Entry {
+        Entry {
+            path: path.to_path_buf(),
+            rank,
+            time,
+        }
+    }
+
+    #[test]
+    fn parses_lines_from_the_end() {
+        assert_eq!(
+            Entry::parse("/tmp/a|b|2.5|100"),
+            Some(entry(Path::new("/tmp/a|b"), 2.5, 100))
+        );
+        assert_eq!(Entry::parse("/tmp|x|100"), None);
+    }
+
+    #[test]
+    fn weighs_recent_visits_more() {
+        let now = 10 * WEEK;
+        let visited = |ago| entry(Path::new("/"), 4.0, now - ago).score(now);
+        assert_eq!(visited(0), 16.0);
+        assert_eq!(visited(2 * HOUR), 8.0);
+        assert_eq!(visited(2 * DAY), 2.0);
+        assert_eq!(visited(2 * WEEK), 1.0);
+    }
+
+    #[test]
+    fn ages_ranks_and_forgets_rare_directories() {
+        let mut entries = vec![
+            entry(Path::new("/often"), 2.0 * MAX_TOTAL_RANK, 0),
+            entry(Path::new("/once"), 1.0, 0),
+        ];
+        visit(&mut entries, Path::new("/once"), 5);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].rank, 2.0 * 0.99);
+        assert_eq!(entries[1].time, 5);
+        visit(&mut entries, Path::new("/new"), 6);
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|entry| entry.path != Path::new("/new")));
+    }
+
+    #[test]
+    fn matches_terms_in_order_with_the_last_one_in_the_name() {
+        let root = std::env::temp_dir().join("shush_frecency_test");
+        let dir = |path: &str| {
+            let dir = root.join(path);
+            std::fs::create_dir_all(&dir).unwrap();
+            dir
+        };
+        let (src, shush, docs) = (dir("src"), dir("src/shush"), dir("Docs/shush"));
+        let entries = vec![
+            entry(&src, 1.0, 0),
+            entry(&shush, 5.0, 0),
+            entry(&docs, 2.0, 0),
+            entry(&root.join("gone"), 9.0, 0),
+        ];
+        let found = |terms: &[&str]| -> Vec<PathBuf> {
+            let terms: Vec<String> = terms.iter().map(|term| term.to_string()).collect();
+            matches(&entries, &terms, 0)
+                .into_iter()
+                .map(|entry| entry.path.clone())
+                .collect()
+        };
+        assert_eq!(found(&["shush"]), [shush.clone(), docs.clone()]);
+        assert_eq!(found(&["src"]), [src]);
+        assert_eq!(found(&["docs", "sh"]), [docs]);
+        assert_eq!(found(&["Docs"]), Vec::<PathBuf>::new());
+        assert_eq!(found(&["sh", "docs"]), Vec::<PathBuf>::new());
+        assert_eq!(found(&["gone"]), Vec::<PathBuf>::new());
+    }
+}
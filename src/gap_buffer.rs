@@ -1,16 +1,17 @@
-#![allow(dead_code)]
-#![allow(unused)]
-use core::panic;
+use std::borrow::Cow;
 use std::cmp;
+use std::ops::Range;
 
 const BUF_INIT_SIZE: usize = 32;
 
+/// Text with a gap of free space at the cursor, so that typing and deleting there only moves the
+/// ends of the gap. The gap is moved in place when the cursor moves, and the buffer at least
+/// doubles its size when the gap fills up, so inserting is amortized O(1).
 #[derive(Debug, Clone)]
 pub struct GapBuffer {
-    data: Box<[u8]>,
+    data: Vec<u8>,
     gap_start: usize,
     gap_end: usize,
-    cursor: usize,
 }
 
 /// Updating the gap to the cursor at every movement is very costly, so it should only be updated when
@@ -18,101 +19,207 @@ pub struct GapBuffer {
 impl GapBuffer {
     pub fn new() -> Self {
         Self {
-            data: Box::new([0; BUF_INIT_SIZE]),
+            data: vec![0; BUF_INIT_SIZE],
             gap_start: 0,
             gap_end: BUF_INIT_SIZE,
-            cursor: 0,
         }
     }
 
-    pub fn get_text(&self) -> Result<String, std::string::FromUtf8Error> {
-        let capacity = self.data.len() + self.gap_end - self.gap_start;
-        let mut buffer = Vec::<u8>::with_capacity(capacity);
-        //eprintln!("gap_start: {}. buflen: {}. gap_end: {}", self.gap_start, self.buffer_len(), self.gap_end);
-        buffer.extend_from_slice(&self.data[0..self.gap_start]);
-        buffer.extend_from_slice(&self.data[self.gap_end..self.data.len()]);
-        String::from_utf8(buffer)
+    /// The text never contains invalid UTF-8, since only whole chars can be inserted and deleted
+    pub fn get_text(&self) -> String {
+        let mut buffer = Vec::<u8>::with_capacity(self.text_len());
+        buffer.extend_from_slice(&self.data[..self.gap_start]);
+        buffer.extend_from_slice(&self.data[self.gap_end..]);
+        String::from_utf8(buffer).expect("gap buffer text should be valid UTF-8")
     }
 
-    /// Inserts a string slice into the gap
-    /// if the gap is big enough
-    pub fn insert(&mut self, slice: &[u8]) {
-        if self.gap_len() > slice.len() {
-            let insert_space = &mut self.data[self.gap_start..self.gap_start + slice.len()];
-            insert_space.copy_from_slice(slice);
-            self.gap_start += slice.len();
-        } else {
-            self.grow_gap(slice);
+    /// Inserts a string slice into the gap, growing it first if it is too small
+    pub fn insert(&mut self, text: &str) {
+        let slice = text.as_bytes();
+        if self.gap_len() < slice.len() {
+            self.grow_gap(slice.len());
         }
+        self.data[self.gap_start..self.gap_start + slice.len()].copy_from_slice(slice);
+        self.gap_start += slice.len();
     }
 
-    pub fn insert_char(&mut self, char: u8) {
-        if self.gap_end - self.gap_start == 0 {
-            self.grow_gap(&[char]);
-        }
-        self.data[self.gap_start] = char;
-        self.gap_start += 1;
+    #[cfg(test)]
+    pub fn insert_char(&mut self, char: char) {
+        let mut encoded = [0; 4];
+        self.insert(char.encode_utf8(&mut encoded));
     }
 
+    /// Deletes `nchars` chars, not bytes, before the gap, or as many as there are
+    #[cfg(test)]
     pub fn delete_backwards(&mut self, nchars: usize) {
-        assert!(nchars <= self.gap_start);
-        self.gap_start -= nchars;
+        for _ in 0..nchars {
+            if self.gap_start == 0 {
+                break;
+            }
+            self.gap_start -= 1;
+            while !is_char_boundary(self.data[self.gap_start]) {
+                self.gap_start -= 1;
+            }
+        }
     }
 
+    /// Deletes `nchars` chars, not bytes, after the gap, or as many as there are
     pub fn delete_forwards(&mut self, nchars: usize) {
-        assert!(nchars <= self.gap_end);
-        self.gap_end += nchars;
+        for _ in 0..nchars {
+            if self.gap_end == self.data.len() {
+                break;
+            }
+            self.gap_end += 1;
+            while self.gap_end < self.data.len() && !is_char_boundary(self.data[self.gap_end]) {
+                self.gap_end += 1;
+            }
+        }
     }
-    
-    /// the cursor behaves in a smaller buffer, the text without gap buffer
+
+    /// the cursor behaves in a smaller buffer, the text without gap buffer. It is a byte offset
+    /// that has to be at a char boundary.
     pub fn move_gap_to_cursor(&mut self, cursor: usize) {
+        assert!(cursor <= self.text_len(), "cursor out of bounds");
         if self.gap_start == cursor {
             return;
         }
-        if self.buffer_len() - self.gap_len() < cursor {
-            panic!("cursor out of bounds");
+        if cursor < self.text_len() {
+            let byte = if cursor < self.gap_start {
+                self.data[cursor]
+            } else {
+                self.data[cursor + self.gap_len()]
+            };
+            assert!(is_char_boundary(byte), "cursor is not at a char boundary");
+        }
+        if cursor < self.gap_start {
+            // the text between the cursor and the gap goes to the end of the gap
+            let len = self.gap_start - cursor;
+            self.data
+                .copy_within(cursor..self.gap_start, self.gap_end - len);
+            self.gap_start = cursor;
+            self.gap_end -= len;
+        } else {
+            // the text between the gap and the cursor goes to the start of the gap
+            let len = cursor - self.gap_start;
+            self.data
+                .copy_within(self.gap_end..self.gap_end + len, self.gap_start);
+            self.gap_start += len;
+            self.gap_end += len;
         }
-        let new_gap_start = cursor;
-        let new_gap_end = cursor + self.gap_len();
+    }
 
-        let buffer = if self.gap_start > new_gap_start {
-            self.move_gap_backwards(new_gap_start, new_gap_end)
+    /// Replaces the bytes in `range` with `text`, leaving the gap after the new text
+    pub fn replace_range(&mut self, range: Range<usize>, text: &str) {
+        assert!(range.start <= range.end, "range starts after it ends");
+        assert!(range.end <= self.text_len(), "range out of bounds");
+        self.move_gap_to_cursor(range.start);
+        let end = range.end + self.gap_len();
+        assert!(
+            end == self.data.len() || is_char_boundary(self.data[end]),
+            "range does not end at a char boundary"
+        );
+        self.gap_end = end;
+        self.insert(text);
+    }
+
+    /// The text before and after the gap
+    pub fn as_slices(&self) -> (&str, &str) {
+        let before = std::str::from_utf8(&self.data[..self.gap_start]);
+        let after = std::str::from_utf8(&self.data[self.gap_end..]);
+        (
+            before.expect("gap buffer text should be valid UTF-8"),
+            after.expect("gap buffer text should be valid UTF-8"),
+        )
+    }
+
+    /// The text in a byte range, only copied if the range spans the gap
+    pub fn slice(&self, range: Range<usize>) -> Cow<'_, str> {
+        let (before, after) = self.as_slices();
+        let split = before.len();
+        if range.end <= split {
+            Cow::Borrowed(&before[range])
+        } else if range.start >= split {
+            Cow::Borrowed(&after[range.start - split..range.end - split])
         } else {
-            self.move_gap_forwards(new_gap_start, new_gap_end)
-        };
+            Cow::Owned(format!(
+                "{}{}",
+                &before[range.start..],
+                &after[..range.end - split]
+            ))
+        }
+    }
 
-        self.gap_start = new_gap_start;
-        self.gap_end = new_gap_end;
-        self.data = buffer.into_boxed_slice(); 
+    /// Byte offset of the first match of `pattern` at or after `from`. An offset past the end of
+    /// the text is taken as its end.
+    pub fn find(&self, pattern: &str, from: usize) -> Option<usize> {
+        let (before, after) = self.as_slices();
+        let split = before.len();
+        let from = cmp::min(from, self.text_len());
+        if from < split {
+            if let Some(index) = before[from..].find(pattern) {
+                return Some(from + index);
+            }
+            // a match can start before the gap and end after it
+            let start = cmp::max(from, split.saturating_sub(pattern.len().saturating_sub(1)));
+            for index in start..split {
+                if before.is_char_boundary(index) && self.starts_with_at(pattern, index) {
+                    return Some(index);
+                }
+            }
+        }
+        let from = cmp::max(from, split) - split;
+        after[from..]
+            .find(pattern)
+            .map(|index| split + from + index)
     }
 
-    pub fn clear_buffer_text(&mut self) {
-        self.gap_start = 0;
-        self.gap_end = self.buffer_len();
+    /// Byte offset of the last match of `pattern` that ends at or before `end`. An offset past
+    /// the end of the text is taken as its end.
+    pub fn rfind(&self, pattern: &str, end: usize) -> Option<usize> {
+        let (before, after) = self.as_slices();
+        let split = before.len();
+        let end = cmp::min(end, self.text_len());
+        if end > split {
+            if let Some(index) = after[..end - split].rfind(pattern) {
+                return Some(split + index);
+            }
+            let start = split.saturating_sub(pattern.len().saturating_sub(1));
+            for index in (start..split).rev() {
+                if index + pattern.len() <= end
+                    && before.is_char_boundary(index)
+                    && self.starts_with_at(pattern, index)
+                {
+                    return Some(index);
+                }
+            }
+        }
+        before[..cmp::min(end, split)].rfind(pattern)
     }
 
-    fn move_gap_backwards(&mut self, new_gap_start: usize, new_gap_end: usize) -> Vec<u8> {
-        let left = &self.data[0..new_gap_start];
-        let swap = &self.data[new_gap_start..self.gap_start];
-        let right = &self.data[self.gap_end..self.buffer_len()];
-        let mut buffer = Vec::<u8>::with_capacity(self.buffer_len());
-        buffer.extend_from_slice(left);
-        buffer.resize(new_gap_end - new_gap_start, 0);
-        buffer.extend_from_slice(swap);
-        buffer.extend_from_slice(right);
-        buffer
+    /// Whether the text at the byte offset starts with `pattern`
+    fn starts_with_at(&self, pattern: &str, offset: usize) -> bool {
+        offset + pattern.len() <= self.text_len()
+            && pattern
+                .bytes()
+                .enumerate()
+                .all(|(index, byte)| self.byte_at(offset + index) == byte)
     }
 
-    fn move_gap_forwards(&mut self, new_gap_start: usize, new_gap_end: usize) -> Vec<u8> {
-        let left = &self.data[0..self.gap_start];
-        let swap = &self.data[self.gap_end..new_gap_end];
-        let right = &self.data[new_gap_end..self.gap_len()];
-        let mut buffer = Vec::<u8>::with_capacity(self.buffer_len());
-        buffer.extend_from_slice(left);
-        buffer.extend_from_slice(swap);
-        buffer.resize(new_gap_end - new_gap_start, 0);
-        buffer.extend_from_slice(right);
-        buffer
+    fn byte_at(&self, offset: usize) -> u8 {
+        if offset < self.gap_start {
+            self.data[offset]
+        } else {
+            self.data[offset + self.gap_len()]
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text_len() == 0
+    }
+
+    pub fn clear_buffer_text(&mut self) {
+        self.gap_start = 0;
+        self.gap_end = self.buffer_len();
     }
 
     fn gap_len(&self) -> usize {
@@ -126,54 +233,176 @@ impl GapBuffer {
     pub fn text_len(&self) -> usize {
         self.data.len() - self.gap_len()
     }
-    
-    fn grow_gap(&mut self, slice: &[u8]) {
-        let slice_len = slice.len();
-        let new_gap_size = self.compute_new_gap_size(slice_len);
-        //eprintln!("gap_start: {}. datalen: {}. gap_end: {}", self.gap_start, self.data.len(), self.gap_end);
-        let new_capacity = {
-            let pre_gap = self.gap_start;
-            let post_gap = self.data.len() - self.gap_end;
-            pre_gap + new_gap_size + slice_len + post_gap
-        };
-        let mut buffer = Vec::<u8>::with_capacity(new_capacity);
-        buffer.extend_from_slice(&self.data[0..self.gap_start]);
-        buffer.extend_from_slice(&slice);
-        buffer.resize(
-            self.gap_start + slice_len + new_gap_size + self.data.len() - self.gap_end,
-            0,
-        );
-        buffer.extend_from_slice(&self.data[self.gap_end..self.data.len()]);
-        self.gap_start += slice_len;
-        self.gap_end = self.gap_start + new_gap_size;
-        self.data = buffer.into_boxed_slice();
+
+    /// Makes room in the gap for `len` more bytes, at least doubling the buffer
+    fn grow_gap(&mut self, len: usize) {
+        let old_len = self.data.len();
+        let new_len = cmp::max(old_len * 2, old_len + len);
+        let after_gap = old_len - self.gap_end;
+        self.data.resize(new_len, 0);
+        self.data
+            .copy_within(self.gap_end..old_len, new_len - after_gap);
+        self.gap_end = new_len - after_gap;
+    }
+}
+
+/// Reading the text by chars, words and lines, and converting between byte offsets, char indices
+/// and positions. The editor still works on a copy of the text for these, so they are only used
+/// by the tests for now.
+#[allow(dead_code)]
+impl GapBuffer {
+    /// The text in a range of chars
+    pub fn slice_chars(&self, range: Range<usize>) -> Cow<'_, str> {
+        self.slice(self.char_to_byte(range.start)..self.char_to_byte(range.end))
+    }
+
+    /// Byte offset of the char with index `char_index`, or the length of the text past the end
+    pub fn char_to_byte(&self, char_index: usize) -> usize {
+        self.chars_from(0)
+            .nth(char_index)
+            .map_or(self.text_len(), |(offset, _)| offset)
+    }
+
+    /// Number of chars before the byte offset
+    pub fn byte_to_char(&self, offset: usize) -> usize {
+        self.chars_from(0)
+            .take_while(|(index, _)| *index < offset)
+            .count()
+    }
+
+    /// Chars from the byte offset to the end, with their offsets
+    pub fn chars_from(&self, offset: usize) -> impl Iterator<Item = (usize, char)> + '_ {
+        let (before, after) = self.as_slices();
+        let split = before.len();
+        let before = before
+            .char_indices()
+            .skip_while(move |(index, _)| *index < offset);
+        let after = after
+            .char_indices()
+            .map(move |(index, char)| (split + index, char))
+            .skip_while(move |(index, _)| *index < offset);
+        before.chain(after)
+    }
+
+    /// Chars before the byte offset, from the closest one to the start, with their offsets
+    pub fn chars_before(&self, offset: usize) -> impl Iterator<Item = (usize, char)> + '_ {
+        let (before, after) = self.as_slices();
+        let split = before.len();
+        let after = after
+            .char_indices()
+            .rev()
+            .map(move |(index, char)| (split + index, char))
+            .skip_while(move |(index, _)| *index >= offset);
+        let before = before
+            .char_indices()
+            .rev()
+            .skip_while(move |(index, _)| *index >= offset);
+        after.chain(before)
     }
 
-    fn compute_new_gap_size(&self, slice_len: usize) -> usize {
-        let five_percent = self.data.len() * 0.05 as usize;
-        let next_pow_of_two = (self.gap_len() + slice_len).next_power_of_two();
-        cmp::max(five_percent, next_pow_of_two)
+    /// Byte ranges of the words in the text from the offset on, a word being a run of
+    /// alphanumeric chars. When the offset is inside a word, the first range is the part of it
+    /// from the offset.
+    pub fn words_from(&self, offset: usize) -> impl Iterator<Item = Range<usize>> + '_ {
+        let mut chars = self.chars_from(offset).peekable();
+        std::iter::from_fn(move || {
+            while chars.next_if(|(_, char)| !char.is_alphanumeric()).is_some() {}
+            let (start, first) = chars.next()?;
+            let mut end = start + first.len_utf8();
+            while let Some((index, char)) = chars.next_if(|(_, char)| char.is_alphanumeric()) {
+                end = index + char.len_utf8();
+            }
+            Some(start..end)
+        })
+    }
+
+    /// Byte ranges of the words in the text before the offset, from the closest one to the
+    /// start. When the offset is inside a word, the first range is the part of it before the
+    /// offset.
+    pub fn words_before(&self, offset: usize) -> impl Iterator<Item = Range<usize>> + '_ {
+        let mut chars = self.chars_before(offset).peekable();
+        std::iter::from_fn(move || {
+            while chars.next_if(|(_, char)| !char.is_alphanumeric()).is_some() {}
+            let (last, char) = chars.next()?;
+            let end = last + char.len_utf8();
+            let mut start = last;
+            while let Some((index, _)) = chars.next_if(|(_, char)| char.is_alphanumeric()) {
+                start = index;
+            }
+            Some(start..end)
+        })
+    }
+
+    /// Byte range of the line that the offset is in, without its newline
+    pub fn line_at(&self, offset: usize) -> Range<usize> {
+        let start = self.rfind("\n", offset).map_or(0, |index| index + 1);
+        let end = self.find("\n", offset).unwrap_or(self.text_len());
+        start..end
+    }
+
+    /// Line and column, counted in chars, of the byte offset
+    pub fn offset_to_position(&self, offset: usize) -> (usize, usize) {
+        let mut row = 0;
+        let mut column = 0;
+        for (_, char) in self.chars_from(0).take_while(|(index, _)| *index < offset) {
+            if char == '\n' {
+                row += 1;
+                column = 0;
+            } else {
+                column += 1;
+            }
+        }
+        (row, column)
+    }
+
+    /// Byte offset of a line and column, counted in chars. Columns past the end of the line
+    /// give the end of the line, and lines past the end of the text give `None`.
+    pub fn position_to_offset(&self, row: usize, column: usize) -> Option<usize> {
+        let start = if row == 0 {
+            0
+        } else {
+            self.chars_from(0)
+                .filter(|(_, char)| *char == '\n')
+                .nth(row - 1)
+                .map(|(index, _)| index + 1)?
+        };
+        let line = self.line_at(start);
+        Some(
+            self.chars_from(start)
+                .take_while(|(index, _)| *index < line.end)
+                .nth(column)
+                .map_or(line.end, |(index, _)| index),
+        )
     }
 }
 
+/// Whether `byte` starts a char in UTF-8, that is, it is not a continuation byte `0b10xxxxxx`
+fn is_char_boundary(byte: u8) -> bool {
+    (byte as i8) >= -0x40
+}
+
 #[cfg(test)]
 mod tests {
-    use super::GapBuffer;
+    use proptest::prelude::*;
+
+    use std::borrow::Cow;
+
+    use super::{GapBuffer, BUF_INIT_SIZE};
 
     #[test]
     fn insert_char() {
         // [one way of doing things]
         let buffer = "one way of doing things";
         let mut gap_buffer = GapBuffer::new();
-        gap_buffer.insert(buffer.as_bytes());
+        gap_buffer.insert(buffer);
         println!(
             "{} - {} + {}",
             gap_buffer.buffer_len(),
             gap_buffer.gap_start,
             gap_buffer.gap_end,
         );
-        gap_buffer.insert_char('C' as u8);
-        let text = gap_buffer.get_text().unwrap();
+        gap_buffer.insert_char('C');
+        let text = gap_buffer.get_text();
         print!("{}", text);
         assert!(text.contains('C'));
     }
@@ -183,7 +412,7 @@ mod tests {
         // [one way of doing things]
         let buffer = "one way of doing things";
         let mut gap_buffer = GapBuffer::new();
-        gap_buffer.insert(buffer.as_bytes());
+        gap_buffer.insert(buffer);
         gap_buffer.move_gap_to_cursor(gap_buffer.buffer_len() - gap_buffer.gap_len());
         println!(
             "buffer len:{}. gap start: {}. gap end: {}.",
@@ -192,17 +421,17 @@ mod tests {
             gap_buffer.gap_end,
         );
         gap_buffer.delete_backwards(1);
-        let text = gap_buffer.get_text().unwrap();
+        let text = gap_buffer.get_text();
         print!("{}", text);
         assert_eq!(text, "one way of doing thing");
     }
-    
+
     #[test]
     fn delete_one_forwards() {
         // [one way of doing things]
         let buffer = "one way of doing things";
         let mut gap_buffer = GapBuffer::new();
-        gap_buffer.insert(buffer.as_bytes());
+        gap_buffer.insert(buffer);
         gap_buffer.move_gap_to_cursor(0);
         println!(
             "buffer len:{}. gap start: {}. gap end: {}.",
@@ -211,8 +440,250 @@ mod tests {
             gap_buffer.gap_end,
         );
         gap_buffer.delete_forwards(1);
-        let text = gap_buffer.get_text().unwrap();
+        let text = gap_buffer.get_text();
         print!("{}", text);
         assert_eq!(text, "ne way of doing things");
     }
+
+    #[test]
+    fn deletes_whole_chars() {
+        let mut gap_buffer = GapBuffer::new();
+        gap_buffer.insert("añ🤫");
+        gap_buffer.delete_backwards(1);
+        assert_eq!(gap_buffer.get_text(), "añ");
+        gap_buffer.move_gap_to_cursor(1);
+        gap_buffer.delete_forwards(1);
+        assert_eq!(gap_buffer.get_text(), "a");
+    }
+
+    #[test]
+    #[should_panic]
+    fn cursor_inside_char_panics() {
+        let mut gap_buffer = GapBuffer::new();
+        gap_buffer.insert("ñ");
+        gap_buffer.move_gap_to_cursor(1);
+    }
+
+    #[test]
+    fn fills_the_gap_before_growing() {
+        let mut gap_buffer = GapBuffer::new();
+        gap_buffer.insert(&"a".repeat(BUF_INIT_SIZE));
+        assert_eq!(gap_buffer.buffer_len(), BUF_INIT_SIZE);
+        gap_buffer.move_gap_to_cursor(1);
+        gap_buffer.insert("b");
+        assert_eq!(gap_buffer.buffer_len(), 2 * BUF_INIT_SIZE);
+        assert_eq!(
+            gap_buffer.get_text(),
+            format!("ab{}", "a".repeat(BUF_INIT_SIZE - 1))
+        );
+    }
+
+    #[test]
+    fn deletes_up_to_the_ends_of_the_text() {
+        let mut gap_buffer = GapBuffer::new();
+        gap_buffer.insert("añb");
+        gap_buffer.move_gap_to_cursor(1);
+        gap_buffer.delete_forwards(5);
+        assert_eq!(gap_buffer.get_text(), "a");
+        gap_buffer.delete_backwards(5);
+        assert_eq!(gap_buffer.text_len(), 0);
+    }
+
+    /// A buffer with `text` and the gap at byte offset `gap`
+    fn with_gap(text: &str, gap: usize) -> GapBuffer {
+        let mut gap_buffer = GapBuffer::new();
+        gap_buffer.insert(text);
+        gap_buffer.move_gap_to_cursor(gap);
+        gap_buffer
+    }
+
+    #[test]
+    fn slices_across_the_gap() {
+        let gap_buffer = with_gap("hello world", 5);
+        assert_eq!(gap_buffer.as_slices(), ("hello", " world"));
+        assert!(matches!(gap_buffer.slice(1..4), Cow::Borrowed("ell")));
+        assert!(matches!(gap_buffer.slice(6..11), Cow::Borrowed("world")));
+        assert!(matches!(gap_buffer.slice(3..8), Cow::Owned(text) if text == "lo wo"));
+        assert_eq!(gap_buffer.slice(5..5), "");
+    }
+
+    #[test]
+    fn converts_between_chars_and_bytes() {
+        let gap_buffer = with_gap("añb€c", 3);
+        assert_eq!(gap_buffer.char_to_byte(2), 3);
+        assert_eq!(gap_buffer.char_to_byte(4), 7);
+        assert_eq!(gap_buffer.char_to_byte(10), 8);
+        assert_eq!(gap_buffer.byte_to_char(7), 4);
+        assert_eq!(gap_buffer.slice_chars(1..4), "ñb€");
+    }
+
+    #[test]
+    fn iterates_chars_both_ways() {
+        let gap_buffer = with_gap("añb", 3);
+        let forwards: Vec<_> = gap_buffer.chars_from(1).collect();
+        assert_eq!(forwards, [(1, 'ñ'), (3, 'b')]);
+        let backwards: Vec<_> = gap_buffer.chars_before(4).collect();
+        assert_eq!(backwards, [(3, 'b'), (1, 'ñ'), (0, 'a')]);
+        assert_eq!(gap_buffer.chars_before(0).next(), None);
+    }
+
+    #[test]
+    fn iterates_words_both_ways() {
+        let gap_buffer = with_gap("git  commit -m fix", 8);
+        // the offset is inside `commit`, which starts at 5
+        let forwards: Vec<_> = gap_buffer.words_from(6).collect();
+        assert_eq!(forwards, [6..11, 13..14, 15..18]);
+        let backwards: Vec<_> = gap_buffer.words_before(9).collect();
+        assert_eq!(backwards, [5..9, 0..3]);
+    }
+
+    #[test]
+    fn finds_matches_across_the_gap() {
+        let gap_buffer = with_gap("echo foo | grep foo", 7);
+        assert_eq!(gap_buffer.find("foo", 0), Some(5));
+        assert_eq!(gap_buffer.find("foo", 6), Some(16));
+        assert_eq!(gap_buffer.find("bar", 0), None);
+        assert_eq!(gap_buffer.rfind("foo", 19), Some(16));
+        assert_eq!(gap_buffer.rfind("foo", 18), Some(5));
+        assert_eq!(gap_buffer.rfind("echo", 3), None);
+        assert_eq!(gap_buffer.find("foo", 100), None);
+        assert_eq!(gap_buffer.find("", 100), Some(19));
+        assert_eq!(gap_buffer.rfind("foo", 100), Some(16));
+    }
+
+    #[test]
+    fn replaces_ranges() {
+        let mut gap_buffer = with_gap("ls -l src", 0);
+        gap_buffer.replace_range(3..5, "-la");
+        assert_eq!(gap_buffer.get_text(), "ls -la src");
+        gap_buffer.replace_range(10..10, "/");
+        assert_eq!(gap_buffer.get_text(), "ls -la src/");
+        gap_buffer.replace_range(0..2, "");
+        assert_eq!(gap_buffer.get_text(), " -la src/");
+    }
+
+    #[test]
+    fn finds_lines_and_positions() {
+        let gap_buffer = with_gap("for f in *\ndo\n  échö $f\ndone", 14);
+        assert_eq!(gap_buffer.line_at(12), 11..13);
+        assert_eq!(gap_buffer.line_at(11), 11..13);
+        assert_eq!(gap_buffer.line_at(0), 0..10);
+        assert_eq!(gap_buffer.offset_to_position(20), (2, 5));
+        assert_eq!(gap_buffer.position_to_offset(2, 5), Some(20));
+        assert_eq!(gap_buffer.position_to_offset(1, 10), Some(13));
+        assert_eq!(gap_buffer.position_to_offset(3, 0), Some(26));
+        assert_eq!(gap_buffer.position_to_offset(4, 0), None);
+    }
+
+    #[derive(Debug, Clone)]
+    enum Operation {
+        Insert(usize, String),
+        DeleteBackwards(usize, usize),
+        DeleteForwards(usize, usize),
+        Clear,
+    }
+
+    fn operation() -> impl Strategy<Value = Operation> {
+        prop_oneof![
+            3 => (any::<usize>(), "\\PC{0,40}").prop_map(|(at, text)| Operation::Insert(at, text)),
+            1 => (any::<usize>(), 0..5usize).prop_map(|(at, n)| Operation::DeleteBackwards(at, n)),
+            1 => (any::<usize>(), 0..5usize).prop_map(|(at, n)| Operation::DeleteForwards(at, n)),
+            1 => Just(Operation::Clear),
+        ]
+    }
+
+    /// One of the char boundaries of `text`, picked with `at`
+    fn boundary(text: &str, at: usize) -> usize {
+        let boundaries: Vec<usize> = text
+            .char_indices()
+            .map(|(index, _)| index)
+            .chain([text.len()])
+            .collect();
+        boundaries[at % boundaries.len()]
+    }
+
+    proptest! {
+        #[test]
+        fn behaves_like_a_string(operations in prop::collection::vec(operation(), 0..50)) {
+            let mut gap_buffer = GapBuffer::new();
+            let mut model = String::new();
+            for operation in operations {
+                match operation {
+                    Operation::Insert(at, text) => {
+                        let at = boundary(&model, at);
+                        gap_buffer.move_gap_to_cursor(at);
+                        gap_buffer.insert(&text);
+                        model.insert_str(at, &text);
+                    }
+                    Operation::DeleteBackwards(at, nchars) => {
+                        let at = boundary(&model, at);
+                        gap_buffer.move_gap_to_cursor(at);
+                        gap_buffer.delete_backwards(nchars);
+                        let start = model[..at]
+                            .char_indices()
+                            .rev()
+                            .take(nchars)
+                            .last()
+                            .map_or(at, |(index, _)| index);
+                        model.replace_range(start..at, "");
+                    }
+                    Operation::DeleteForwards(at, nchars) => {
+                        let at = boundary(&model, at);
+                        gap_buffer.move_gap_to_cursor(at);
+                        gap_buffer.delete_forwards(nchars);
+                        let end = model[at..]
+                            .char_indices()
+                            .nth(nchars)
+                            .map_or(model.len(), |(index, _)| at + index);
+                        model.replace_range(at..end, "");
+                    }
+                    Operation::Clear => {
+                        gap_buffer.clear_buffer_text();
+                        model.clear();
+                    }
+                }
+                prop_assert_eq!(gap_buffer.get_text(), model.as_str());
+                prop_assert_eq!(gap_buffer.text_len(), model.len());
+            }
+        }
+
+        #[test]
+        fn reads_like_a_string(
+            text in "[ab\nñ]{0,30}",
+            gap in any::<usize>(),
+            start in any::<usize>(),
+            end in any::<usize>(),
+            pattern in "[ab\nñ]{1,3}",
+        ) {
+            let gap_buffer = with_gap(&text, boundary(&text, gap));
+            let (start, end) = {
+                let (a, b) = (boundary(&text, start), boundary(&text, end));
+                (a.min(b), a.max(b))
+            };
+            prop_assert_eq!(gap_buffer.slice(start..end), &text[start..end]);
+            let found = text[start..].find(pattern.as_str()).map(|index| start + index);
+            prop_assert_eq!(gap_buffer.find(&pattern, start), found);
+            prop_assert_eq!(gap_buffer.rfind(&pattern, end), text[..end].rfind(pattern.as_str()));
+            let (row, column) = gap_buffer.offset_to_position(start);
+            prop_assert_eq!(gap_buffer.position_to_offset(row, column), Some(start));
+            let line_start = text[..start].rfind('\n').map_or(0, |index| index + 1);
+            prop_assert_eq!(row, text[..start].matches('\n').count());
+            prop_assert_eq!(column, text[line_start..start].chars().count());
+        }
+
+        #[test]
+        fn searches_from_offsets_past_the_end(
+            text in "[ab\nñ]{0,30}",
+            gap in any::<usize>(),
+            past in 1..10usize,
+            pattern in "[ab\nñ]{0,3}",
+        ) {
+            let gap_buffer = with_gap(&text, boundary(&text, gap));
+            let offset = text.len() + past;
+            // only the empty pattern matches, at the end of the text
+            let found = pattern.is_empty().then_some(text.len());
+            prop_assert_eq!(gap_buffer.find(&pattern, offset), found);
+            prop_assert_eq!(gap_buffer.rfind(&pattern, offset), text.rfind(pattern.as_str()));
+        }
+    }
 }
@@ -0,0 +1,282 @@
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::matcher::{self, MatchMode};
+
+/// A command of the history file, saved as `timestamp;command;exit code;directory`. Lines saved
+/// before the directory was recorded have no directory. The command is the line as it was typed,
+/// with its newlines and backslashes escaped so that each entry takes one line of the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub command: String,
+    pub exit_code: i32,
+    pub cwd: Option<PathBuf>,
+}
+
+impl Entry {
+    /// Parses a line of the history file. Commands can contain `;`, so the fields after the
+    /// command are split from the end.
+    pub fn parse(line: &str) -> Option<Entry> {
+        let (_timestamp, rest) = line.split_once(';')?;
+        let (head, last) = rest.rsplit_once(';')?;
+        if let Ok(exit_code) = last.parse() {
+            return Some(Entry {
+                command: unescape(head),
+                exit_code,
+                cwd: None,
+            });
+        }
+        let (command, exit_code) = head.rsplit_once(';')?;
+        Some(Entry {
+            command: unescape(command),
+            exit_code: exit_code.parse().ok()?,
+            cwd: (!last.is_empty()).then(|| PathBuf::from(last)),
+        })
+    }
+
+    /// The line of the history file for the entry, run at `timestamp`
+    pub fn to_line(&self, timestamp: u128) -> String {
+        format!(
+            "{};{};{};{}",
+            timestamp,
+            escape(&self.command),
+            self.exit_code,
+            self.cwd.as_deref().unwrap_or(Path::new("")).display(),
+        )
+    }
+}
+
+fn escape(command: &str) -> String {
+    command.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Undoes `escape`. Other backslashes are kept, as in lines saved before commands were escaped.
+fn unescape(command: &str) -> String {
+    let mut unescaped = String::with_capacity(command.len());
+    let mut chars = command.chars();
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            unescaped.push(char);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('\\') => unescaped.push('\\'),
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+thread_local! {
+    /// Commands of the history file and the ones run since the shell started, oldest first
+    static ENTRIES: RefCell<Vec<Entry>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Reads the history file into memory. A missing file is not an error.
+pub fn load(histfile: &Path) -> io::Result<()> {
+    let contents = match fs::read_to_string(histfile) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error),
+    };
+    let entries = contents.lines().filter_map(Entry::parse).collect();
+    ENTRIES.with(|history| *history.borrow_mut() = entries);
+    Ok(())
+}
+
+pub fn push(entry: Entry) {
+    ENTRIES.with(|history| history.borrow_mut().push(entry));
+}
+
+/// Rest of the command that the line being typed most likely ends as, for autosuggestions
+pub fn suggest(prefix: &str) -> Option<String> {
+    let cwd = std::env::current_dir().ok();
+    ENTRIES.with(|history| {
+        let history = history.borrow();
+        let command = best_match(&history, prefix, cwd.as_deref())?;
+        Some(command[prefix.len()..].to_string())
+    })
+}
+
+/// The most recent command that starts with `prefix`, preferring the ones run in `cwd` and then
+/// the ones that succeeded
+fn best_match<'a>(entries: &'a [Entry], prefix: &str, cwd: Option<&Path>) -> Option<&'a str> {
+    entries
+        .iter()
+        .filter(|entry| entry.command.len() > prefix.len() && entry.command.starts_with(prefix))
+        // the last of the best entries is the most recent
+        .max_by_key(|entry| {
+            (
+                cwd.is_some() && entry.cwd.as_deref() == cwd,
+                entry.exit_code == 0,
+            )
+        })
+        .map(|entry| entry.command.as_str())
+}
+
+/// A command of the history that matched a search, with the byte offsets of the matched chars
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Found {
+    pub command: String,
+    pub positions: Vec<usize>,
+}
+
+/// Commands of the history that match `query`, each once. The most recent come first, and with
+/// fuzzy matching the best scoring ones before them.
+pub fn search(query: &str, mode: MatchMode) -> Vec<Found> {
+    ENTRIES.with(|history| matches(&history.borrow(), query, mode))
+}
+
+fn matches(entries: &[Entry], query: &str, mode: MatchMode) -> Vec<Found> {
+    let mut seen = HashSet::new();
+    let mut found: Vec<(i64, Found)> = entries
+        .iter()
+        .rev()
+        .filter(|entry| seen.insert(entry.command.as_str()))
+        .filter_map(|entry| {
+            let found = matcher::find(mode, query, &entry.command)?;
+            Some((
+                found.score,
+                Found {
+                    command: entry.command.clone(),
+                    positions: found.positions,
+                },
+            ))
+        })
+        .collect();
+    if mode == MatchMode::Fuzzy {
+        found.sort_by_key(|(score, _)| Reverse(*score));
+    }
+    found.into_iter().map(|(_, found)| found).collect()
+}
+
+/// Keeps only the last `size` commands of the history file. A missing file is not an error.
+pub fn trim(histfile: &Path, size: usize) -> io::Result<()> {
+    let contents = match fs::read_to_string(histfile) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error),
+    };
+    let lines: Vec<&str> = contents.lines().collect();
+    if lines.len() <= size {
+        return Ok(());
+    }
+    let mut trimmed = lines[lines.len() - size..].join("\n");
+    if !trimmed.is_empty() {
+        trimmed.push('\n');
+    }
+    fs::write(histfile, trimmed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{best_match, matches, trim, Entry};
+    use crate::matcher::MatchMode;
+
+    #[test]
+    fn keeps_last_commands() {
+        let path = std::env::temp_dir().join("shush_trim_hist_test");
+        std::fs::write(&path, "1;a;true\n2;b;true\n3;c;false\n").unwrap();
+        trim(&path, 2).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "2;b;true\n3;c;false\n"
+        );
+    }
+
+    #[test]
+    fn parses_lines_with_and_without_directory() {
+        let entry = Entry::parse("1;a; b;0;/tmp/x").unwrap();
+        assert_eq!(entry.command, "a; b");
+        assert_eq!(entry.exit_code, 0);
+        assert_eq!(entry.cwd, Some(PathBuf::from("/tmp/x")));
+        let entry = Entry::parse("2;cd; ls;1").unwrap();
+        assert_eq!(entry.command, "cd; ls");
+        assert_eq!(entry.exit_code, 1);
+        assert_eq!(entry.cwd, None);
+        assert_eq!(Entry::parse("garbage"), None);
+    }
+
+    #[test]
+    fn saves_lines_as_typed_one_per_entry() {
+        let entry = Entry {
+            command: "for f in *.rs\ndo echo \"$f\\n\"; done".to_string(),
+            exit_code: 0,
+            cwd: Some(PathBuf::from("/src")),
+        };
+        let line = entry.to_line(42);
+        assert_eq!(line, "42;for f in *.rs\\ndo echo \"$f\\\\n\"; done;0;/src");
+        assert_eq!(Entry::parse(&line), Some(entry));
+        let entry = Entry::parse("1;printf 'a\\tb';0;").unwrap();
+        assert_eq!(entry.command, "printf 'a\\tb'");
+    }
+
+    #[test]
+    fn prefers_commands_of_the_directory_that_succeeded() {
+        let entry = |command: &str, exit_code, cwd: &str| Entry {
+            command: command.to_string(),
+            exit_code,
+            cwd: Some(PathBuf::from(cwd)),
+        };
+        let entries = [
+            entry("make test", 0, "/src"),
+            entry("make check", 0, "/src"),
+            entry("make install", 0, "/home"),
+            entry("make bench", 2, "/src"),
+        ];
+        let src = Some(Path::new("/src"));
+        assert_eq!(best_match(&entries, "make", src), Some("make check"));
+        assert_eq!(best_match(&entries, "make b", src), Some("make bench"));
+        let home = Some(Path::new("/home"));
+        assert_eq!(best_match(&entries, "make", home), Some("make install"));
+        assert_eq!(best_match(&entries, "make", None), Some("make install"));
+        assert_eq!(best_match(&entries, "make check", src), None);
+    }
+
+    #[test]
+    fn searches_the_newest_commands_first() {
+        let entry = |command: &str| Entry {
+            command: command.to_string(),
+            exit_code: 0,
+            cwd: None,
+        };
+        let entries = [
+            entry("git commit"),
+            entry("cargo check"),
+            entry("git checkout main"),
+            entry("git commit"),
+        ];
+        let commands = |query, mode| -> Vec<String> {
+            matches(&entries, query, mode)
+                .into_iter()
+                .map(|found| found.command)
+                .collect()
+        };
+        assert_eq!(
+            commands("git", MatchMode::Prefix),
+            ["git commit", "git checkout main"]
+        );
+        assert_eq!(
+            commands("check", MatchMode::Substring),
+            ["git checkout main", "cargo check"]
+        );
+        assert_eq!(
+            commands("gcom", MatchMode::Fuzzy),
+            ["git commit", "git checkout main"]
+        );
+        let found = matches(&entries, "ckm", MatchMode::Fuzzy);
+        assert_eq!(found[0].command, "git checkout main");
+        assert_eq!(found[0].positions, [7, 8, 13]);
+    }
+}
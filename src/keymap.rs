@@ -0,0 +1,675 @@
+//! Key bindings, from sequences of key presses to named actions.
+//!
+//! Bindings are written like in readline's `inputrc`, in the `inputrc` file of the config
+//! directory or with the `bind` builtin:
+//!
+//! ```text
+//! # comments start with `#`
+//! "\C-a": beginning-of-line
+//! "\e[1;5D": backward-word
+//! Meta-Rubout: backward-kill-word
+//! set keymap vi-insert
+//! "\C-l": clear-screen
+//! ```
+//!
+//! Quoted sequences accept `\C-x` for Ctrl plus a key, `\M-x` for Alt plus a key, `\e` for Esc and
+//! the usual C escapes. Unquoted key names accept `Control-` or `C-`, `Meta-` or `M-` and the
+//! names `Rubout`, `Escape`, `Return`, `Newline`, `Tab` and `Space`. There is a keymap for each
+//! editing mode, `emacs` and `vi-insert`, selected with `set keymap <name>`.
+//!
+//! Sequences are decoded like the bytes read from the terminal, so `"\e[H"` and `"\eOH"` both
+//! bind the Home key and `"\e[1;5D"` binds Ctrl-Left.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use crate::config::RcError;
+use crate::editor::EditAction;
+use crate::keys::{self, Key, KeyPress};
+use crate::shell_options::{self, EditingMode};
+
+/// What a key sequence does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Edit(EditAction),
+    /// Inserts the typed text
+    SelfInsert,
+    /// Executes the line
+    AcceptLine,
+    Complete,
+    /// Searches the history backwards as the query is typed
+    ReverseSearchHistory,
+    /// Ends the shell on an empty line, deletes forward otherwise
+    DeleteCharOrEof,
+}
+
+/// Names used in bindings, the same as readline's where there is one
+const FUNCTIONS: &[(&str, Action)] = &[
+    ("backward-char", Action::Edit(EditAction::BackwardChar)),
+    ("forward-char", Action::Edit(EditAction::ForwardChar)),
+    ("backward-word", Action::Edit(EditAction::BackwardWord)),
+    ("forward-word", Action::Edit(EditAction::ForwardWord)),
+    (
+        "beginning-of-line",
+        Action::Edit(EditAction::BeginningOfLine),
+    ),
+    ("end-of-line", Action::Edit(EditAction::EndOfLine)),
+    ("previous-line", Action::Edit(EditAction::PreviousLine)),
+    ("next-line", Action::Edit(EditAction::NextLine)),
+    (
+        "backward-delete-char",
+        Action::Edit(EditAction::DeleteBackwardChar),
+    ),
+    ("delete-char", Action::Edit(EditAction::DeleteForwardChar)),
+    ("kill-line", Action::Edit(EditAction::KillLine)),
+    (
+        "unix-line-discard",
+        Action::Edit(EditAction::UnixLineDiscard),
+    ),
+    ("unix-word-rubout", Action::Edit(EditAction::UnixWordRubout)),
+    (
+        "backward-kill-word",
+        Action::Edit(EditAction::BackwardKillWord),
+    ),
+    ("kill-word", Action::Edit(EditAction::KillWord)),
+    ("yank", Action::Edit(EditAction::Yank)),
+    ("yank-pop", Action::Edit(EditAction::YankPop)),
+    ("transpose-chars", Action::Edit(EditAction::TransposeChars)),
+    ("clear-screen", Action::Edit(EditAction::ClearScreen)),
+    ("undo", Action::Edit(EditAction::Undo)),
+    ("redo", Action::Edit(EditAction::Redo)),
+    ("self-insert", Action::SelfInsert),
+    ("accept-line", Action::AcceptLine),
+    ("complete", Action::Complete),
+    ("reverse-search-history", Action::ReverseSearchHistory),
+    ("delete-char-or-eof", Action::DeleteCharOrEof),
+];
+
+/// Bindings of both editing modes
+const DEFAULT_BINDINGS: &str = r#"
+"\C-a": beginning-of-line
+"\C-b": backward-char
+"\C-d": delete-char-or-eof
+"\C-e": end-of-line
+"\C-f": forward-char
+"\C-h": backward-delete-char
+"\C-i": complete
+"\C-j": accept-line
+"\C-k": kill-line
+"\C-l": clear-screen
+"\C-m": accept-line
+"\C-r": reverse-search-history
+"\C-t": transpose-chars
+"\C-u": unix-line-discard
+"\C-w": unix-word-rubout
+"\C-x\C-u": undo
+"\C-y": yank
+"\C-_": undo
+"\C-?": backward-delete-char
+"\e[A": previous-line
+"\e[B": next-line
+"\e[C": forward-char
+"\e[D": backward-char
+"\e[1;5C": forward-word
+"\e[1;5D": backward-word
+"\e[H": beginning-of-line
+"\e[F": end-of-line
+"\e[1~": beginning-of-line
+"\e[3~": delete-char
+"\e[4~": end-of-line
+"\e[7~": beginning-of-line
+"\e[8~": end-of-line
+"\eOA": previous-line
+"\eOB": next-line
+"\eOC": forward-char
+"\eOD": backward-char
+"\eOH": beginning-of-line
+"\eOF": end-of-line
+"#;
+
+/// Alt bindings, left out of vi mode where Esc switches to normal mode
+const EMACS_BINDINGS: &str = r#"
+"\eb": backward-word
+"\ef": forward-word
+"\ed": kill-word
+"\ey": yank-pop
+"\e/": redo
+"\e\C-?": backward-kill-word
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeymapName {
+    Emacs,
+    ViInsert,
+}
+
+impl KeymapName {
+    pub fn parse(name: &str) -> anyhow::Result<KeymapName> {
+        match name {
+            "emacs" => Ok(KeymapName::Emacs),
+            "vi" | "vi-insert" => Ok(KeymapName::ViInsert),
+            _ => Err(anyhow!("{}: unknown keymap", name)),
+        }
+    }
+
+    /// Keymap used by the current editing mode
+    pub fn current() -> KeymapName {
+        match shell_options::editing_mode() {
+            EditingMode::Emacs => KeymapName::Emacs,
+            EditingMode::Vi => KeymapName::ViInsert,
+        }
+    }
+}
+
+/// Result of looking up the keys pressed so far
+#[derive(Debug, PartialEq, Eq)]
+pub enum Lookup {
+    Bound(Action),
+    /// The keys start a longer binding, more have to be read
+    Prefix,
+    Unbound,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Keymap {
+    bindings: HashMap<Vec<KeyPress>, Action>,
+}
+
+impl Keymap {
+    fn with_bindings(text: &str) -> Keymap {
+        let mut keymap = Keymap::default();
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            let (sequence, action) = parse_binding(line).expect("default bindings are valid");
+            keymap.bind(sequence, action);
+        }
+        keymap
+    }
+
+    pub fn bind(&mut self, sequence: Vec<KeyPress>, action: Action) {
+        self.bindings.insert(sequence, action);
+    }
+
+    /// Returns whether the sequence was bound
+    pub fn unbind(&mut self, sequence: &[KeyPress]) -> bool {
+        self.bindings.remove(sequence).is_some()
+    }
+
+    /// A sequence that starts a longer binding waits for more keys, even if it is bound itself
+    pub fn lookup(&self, sequence: &[KeyPress]) -> Lookup {
+        let prefix = self
+            .bindings
+            .keys()
+            .any(|bound| bound.len() > sequence.len() && bound.starts_with(sequence));
+        if prefix {
+            return Lookup::Prefix;
+        }
+        match self.bindings.get(sequence) {
+            Some(&action) => Lookup::Bound(action),
+            None => Lookup::Unbound,
+        }
+    }
+
+    /// Bindings sorted by sequence
+    pub fn bindings(&self) -> Vec<(&[KeyPress], Action)> {
+        let mut bindings: Vec<_> = self
+            .bindings
+            .iter()
+            .map(|(sequence, &action)| (sequence.as_slice(), action))
+            .collect();
+        bindings.sort_by_cached_key(|(sequence, _)| sequence_to_string(sequence));
+        bindings
+    }
+}
+
+impl Action {
+    pub fn parse(name: &str) -> anyhow::Result<Action> {
+        FUNCTIONS
+            .iter()
+            .find(|(function, _)| *function == name)
+            .map(|&(_, action)| action)
+            .ok_or(anyhow!("{}: unknown function name", name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        FUNCTIONS
+            .iter()
+            .find(|(_, action)| action == self)
+            .map_or("", |(name, _)| name)
+    }
+}
+
+/// Names of all the functions that can be bound
+pub fn function_names() -> impl Iterator<Item = &'static str> {
+    FUNCTIONS.iter().map(|(name, _)| *name)
+}
+
+#[derive(Debug, Clone)]
+struct Keymaps {
+    emacs: Keymap,
+    vi_insert: Keymap,
+}
+
+impl Default for Keymaps {
+    fn default() -> Self {
+        Self {
+            emacs: Keymap::with_bindings(&format!("{}{}", DEFAULT_BINDINGS, EMACS_BINDINGS)),
+            vi_insert: Keymap::with_bindings(DEFAULT_BINDINGS),
+        }
+    }
+}
+
+impl Keymaps {
+    fn get_mut(&mut self, name: KeymapName) -> &mut Keymap {
+        match name {
+            KeymapName::Emacs => &mut self.emacs,
+            KeymapName::ViInsert => &mut self.vi_insert,
+        }
+    }
+}
+
+thread_local! {
+    static KEYMAPS: RefCell<Keymaps> = RefCell::new(Keymaps::default());
+}
+
+/// Runs `f` with one of the keymaps of the shell
+pub fn with_keymap<T>(name: KeymapName, f: impl FnOnce(&mut Keymap) -> T) -> T {
+    KEYMAPS.with(|keymaps| f(keymaps.borrow_mut().get_mut(name)))
+}
+
+/// Looks up a sequence in the keymap of the current editing mode
+pub fn lookup(sequence: &[KeyPress]) -> Lookup {
+    with_keymap(KeymapName::current(), |keymap| keymap.lookup(sequence))
+}
+
+/// Reads a file of bindings. Lines that fail do not stop the rest of the file from being read,
+/// all errors are returned at the end.
+pub fn read_file(path: &Path) -> anyhow::Result<Vec<RcError>> {
+    let contents =
+        fs::read_to_string(path).map_err(|error| anyhow!("{}: {}", path.display(), error))?;
+    let mut keymap = KeymapName::current();
+    let mut errors = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        if let Err(error) = read_line(line, &mut keymap) {
+            errors.push(RcError {
+                line: index + 1,
+                error,
+            });
+        }
+    }
+    Ok(errors)
+}
+
+/// Applies a line of an `inputrc` file, `set keymap <name>` changes the keymap that the next
+/// bindings go to
+pub fn read_line(line: &str, keymap: &mut KeymapName) -> anyhow::Result<()> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(());
+    }
+    if let Some(variable) = line.strip_prefix("set ") {
+        let mut words = variable.split_whitespace();
+        return match (words.next(), words.next()) {
+            (Some("keymap"), Some(name)) => {
+                *keymap = KeymapName::parse(name)?;
+                Ok(())
+            }
+            (Some("editing-mode"), Some(mode)) => shell_options::set(mode, true),
+            _ => Err(anyhow!("{}: unknown variable", variable.trim())),
+        };
+    }
+    let (sequence, action) = parse_binding(line)?;
+    with_keymap(*keymap, |keymap| keymap.bind(sequence, action));
+    Ok(())
+}
+
+/// Parses `"sequence": function` or `Key-Name: function`
+pub fn parse_binding(line: &str) -> anyhow::Result<(Vec<KeyPress>, Action)> {
+    let (sequence, function) = match line.strip_prefix('"') {
+        Some(rest) => {
+            let end = closing_quote(rest).ok_or(anyhow!("missing closing quote"))?;
+            let function = rest[end + 1..]
+                .trim_start()
+                .strip_prefix(':')
+                .ok_or(anyhow!("missing `:` after the key sequence"))?;
+            (parse_sequence(&rest[..end])?, function)
+        }
+        None => {
+            let (name, function) = line
+                .split_once(':')
+                .ok_or(anyhow!("missing `:` after the key name"))?;
+            (keys::decode(&parse_key_name(name.trim())?), function)
+        }
+    };
+    let function = function.trim();
+    if function.starts_with('"') {
+        return Err(anyhow!("macros are not supported"));
+    }
+    Ok((sequence, Action::parse(function)?))
+}
+
+/// Binds the keys of the `key_bindings` tables of the config, one for each keymap. A key with a
+/// backslash is a sequence written as between the quotes of `inputrc`, like `\C-x\C-e`, others
+/// are key names like `Meta-Rubout`. Bindings that fail do not stop the others.
+pub fn bind_all(bindings: &BTreeMap<String, BTreeMap<String, String>>) -> Vec<anyhow::Error> {
+    let mut errors = Vec::new();
+    for (name, bindings) in bindings {
+        let keymap = match KeymapName::parse(name) {
+            Ok(keymap) => keymap,
+            Err(error) => {
+                errors.push(error);
+                continue;
+            }
+        };
+        for (keys, function) in bindings {
+            let sequence = if keys.contains('\\') {
+                parse_sequence(keys)
+            } else {
+                parse_key_name(keys).map(|bytes| keys::decode(&bytes))
+            };
+            match sequence.and_then(|sequence| Ok((sequence, Action::parse(function)?))) {
+                Ok((sequence, action)) => {
+                    with_keymap(keymap, |keymap| keymap.bind(sequence, action))
+                }
+                Err(error) => errors.push(anyhow!("{}: {}", keys, error)),
+            }
+        }
+    }
+    errors
+}
+
+/// Byte offset of the first quote not escaped with a backslash
+fn closing_quote(text: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (index, char) in text.char_indices() {
+        match char {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return Some(index),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+/// Parses the text between the quotes of a key sequence
+pub fn parse_sequence(text: &str) -> anyhow::Result<Vec<KeyPress>> {
+    let mut chars = text.chars().peekable();
+    let mut bytes = Vec::new();
+    while chars.peek().is_some() {
+        bytes.extend(parse_key(&mut chars)?);
+    }
+    let sequence = keys::decode(&bytes);
+    if sequence.is_empty() {
+        return Err(anyhow!("empty key sequence"));
+    }
+    if sequence.iter().any(|key| matches!(key.key, Key::Paste(_))) {
+        return Err(anyhow!("pasted text can't be bound"));
+    }
+    Ok(sequence)
+}
+
+/// Parses a single key of a sequence, which can be a `\C-` or `\M-` prefix followed by a key
+fn parse_key(chars: &mut std::iter::Peekable<std::str::Chars>) -> anyhow::Result<Vec<u8>> {
+    let Some(char) = chars.next() else {
+        return Err(anyhow!("missing key after a modifier"));
+    };
+    if char != '\\' {
+        return Ok(char.to_string().into_bytes());
+    }
+    let Some(escape) = chars.next() else {
+        return Err(anyhow!("sequence ends with a backslash"));
+    };
+    let byte = match escape {
+        'C' | 'M' if chars.peek() == Some(&'-') => {
+            chars.next();
+            let key = parse_key(chars)?;
+            return if escape == 'C' {
+                control(&key).map(|byte| vec![byte])
+            } else {
+                Ok([vec![0x1B], key].concat())
+            };
+        }
+        'e' => 0x1B,
+        'a' => 0x07,
+        'b' => 0x08,
+        'd' => 0x7F,
+        'f' => 0x0C,
+        'n' => b'\n',
+        'r' => b'\r',
+        't' => b'\t',
+        'v' => 0x0B,
+        '0'..='7' => {
+            let mut value = escape.to_digit(8).unwrap_or(0);
+            for _ in 0..2 {
+                match chars.peek().and_then(|char| char.to_digit(8)) {
+                    Some(digit) => {
+                        value = value * 8 + digit;
+                        chars.next();
+                    }
+                    None => break,
+                }
+            }
+            u8::try_from(value).map_err(|_| anyhow!("\\{:o}: octal escape out of range", value))?
+        }
+        'x' => {
+            let mut value = 0;
+            for _ in 0..2 {
+                match chars.peek().and_then(|char| char.to_digit(16)) {
+                    Some(digit) => {
+                        value = value * 16 + digit;
+                        chars.next();
+                    }
+                    None => break,
+                }
+            }
+            value as u8
+        }
+        other => return Ok(other.to_string().into_bytes()),
+    };
+    Ok(vec![byte])
+}
+
+/// Byte sent by Ctrl plus a key, `\C-?` is Delete
+fn control(key: &[u8]) -> anyhow::Result<u8> {
+    match key {
+        [b'?'] => Ok(0x7F),
+        [byte] if byte.is_ascii_alphabetic() || (b'@'..=b'_').contains(byte) => {
+            Ok(byte.to_ascii_uppercase() & 0x1F)
+        }
+        _ => Err(anyhow!("Ctrl only works with letters and `@[\\]^_?`")),
+    }
+}
+
+/// Parses an unquoted key name like `Control-u` or `Meta-Rubout`
+fn parse_key_name(name: &str) -> anyhow::Result<Vec<u8>> {
+    let lowercase = name.to_lowercase();
+    for (prefix, ctrl) in [
+        ("control-", true),
+        ("c-", true),
+        ("meta-", false),
+        ("m-", false),
+    ] {
+        if lowercase.starts_with(prefix) {
+            let key = parse_key_name(&name[prefix.len()..])?;
+            return if ctrl {
+                control(&key).map(|byte| vec![byte])
+            } else {
+                Ok([vec![0x1B], key].concat())
+            };
+        }
+    }
+    let byte = match lowercase.as_str() {
+        "rubout" | "del" => 0x7F,
+        "escape" | "esc" => 0x1B,
+        "return" | "ret" => b'\r',
+        "newline" | "lfd" => b'\n',
+        "tab" => b'\t',
+        "space" | "spc" => b' ',
+        _ if name.chars().count() == 1 => return Ok(name.as_bytes().to_vec()),
+        _ => return Err(anyhow!("{}: unknown key name", name)),
+    };
+    Ok(vec![byte])
+}
+
+/// Writes a sequence back in the quoted notation of bindings
+pub fn sequence_to_string(sequence: &[KeyPress]) -> String {
+    let bytes: Vec<u8> = sequence.iter().flat_map(keys::encode).collect();
+    let mut text = String::new();
+    for char in String::from_utf8_lossy(&bytes).chars() {
+        match char {
+            '\x1B' => text.push_str("\\e"),
+            '\x7F' => text.push_str("\\C-?"),
+            '\x01'..='\x1A' => {
+                text.push_str("\\C-");
+                text.push((char as u8 + b'`') as char);
+            }
+            '\x00'..='\x1F' => {
+                text.push_str("\\C-");
+                match (char as u8 + b'@') as char {
+                    '\\' => text.push_str("\\\\"),
+                    char => text.push(char),
+                }
+            }
+            '"' | '\\' => {
+                text.push('\\');
+                text.push(char);
+            }
+            _ => text.push(char),
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{
+        bind_all, parse_binding, parse_sequence, read_line, sequence_to_string, with_keymap,
+        Action, Keymap, KeymapName, Lookup,
+    };
+    use crate::editor::EditAction;
+    use crate::keys::{decode, Key, KeyPress};
+
+    #[test]
+    fn parses_sequences() {
+        let sequence = |text| parse_sequence(text).unwrap();
+        assert_eq!(sequence(r"\C-a"), vec![KeyPress::ctrl('a')]);
+        assert_eq!(sequence(r"\C-?"), vec![KeyPress::new(Key::Backspace)]);
+        assert_eq!(sequence(r"\M-f"), vec![KeyPress::alt(Key::Char('f'))]);
+        assert_eq!(sequence(r"\ef"), sequence(r"\M-f"));
+        assert_eq!(sequence(r"\M-\C-h"), decode(b"\x1b\x08"));
+        assert_eq!(sequence(r"\e[3~"), vec![KeyPress::new(Key::Delete)]);
+        assert_eq!(sequence(r"\eOH"), sequence(r"\e[1~"));
+        assert_eq!(sequence(r"\033[A\x7f\t"), decode(b"\x1b[A\x7f\t"));
+        assert_eq!(sequence(r#"\"é"#), decode("\"é".as_bytes()));
+        assert_eq!(sequence(r"\C-x\C-e").len(), 2);
+        assert!(parse_sequence(r"\C-").is_err());
+        assert!(parse_sequence(r"\C-1").is_err());
+        assert!(parse_sequence(r"\e[200~ls\e[201~").is_err());
+        assert!(parse_sequence("").is_err());
+    }
+
+    #[test]
+    fn parses_bindings() {
+        let (sequence, action) = parse_binding(r#""\C-x\C-e": end-of-line"#).unwrap();
+        assert_eq!(sequence, vec![KeyPress::ctrl('x'), KeyPress::ctrl('e')]);
+        assert_eq!(action, Action::Edit(EditAction::EndOfLine));
+        let (sequence, _) = parse_binding(r#""a\":b" : self-insert"#).unwrap();
+        assert_eq!(sequence, decode(b"a\":b"));
+        let (sequence, action) = parse_binding("Meta-Rubout: backward-kill-word").unwrap();
+        assert_eq!(sequence, vec![KeyPress::alt(Key::Backspace)]);
+        assert_eq!(action, Action::Edit(EditAction::BackwardKillWord));
+        assert_eq!(
+            parse_binding("C-u: yank").unwrap().0,
+            vec![KeyPress::ctrl('u')]
+        );
+
+        assert!(parse_binding(r#""\C-a" beginning-of-line"#).is_err());
+        assert!(parse_binding(r#""\C-a: beginning-of-line"#).is_err());
+        assert!(parse_binding(r#""\C-a": nope"#).is_err());
+        assert!(parse_binding(r#""\C-a": "macro""#).is_err());
+        assert!(parse_binding("Hyper-a: yank").is_err());
+    }
+
+    #[test]
+    fn prints_sequences() {
+        for sequence in [r"\C-a", r"\e[3~", r"\e\C-?", r"\e[1;5D", r#"\"\\é"#] {
+            let keys = parse_sequence(sequence).unwrap();
+            assert_eq!(sequence_to_string(&keys), sequence);
+        }
+    }
+
+    #[test]
+    fn looks_up_prefixes() {
+        let mut keymap = Keymap::default();
+        let delete = KeyPress::new(Key::Delete);
+        keymap.bind(
+            vec![delete.clone()],
+            Action::Edit(EditAction::DeleteForwardChar),
+        );
+        keymap.bind(vec![KeyPress::ctrl('x')], Action::AcceptLine);
+        keymap.bind(
+            vec![KeyPress::ctrl('x'), KeyPress::ctrl('u')],
+            Action::Complete,
+        );
+        assert_eq!(
+            keymap.lookup(&[delete]),
+            Lookup::Bound(Action::Edit(EditAction::DeleteForwardChar))
+        );
+        assert_eq!(keymap.lookup(&[KeyPress::new(Key::End)]), Lookup::Unbound);
+        // a bound sequence waits when a longer one starts with it
+        assert_eq!(keymap.lookup(&[KeyPress::ctrl('x')]), Lookup::Prefix);
+        assert!(keymap.unbind(&[KeyPress::ctrl('x'), KeyPress::ctrl('u')]));
+        assert_eq!(
+            keymap.lookup(&[KeyPress::ctrl('x')]),
+            Lookup::Bound(Action::AcceptLine)
+        );
+    }
+
+    #[test]
+    fn reads_keymap_lines() {
+        let mut keymap = KeymapName::Emacs;
+        read_line("  # comment", &mut keymap).unwrap();
+        read_line("set keymap vi-insert", &mut keymap).unwrap();
+        assert_eq!(keymap, KeymapName::ViInsert);
+        assert!(read_line("set keymap nope", &mut keymap).is_err());
+        assert!(read_line("set bell-style none", &mut keymap).is_err());
+    }
+
+    #[test]
+    fn binds_keys_of_the_config() {
+        let table = |bindings: &[(&str, &str)]| -> BTreeMap<String, String> {
+            bindings
+                .iter()
+                .map(|(keys, function)| (keys.to_string(), function.to_string()))
+                .collect()
+        };
+        let bindings = BTreeMap::from([
+            (
+                "emacs".to_string(),
+                table(&[
+                    (r"\C-x\C-t", "end-of-line"),
+                    ("Meta-x", "beginning-of-line"),
+                    ("Meta-y", "no-such-function"),
+                ]),
+            ),
+            ("vim".to_string(), table(&[("x", "undo")])),
+        ]);
+        assert_eq!(bind_all(&bindings).len(), 2);
+        let lookup = |sequence: &[KeyPress]| {
+            with_keymap(KeymapName::Emacs, |keymap| keymap.lookup(sequence))
+        };
+        assert_eq!(
+            lookup(&[KeyPress::ctrl('x'), KeyPress::ctrl('t')]),
+            Lookup::Bound(Action::Edit(EditAction::EndOfLine))
+        );
+        assert_eq!(
+            lookup(&[KeyPress::alt(Key::Char('x'))]),
+            Lookup::Bound(Action::Edit(EditAction::BeginningOfLine))
+        );
+    }
+}
@@ -0,0 +1,817 @@
+//! Decoding of the bytes that terminals send for key presses.
+//!
+//! Handles the sequences of VT and xterm compatible terminals:
+//!
+//! - UTF-8 text, and control bytes as Ctrl plus a key (`0x01` is `Ctrl-a`)
+//! - `ESC` followed by a key as Alt plus the key
+//! - CSI sequences, `ESC [` with parameters and a final byte, like `ESC [ 3 ~` for Delete or
+//!   `ESC [ 1 ; 5 D` for Ctrl-Left. The modifier parameter is 1 plus a bit mask of Shift (1),
+//!   Alt (2) and Ctrl (4). `CSI u` and xterm's `CSI 27 ; m ; code ~` report keys with modifiers
+//!   that have no byte of their own.
+//! - SS3 sequences, `ESC O` and a letter, sent for arrows and F1 to F4 in application mode
+//! - bracketed paste, the text between `ESC [ 200 ~` and `ESC [ 201 ~`
+//!
+//! A lone `ESC` can't be told apart from the start of a sequence, so the decoder waits for more
+//! bytes until the caller decides they are not coming and calls `Decoder::timeout`. A paste whose
+//! end never comes is ended the same way, after a longer wait.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// How long to wait for the rest of a sequence after `ESC` before taking it as the Esc key
+pub const ESC_TIMEOUT: Duration = Duration::from_millis(50);
+/// How long to wait for more of a paste before ending it, in case its end marker got lost
+pub const PASTE_TIMEOUT: Duration = Duration::from_secs(1);
+
+const ESC: u8 = 0x1B;
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+/// Longest CSI sequence accepted, longer ones are dropped
+const MAX_SEQUENCE_LEN: usize = 32;
+/// Bytes of a paste kept before they are handed over as a paste of their own
+const MAX_PASTE_LEN: usize = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    F(u8),
+    /// Text pasted in the terminal with bracketed paste on
+    Paste(String),
+    /// The terminal changed size. It is not read from the input but sent by `IO` on `SIGWINCH`.
+    Resize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers {
+        shift: false,
+        alt: false,
+        ctrl: false,
+    };
+
+    /// Modifiers from the parameter of a CSI sequence
+    fn from_parameter(parameter: u32) -> Modifiers {
+        let mask = parameter.saturating_sub(1);
+        Modifiers {
+            shift: mask & 1 != 0,
+            // Meta (8) is taken as Alt, like terminals that send `ESC` for both
+            alt: mask & (2 | 8) != 0,
+            ctrl: mask & 4 != 0,
+        }
+    }
+
+    fn parameter(&self) -> u32 {
+        1 + self.shift as u32 + 2 * self.alt as u32 + 4 * self.ctrl as u32
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Modifiers::NONE
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyPress {
+    pub key: Key,
+    pub modifiers: Modifiers,
+}
+
+impl KeyPress {
+    pub fn new(key: Key) -> KeyPress {
+        KeyPress {
+            key,
+            modifiers: Modifiers::NONE,
+        }
+    }
+
+    pub fn ctrl(char: char) -> KeyPress {
+        KeyPress {
+            key: Key::Char(char),
+            modifiers: Modifiers {
+                ctrl: true,
+                ..Modifiers::NONE
+            },
+        }
+    }
+
+    pub fn alt(key: Key) -> KeyPress {
+        KeyPress {
+            key,
+            modifiers: Modifiers {
+                alt: true,
+                ..Modifiers::NONE
+            },
+        }
+    }
+
+    /// The char typed with this key, if it inserts one
+    pub fn text(&self) -> Option<char> {
+        match self.key {
+            Key::Char(char) if !self.modifiers.ctrl && !self.modifiers.alt => Some(char),
+            _ => None,
+        }
+    }
+}
+
+/// Turns bytes read from the terminal into key presses
+#[derive(Debug, Default)]
+pub struct Decoder {
+    /// Bytes of a key that is not complete yet
+    pending: Vec<u8>,
+    /// Text pasted so far, while inside a bracketed paste
+    paste: Option<Vec<u8>>,
+    keys: VecDeque<KeyPress>,
+}
+
+/// What the start of the pending bytes holds
+#[derive(Debug, PartialEq, Eq)]
+enum Parse {
+    Key(KeyPress, usize),
+    /// An unknown or invalid sequence to drop
+    Skip(usize),
+    PasteStart(usize),
+    Incomplete,
+}
+
+impl Decoder {
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if let Some(paste) = &mut self.paste {
+                paste.push(byte);
+                if paste.ends_with(PASTE_END) {
+                    paste.truncate(paste.len() - PASTE_END.len());
+                    self.end_paste();
+                } else if paste.len() >= MAX_PASTE_LEN {
+                    self.split_paste();
+                }
+                continue;
+            }
+            self.pending.push(byte);
+            self.parse_pending(false);
+        }
+    }
+
+    pub fn next_key(&mut self) -> Option<KeyPress> {
+        self.keys.pop_front()
+    }
+
+    /// How long to wait for more bytes before calling `timeout`: `ESC_TIMEOUT` when the bytes read
+    /// so far could still become a longer key, `PASTE_TIMEOUT` inside a paste, and no limit
+    /// otherwise
+    pub fn timeout_after(&self) -> Option<Duration> {
+        if self.paste.is_some() {
+            Some(PASTE_TIMEOUT)
+        } else {
+            (!self.pending.is_empty()).then_some(ESC_TIMEOUT)
+        }
+    }
+
+    /// Decides that no more bytes are coming for the pending key, so a lone `ESC` is the Esc key
+    /// and `ESC` followed by the start of a sequence is Alt plus a key. A paste ends with the
+    /// text received.
+    pub fn timeout(&mut self) {
+        if self.paste.is_some() {
+            self.end_paste();
+        }
+        self.parse_pending(true);
+    }
+
+    fn end_paste(&mut self) {
+        if let Some(paste) = self.paste.take() {
+            let text = String::from_utf8_lossy(&paste).to_string();
+            self.keys.push_back(KeyPress::new(Key::Paste(text)));
+        }
+    }
+
+    /// Hands over the start of a long paste, keeping the bytes that could begin the end marker
+    /// or be part of a UTF-8 char in the paste
+    fn split_paste(&mut self) {
+        let Some(paste) = &mut self.paste else {
+            return;
+        };
+        let mut split = paste.len() - (PASTE_END.len() - 1);
+        while split > 0 && paste[split] & 0xC0 == 0x80 {
+            split -= 1;
+        }
+        let rest = paste.split_off(split);
+        self.end_paste();
+        self.paste = Some(rest);
+    }
+
+    fn parse_pending(&mut self, timed_out: bool) {
+        while !self.pending.is_empty() {
+            let consumed = match parse(&self.pending) {
+                Parse::Key(key, len) => {
+                    self.keys.push_back(key);
+                    len
+                }
+                Parse::Skip(len) => len,
+                Parse::PasteStart(len) => {
+                    self.paste = Some(Vec::new());
+                    len
+                }
+                Parse::Incomplete if !timed_out => break,
+                Parse::Incomplete => match self.pending.as_slice() {
+                    [ESC, byte, ..] if byte.is_ascii() && *byte != ESC => {
+                        self.keys.push_back(KeyPress::alt(Key::Char(*byte as char)));
+                        2
+                    }
+                    [ESC, ..] => {
+                        self.keys.push_back(KeyPress::new(Key::Escape));
+                        1
+                    }
+                    // the rest of a UTF-8 char never came
+                    _ => 1,
+                },
+            };
+            self.pending.drain(..consumed);
+        }
+    }
+}
+
+/// Decodes a complete sequence of bytes, as if the user stopped typing after them
+pub fn decode(bytes: &[u8]) -> Vec<KeyPress> {
+    let mut decoder = Decoder::default();
+    decoder.feed(bytes);
+    decoder.timeout();
+    decoder.keys.into()
+}
+
+fn parse(bytes: &[u8]) -> Parse {
+    match bytes {
+        [] => Parse::Incomplete,
+        [ESC] => Parse::Incomplete,
+        [ESC, b'[', rest @ ..] => parse_csi(rest),
+        [ESC, b'O'] => Parse::Incomplete,
+        [ESC, b'O', byte, ..] => match ss3_key(*byte) {
+            Some(key) => Parse::Key(KeyPress::new(key), 3),
+            None => Parse::Skip(3),
+        },
+        [ESC, rest @ ..] => match parse(rest) {
+            Parse::Key(key, len) if !key.modifiers.alt && !matches!(key.key, Key::Paste(_)) => {
+                let modifiers = Modifiers {
+                    alt: true,
+                    ..key.modifiers
+                };
+                Parse::Key(KeyPress { modifiers, ..key }, len + 1)
+            }
+            // `ESC ESC [ A` is Esc followed by Up
+            Parse::Key(..) | Parse::PasteStart(_) => Parse::Key(KeyPress::new(Key::Escape), 1),
+            Parse::Skip(len) => Parse::Skip(len + 1),
+            Parse::Incomplete => Parse::Incomplete,
+        },
+        [byte, ..] if *byte < 0x80 => Parse::Key(control_key(*byte), 1),
+        [byte, ..] => {
+            let len = match byte {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => return Parse::Skip(1),
+            };
+            if bytes.len() < len {
+                return Parse::Incomplete;
+            }
+            match std::str::from_utf8(&bytes[..len]) {
+                Ok(text) => {
+                    let char = text.chars().next().unwrap_or_default();
+                    Parse::Key(KeyPress::new(Key::Char(char)), len)
+                }
+                Err(_) => Parse::Skip(1),
+            }
+        }
+    }
+}
+
+/// Key of an ASCII byte, control bytes are Ctrl plus the key they are typed with
+fn control_key(byte: u8) -> KeyPress {
+    match byte {
+        b'\t' => KeyPress::new(Key::Tab),
+        b'\r' | b'\n' => KeyPress::new(Key::Enter),
+        0x7F => KeyPress::new(Key::Backspace),
+        0x00 => KeyPress::ctrl(' '),
+        0x01..=0x1A => KeyPress::ctrl((byte + b'`') as char),
+        0x1C..=0x1F => KeyPress::ctrl((byte + b'@') as char),
+        _ => KeyPress::new(Key::Char(byte as char)),
+    }
+}
+
+/// Parses a CSI sequence, `bytes` starts after `ESC [`
+fn parse_csi(bytes: &[u8]) -> Parse {
+    // parameter bytes, then intermediate bytes, then a final byte
+    let Some(end) = bytes.iter().position(|byte| (0x40..=0x7E).contains(byte)) else {
+        if bytes.len() + 2 > MAX_SEQUENCE_LEN {
+            return Parse::Skip(bytes.len() + 2);
+        }
+        return Parse::Incomplete;
+    };
+    let len = end + 3;
+    if bytes[..end]
+        .iter()
+        .any(|byte| !(0x20..=0x3F).contains(byte))
+    {
+        return Parse::Skip(len);
+    }
+    let Ok(parameters) = std::str::from_utf8(&bytes[..end]) else {
+        return Parse::Skip(len);
+    };
+    // private sequences like mouse reports start with one of `<=>?`
+    if parameters.starts_with(['<', '=', '>', '?']) {
+        return Parse::Skip(len);
+    }
+    // sub parameters after `:` are not used
+    let parameters: Vec<u32> = parameters
+        .split(';')
+        .map(|parameter| parameter.split(':').next().unwrap_or_default())
+        .map(|parameter| parameter.parse().unwrap_or(0))
+        .collect();
+    let parameter = |index: usize| parameters.get(index).copied().unwrap_or(0);
+    let modifiers = Modifiers::from_parameter(parameter(1));
+    let key = match (bytes[end], parameter(0)) {
+        (b'~', 200) if parameters.len() == 1 => return Parse::PasteStart(len),
+        (b'~', 27) => return key_code(parameter(2), Modifiers::from_parameter(parameter(1)), len),
+        (b'~', code) => match tilde_key(code) {
+            Some(key) => key,
+            None => return Parse::Skip(len),
+        },
+        (b'u', code) => return key_code(code, modifiers, len),
+        (b'Z', _) => {
+            let modifiers = Modifiers {
+                shift: true,
+                ..modifiers
+            };
+            return Parse::Key(
+                KeyPress {
+                    key: Key::Tab,
+                    modifiers,
+                },
+                len,
+            );
+        }
+        (byte, _) => match ss3_key(byte) {
+            Some(key) => key,
+            None => return Parse::Skip(len),
+        },
+    };
+    Parse::Key(KeyPress { key, modifiers }, len)
+}
+
+/// Keys of `CSI u` and xterm's `modifyOtherKeys`, reported by their code point
+fn key_code(code: u32, modifiers: Modifiers, len: usize) -> Parse {
+    let key = match code {
+        9 => Key::Tab,
+        13 => Key::Enter,
+        27 => Key::Escape,
+        127 => Key::Backspace,
+        code => match char::from_u32(code) {
+            Some(char) if modifiers.shift => Key::Char(char.to_uppercase().next().unwrap_or(char)),
+            Some(char) => Key::Char(char),
+            None => return Parse::Skip(len),
+        },
+    };
+    let modifiers = match key {
+        // the case of the char already tells about Shift
+        Key::Char(_) => Modifiers {
+            shift: false,
+            ..modifiers
+        },
+        _ => modifiers,
+    };
+    Parse::Key(KeyPress { key, modifiers }, len)
+}
+
+/// Keys of `CSI <number> ~` sequences
+fn tilde_key(code: u32) -> Option<Key> {
+    let key = match code {
+        1 | 7 => Key::Home,
+        2 => Key::Insert,
+        3 => Key::Delete,
+        4 | 8 => Key::End,
+        5 => Key::PageUp,
+        6 => Key::PageDown,
+        11..=15 => Key::F((code - 10) as u8),
+        17..=21 => Key::F((code - 11) as u8),
+        23..=26 => Key::F((code - 12) as u8),
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Keys with a letter as the final byte, the same in CSI and SS3 sequences
+fn ss3_key(byte: u8) -> Option<Key> {
+    let key = match byte {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        b'C' => Key::Right,
+        b'D' => Key::Left,
+        b'H' => Key::Home,
+        b'F' => Key::End,
+        b'M' => Key::Enter,
+        b'P' => Key::F(1),
+        b'Q' => Key::F(2),
+        b'R' => Key::F(3),
+        b'S' => Key::F(4),
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Bytes that an xterm sends for a key press, the inverse of `decode`
+pub fn encode(key: &KeyPress) -> Vec<u8> {
+    let modifiers = key.modifiers;
+    let csi = |code: &str, suffix: char| -> Vec<u8> {
+        if modifiers.is_empty() {
+            format!("\x1b[{}{}", code, suffix).into_bytes()
+        } else {
+            let code = if code.is_empty() { "1" } else { code };
+            format!("\x1b[{};{}{}", code, modifiers.parameter(), suffix).into_bytes()
+        }
+    };
+    // keys that have a byte of their own take Alt as an `ESC` prefix
+    let with_alt = |bytes: Vec<u8>| -> Vec<u8> {
+        if modifiers.alt {
+            [vec![ESC], bytes].concat()
+        } else {
+            bytes
+        }
+    };
+    let csi_u =
+        |code: u32| -> Vec<u8> { format!("\x1b[{};{}u", code, modifiers.parameter()).into_bytes() };
+    match &key.key {
+        Key::Char(char) if modifiers.ctrl => match control_byte(*char) {
+            Some(byte) if !modifiers.shift => with_alt(vec![byte]),
+            _ => csi_u(*char as u32),
+        },
+        Key::Char(char) => with_alt(char.to_string().into_bytes()),
+        Key::Tab if modifiers.shift => csi("", 'Z'),
+        Key::Tab if !modifiers.ctrl => with_alt(vec![b'\t']),
+        Key::Enter if !modifiers.ctrl && !modifiers.shift => with_alt(vec![b'\r']),
+        Key::Backspace if !modifiers.ctrl && !modifiers.shift => with_alt(vec![0x7F]),
+        Key::Escape if modifiers.is_empty() => vec![ESC],
+        Key::Tab => csi_u(9),
+        Key::Enter => csi_u(13),
+        Key::Backspace => csi_u(127),
+        Key::Escape => csi_u(27),
+        Key::Up => csi("", 'A'),
+        Key::Down => csi("", 'B'),
+        Key::Right => csi("", 'C'),
+        Key::Left => csi("", 'D'),
+        Key::Home => csi("", 'H'),
+        Key::End => csi("", 'F'),
+        Key::Insert => csi("2", '~'),
+        Key::Delete => csi("3", '~'),
+        Key::PageUp => csi("5", '~'),
+        Key::PageDown => csi("6", '~'),
+        Key::F(n @ 1..=4) if modifiers.is_empty() => vec![ESC, b'O', b'O' + n],
+        Key::F(n @ 1..=4) => csi("", (b'O' + n) as char),
+        Key::F(n) => {
+            let code = match n {
+                5 => 15,
+                6..=10 => *n as u32 + 11,
+                _ => *n as u32 + 12,
+            };
+            csi(&code.to_string(), '~')
+        }
+        Key::Paste(text) => [PASTE_START, text.as_bytes(), PASTE_END].concat(),
+        Key::Resize => Vec::new(),
+    }
+}
+
+/// Byte sent for Ctrl plus a char, for the chars that have one
+fn control_byte(char: char) -> Option<u8> {
+    match char {
+        ' ' => Some(0x00),
+        'a'..='z' if !matches!(char, 'h' | 'i' | 'j' | 'm') => Some(char as u8 - b'`'),
+        // Ctrl-H, Ctrl-I, Ctrl-J and Ctrl-M share their byte with Backspace, Tab and Enter, but
+        // Ctrl-H is kept as is since some terminals send it for Backspace
+        'h' => Some(0x08),
+        '\\' | ']' | '^' | '_' => Some(char as u8 - b'@'),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode, encode, Decoder, Key, KeyPress, Modifiers, ESC_TIMEOUT, MAX_PASTE_LEN,
+        PASTE_TIMEOUT,
+    };
+
+    fn key(key: Key, shift: bool, alt: bool, ctrl: bool) -> KeyPress {
+        KeyPress {
+            key,
+            modifiers: Modifiers { shift, alt, ctrl },
+        }
+    }
+
+    fn one(bytes: &[u8]) -> KeyPress {
+        let keys = decode(bytes);
+        assert_eq!(keys.len(), 1, "{:?} decoded to {:?}", bytes, keys);
+        keys[0].clone()
+    }
+
+    #[test]
+    fn decodes_text() {
+        assert_eq!(
+            decode("aé🤫".as_bytes()),
+            vec![
+                KeyPress::new(Key::Char('a')),
+                KeyPress::new(Key::Char('é')),
+                KeyPress::new(Key::Char('🤫')),
+            ]
+        );
+        assert_eq!(decode(b"\xff\x80a"), vec![KeyPress::new(Key::Char('a'))]);
+        // a char split between two reads
+        let mut decoder = Decoder::default();
+        decoder.feed(&"日".as_bytes()[..1]);
+        assert!(decoder.next_key().is_none());
+        decoder.feed(&"日".as_bytes()[1..]);
+        assert_eq!(decoder.next_key(), Some(KeyPress::new(Key::Char('日'))));
+    }
+
+    #[test]
+    fn decodes_control_bytes() {
+        assert_eq!(one(b"\x01"), KeyPress::ctrl('a'));
+        assert_eq!(one(b"\x1a"), KeyPress::ctrl('z'));
+        assert_eq!(one(b"\x08"), KeyPress::ctrl('h'));
+        assert_eq!(one(b"\x00"), KeyPress::ctrl(' '));
+        assert_eq!(one(b"\x1f"), KeyPress::ctrl('_'));
+        assert_eq!(one(b"\x1c"), KeyPress::ctrl('\\'));
+        assert_eq!(one(b"\t"), KeyPress::new(Key::Tab));
+        assert_eq!(one(b"\r"), KeyPress::new(Key::Enter));
+        assert_eq!(one(b"\n"), KeyPress::new(Key::Enter));
+        assert_eq!(one(b"\x7f"), KeyPress::new(Key::Backspace));
+    }
+
+    #[test]
+    fn decodes_alt_keys() {
+        assert_eq!(one(b"\x1bf"), KeyPress::alt(Key::Char('f')));
+        assert_eq!(one(b"\x1bF"), KeyPress::alt(Key::Char('F')));
+        assert_eq!(one("\x1bé".as_bytes()), KeyPress::alt(Key::Char('é')));
+        assert_eq!(one(b"\x1b\x7f"), KeyPress::alt(Key::Backspace));
+        assert_eq!(one(b"\x1b\x01"), key(Key::Char('a'), false, true, true));
+        assert_eq!(one(b"\x1b\r"), KeyPress::alt(Key::Enter));
+        // rxvt sends Alt with arrows as an `ESC` prefix
+        assert_eq!(one(b"\x1b\x1b[A"), KeyPress::alt(Key::Up));
+        assert_eq!(
+            decode(b"\x1b\x1b"),
+            vec![KeyPress::new(Key::Escape), KeyPress::new(Key::Escape)]
+        );
+        assert_eq!(
+            decode(b"\x1b\x1bf"),
+            vec![KeyPress::new(Key::Escape), KeyPress::alt(Key::Char('f'))]
+        );
+    }
+
+    #[test]
+    fn waits_for_escape_timeout() {
+        let mut decoder = Decoder::default();
+        decoder.feed(b"\x1b");
+        assert_eq!(decoder.timeout_after(), Some(ESC_TIMEOUT));
+        assert_eq!(decoder.next_key(), None);
+        decoder.timeout();
+        assert_eq!(decoder.timeout_after(), None);
+        assert_eq!(decoder.next_key(), Some(KeyPress::new(Key::Escape)));
+
+        // the start of a sequence that never ended is Alt plus its second byte
+        assert_eq!(decode(b"\x1b["), vec![KeyPress::alt(Key::Char('['))]);
+        assert_eq!(decode(b"\x1bO"), vec![KeyPress::alt(Key::Char('O'))]);
+        assert_eq!(
+            decode(b"\x1b[1;"),
+            vec![
+                KeyPress::alt(Key::Char('[')),
+                KeyPress::new(Key::Char('1')),
+                KeyPress::new(Key::Char(';')),
+            ]
+        );
+        assert_eq!(
+            decode(b"\x1b\x1b["),
+            vec![KeyPress::new(Key::Escape), KeyPress::alt(Key::Char('['))]
+        );
+    }
+
+    #[test]
+    fn decodes_sequences_split_between_reads() {
+        let mut decoder = Decoder::default();
+        for byte in b"\x1b[1;5C" {
+            assert_eq!(decoder.next_key(), None);
+            decoder.feed(&[*byte]);
+        }
+        assert_eq!(
+            decoder.next_key(),
+            Some(key(Key::Right, false, false, true))
+        );
+        assert_eq!(decoder.timeout_after(), None);
+    }
+
+    #[test]
+    fn decodes_csi_keys() {
+        let cases: &[(&[u8], Key)] = &[
+            (b"\x1b[A", Key::Up),
+            (b"\x1b[B", Key::Down),
+            (b"\x1b[C", Key::Right),
+            (b"\x1b[D", Key::Left),
+            (b"\x1b[H", Key::Home),
+            (b"\x1b[F", Key::End),
+            (b"\x1b[1~", Key::Home),
+            (b"\x1b[2~", Key::Insert),
+            (b"\x1b[3~", Key::Delete),
+            (b"\x1b[4~", Key::End),
+            (b"\x1b[5~", Key::PageUp),
+            (b"\x1b[6~", Key::PageDown),
+            (b"\x1b[7~", Key::Home),
+            (b"\x1b[8~", Key::End),
+            (b"\x1b[11~", Key::F(1)),
+            (b"\x1b[15~", Key::F(5)),
+            (b"\x1b[17~", Key::F(6)),
+            (b"\x1b[21~", Key::F(10)),
+            (b"\x1b[23~", Key::F(11)),
+            (b"\x1b[24~", Key::F(12)),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(one(bytes), KeyPress::new(expected.clone()), "{:?}", bytes);
+        }
+    }
+
+    #[test]
+    fn decodes_ss3_keys() {
+        let cases: &[(&[u8], Key)] = &[
+            (b"\x1bOA", Key::Up),
+            (b"\x1bOB", Key::Down),
+            (b"\x1bOC", Key::Right),
+            (b"\x1bOD", Key::Left),
+            (b"\x1bOH", Key::Home),
+            (b"\x1bOF", Key::End),
+            (b"\x1bOM", Key::Enter),
+            (b"\x1bOP", Key::F(1)),
+            (b"\x1bOQ", Key::F(2)),
+            (b"\x1bOR", Key::F(3)),
+            (b"\x1bOS", Key::F(4)),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(one(bytes), KeyPress::new(expected.clone()), "{:?}", bytes);
+        }
+        assert_eq!(decode(b"\x1bOz"), vec![]);
+    }
+
+    #[test]
+    fn decodes_modifiers() {
+        assert_eq!(one(b"\x1b[1;2A"), key(Key::Up, true, false, false));
+        assert_eq!(one(b"\x1b[1;3D"), key(Key::Left, false, true, false));
+        assert_eq!(one(b"\x1b[1;5C"), key(Key::Right, false, false, true));
+        assert_eq!(one(b"\x1b[1;6H"), key(Key::Home, true, false, true));
+        assert_eq!(one(b"\x1b[1;8F"), key(Key::End, true, true, true));
+        assert_eq!(one(b"\x1b[1;9B"), key(Key::Down, false, true, false));
+        assert_eq!(one(b"\x1b[3;5~"), key(Key::Delete, false, false, true));
+        assert_eq!(one(b"\x1b[15;2~"), key(Key::F(5), true, false, false));
+        assert_eq!(one(b"\x1b[1;5P"), key(Key::F(1), false, false, true));
+        assert_eq!(one(b"\x1b[Z"), key(Key::Tab, true, false, false));
+    }
+
+    #[test]
+    fn decodes_key_codes() {
+        assert_eq!(one(b"\x1b[97;5u"), KeyPress::ctrl('a'));
+        assert_eq!(one(b"\x1b[105;5u"), KeyPress::ctrl('i'));
+        assert_eq!(one(b"\x1b[97;2u"), KeyPress::new(Key::Char('A')));
+        assert_eq!(one(b"\x1b[13;5u"), key(Key::Enter, false, false, true));
+        assert_eq!(one(b"\x1b[127;3u"), KeyPress::alt(Key::Backspace));
+        assert_eq!(
+            one(b"\x1b[97:65;6u"),
+            key(Key::Char('A'), false, false, true)
+        );
+        assert_eq!(one(b"\x1b[27;5;9~"), key(Key::Tab, false, false, true));
+        assert_eq!(one(b"\x1b[27;3;46~"), KeyPress::alt(Key::Char('.')));
+    }
+
+    #[test]
+    fn skips_unknown_sequences() {
+        assert_eq!(decode(b"\x1b[99~a"), vec![KeyPress::new(Key::Char('a'))]);
+        // focus events and mouse reports
+        assert_eq!(decode(b"\x1b[I\x1b[O\x1b[<0;10;5M"), vec![]);
+        assert_eq!(decode(b"\x1b[?1;2c"), vec![]);
+        let mut endless = b"\x1b[".to_vec();
+        endless.extend([b'1'; 31]);
+        endless.push(b'x');
+        assert_eq!(decode(&endless), vec![KeyPress::new(Key::Char('x'))]);
+    }
+
+    #[test]
+    fn decodes_bracketed_paste() {
+        let mut decoder = Decoder::default();
+        decoder.feed(b"a\x1b[200~echo \x1b[A\n");
+        assert_eq!(decoder.timeout_after(), Some(PASTE_TIMEOUT));
+        decoder.feed("é\x1b[201".as_bytes());
+        assert_eq!(decoder.next_key(), Some(KeyPress::new(Key::Char('a'))));
+        assert_eq!(decoder.next_key(), None);
+        decoder.feed(b"~b");
+        assert_eq!(
+            decoder.next_key(),
+            Some(KeyPress::new(Key::Paste("echo \x1b[A\né".to_string())))
+        );
+        assert_eq!(decoder.next_key(), Some(KeyPress::new(Key::Char('b'))));
+    }
+
+    #[test]
+    fn ends_a_paste_whose_end_never_comes() {
+        let mut decoder = Decoder::default();
+        decoder.feed(b"\x1b[200~ls\n");
+        assert_eq!(decoder.next_key(), None);
+        decoder.timeout();
+        assert_eq!(
+            decoder.next_key(),
+            Some(KeyPress::new(Key::Paste("ls\n".to_string())))
+        );
+        decoder.feed(b"a");
+        assert_eq!(decoder.next_key(), Some(KeyPress::new(Key::Char('a'))));
+
+        let mut decoder = Decoder::default();
+        decoder.feed(b"\x1b[200~");
+        decoder.feed("é".repeat(MAX_PASTE_LEN).as_bytes());
+        decoder.feed(b"\x1b[201~");
+        let mut pasted = String::new();
+        while let Some(key) = decoder.next_key() {
+            let Key::Paste(text) = key.key else {
+                panic!("{:?} is not a paste", key);
+            };
+            assert!(text.len() <= MAX_PASTE_LEN);
+            pasted.push_str(&text);
+        }
+        assert_eq!(pasted, "é".repeat(MAX_PASTE_LEN));
+    }
+
+    #[test]
+    fn encodes_keys_back() {
+        let mut keys = vec![
+            KeyPress::new(Key::Char('x')),
+            KeyPress::new(Key::Char('🤫')),
+            KeyPress::ctrl('a'),
+            KeyPress::ctrl('h'),
+            KeyPress::ctrl('i'),
+            KeyPress::ctrl(' '),
+            KeyPress::ctrl(']'),
+            KeyPress::alt(Key::Char('f')),
+            key(Key::Char('a'), false, true, true),
+            KeyPress::new(Key::Paste("ls\n".to_string())),
+        ];
+        let named = [
+            Key::Enter,
+            Key::Tab,
+            Key::Backspace,
+            Key::Escape,
+            Key::Up,
+            Key::Down,
+            Key::Left,
+            Key::Right,
+            Key::Home,
+            Key::End,
+            Key::Insert,
+            Key::Delete,
+            Key::PageUp,
+            Key::PageDown,
+            Key::F(1),
+            Key::F(4),
+            Key::F(5),
+            Key::F(10),
+            Key::F(12),
+        ];
+        for named in named {
+            for mask in 0..8 {
+                keys.push(key(
+                    named.clone(),
+                    mask & 1 != 0,
+                    mask & 2 != 0,
+                    mask & 4 != 0,
+                ));
+            }
+        }
+        for key in keys {
+            assert_eq!(
+                decode(&encode(&key)),
+                vec![key.clone()],
+                "{:?}",
+                encode(&key)
+            );
+        }
+        assert_eq!(encode(&KeyPress::new(Key::Delete)), b"\x1b[3~");
+        assert_eq!(encode(&key(Key::Left, false, false, true)), b"\x1b[1;5D");
+        assert_eq!(encode(&KeyPress::alt(Key::Backspace)), b"\x1b\x7f");
+    }
+}
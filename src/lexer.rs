@@ -1,24 +1,79 @@
-/// This lexer performs lexing on the fly, in order to make syntax highlighting possible
-#[allow(dead_code)]
+//! This lexer performs lexing on the fly, in order to make syntax highlighting possible
 
+use std::ops::Range;
+
+/// Words with a meaning for the parser when they are in command position
+pub const RESERVED_WORDS: &[&str] = &["{", "}", "if", "then", "elif", "else", "fi"];
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Token {
     OutGreaterThan,
+    AppendGreaterThan,
     EnvVar,
     Item,
-    EOF,
+    OpenParen,
+    CloseParen,
+    Semicolon,
+    Pipe,
+    Ampersand,
+    And,
+    Or,
+    Eof,
 }
 
 #[derive(Debug)]
 pub struct TokenShush {
     token: Token,
     content: Option<String>,
+    /// Bytes of the line the token was lexed from, with its quotes
+    span: Range<usize>,
+    /// Quote of the first char of the word, `\\` if it is escaped, which decides whether a
+    /// leading `~` or `$` is expanded
+    quote: Option<char>,
 }
 
 impl TokenShush {
-    pub fn new(token: Token, content: Option<String>) -> TokenShush {
-        TokenShush { token, content }
+    pub fn new(token: Token, content: Option<String>, span: Range<usize>) -> TokenShush {
+        TokenShush {
+            token,
+            content,
+            span,
+            quote: None,
+        }
+    }
+
+    pub fn token(&self) -> &Token {
+        &self.token
+    }
+
+    pub fn content(&self) -> Option<&str> {
+        self.content.as_deref()
+    }
+
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+
+    pub fn quote(&self) -> Option<char> {
+        self.quote
+    }
+
+    /// The token as if it was lexed from another part of the line, like the tokens of an alias
+    pub fn with_span(self, span: Range<usize>) -> TokenShush {
+        TokenShush { span, ..self }
+    }
+
+    /// Whether the token after this one is in command position, given whether this one is.
+    /// Reserved words like `if` or `{` are followed by a command too.
+    pub fn starts_command(&self, command_start: bool) -> bool {
+        match self.token {
+            Token::Item => {
+                command_start
+                    && matches!(self.content(), Some("{" | "if" | "then" | "else" | "elif"))
+            }
+            Token::EnvVar | Token::OutGreaterThan | Token::AppendGreaterThan => false,
+            _ => true,
+        }
     }
 }
 
@@ -27,6 +82,8 @@ pub enum LexerState {
     General,
     InsideItem,
     EnvVar,
+    /// The previous char could be the start of a two char operator (`&&`, `||`, `>>`)
+    Operator(char),
 }
 
 #[derive(Debug)]
@@ -34,6 +91,18 @@ pub struct Lexer {
     state: LexerState,
     lexed: Vec<TokenShush>,
     acc: String,
+    /// Quote char of the quoted part of the word being lexed
+    quote: Option<char>,
+    /// The previous char was a backslash that quotes this one
+    escaped: bool,
+    /// The word being lexed has a quoted part, so it is kept even if it is empty like `''`
+    quoted: bool,
+    /// Quote of the first char of the word being lexed, `\\` if it is escaped
+    start_quote: Option<char>,
+    /// Byte offset of the char being lexed
+    offset: usize,
+    /// Byte offset where the word or operator being lexed started
+    start: usize,
 }
 
 impl Lexer {
@@ -42,60 +111,126 @@ impl Lexer {
             state: LexerState::General,
             lexed: Vec::new(),
             acc: String::new(),
+            quote: None,
+            escaped: false,
+            quoted: false,
+            start_quote: None,
+            offset: 0,
+            start: 0,
         }
     }
 
+    /// Lexes a whole line, which does not need to end with a newline.
+    pub fn tokenize(line: &str) -> Vec<TokenShush> {
+        let mut lexer = Lexer::new();
+        for char in line.chars() {
+            lexer.lex(char);
+        }
+        if !line.ends_with('\n') {
+            lexer.lex('\n');
+        }
+        lexer.lexed
+    }
+
+    /// Whether the text lexed so far ends inside quotes or right after a backslash, so the word
+    /// goes on in the next line
+    pub fn in_word(&self) -> bool {
+        self.quote.is_some() || self.escaped
+    }
+
     pub fn lex(&mut self, next_char: char) {
+        self.lex_char(next_char);
+        self.offset += next_char.len_utf8();
+    }
+
+    fn lex_char(&mut self, next_char: char) {
+        if self.escaped {
+            self.escaped = false;
+            // inside double quotes a backslash only quotes the chars that are special there
+            if self.quote == Some('"') && !matches!(next_char, '$' | '`' | '"' | '\\' | '\n') {
+                self.acc.push('\\');
+            }
+            // an escaped newline joins the lines
+            if next_char != '\n' {
+                self.start_item();
+                self.acc.push(next_char);
+            } else if self.acc.is_empty() && self.quote.is_none() {
+                self.start_quote = None;
+            }
+            return;
+        }
+        if let Some(quote) = self.quote {
+            match next_char {
+                _ if next_char == quote => self.quote = None,
+                '\\' if quote == '"' => self.escaped = true,
+                _ => self.acc.push(next_char),
+            }
+            return;
+        }
+        if let LexerState::Operator(previous) = self.state {
+            self.state = LexerState::General;
+            let token = match (previous, next_char) {
+                ('&', '&') => Some(Token::And),
+                ('|', '|') => Some(Token::Or),
+                ('>', '>') => Some(Token::AppendGreaterThan),
+                _ => None,
+            };
+            match token {
+                Some(token) => {
+                    self.push(token, None, self.start..self.offset + 1);
+                    return;
+                }
+                None => self.push_operator(previous),
+            }
+        }
+
         match next_char {
-            '>' => {
-                self.state = LexerState::General;
-                self.lexed
-                    .push(TokenShush::new(Token::OutGreaterThan, None));
+            '>' | '&' | '|' => {
+                self.flush_acc();
+                self.state = LexerState::Operator(next_char);
+                self.start = self.offset;
+            }
+            '(' | ')' | ';' => {
+                self.flush_acc();
+                let token = match next_char {
+                    '(' => Token::OpenParen,
+                    ')' => Token::CloseParen,
+                    _ => Token::Semicolon,
+                };
+                self.push(token, None, self.offset..self.offset + 1);
             }
             '$' => {
+                self.flush_acc();
                 self.state = LexerState::EnvVar;
                 self.acc = String::new();
+                self.start = self.offset;
             }
-            ' ' => match self.state {
-                LexerState::InsideItem => {
-                    self.state = LexerState::General;
-                    if !self.acc.is_empty() {
-                        self.lexed
-                            .push(TokenShush::new(Token::Item, Some(self.acc.clone())));
-                    }
+            '\'' | '"' => {
+                self.start_item();
+                if self.acc.is_empty() && self.start_quote.is_none() {
+                    self.start_quote = Some(next_char);
                 }
-                LexerState::General => (),
-                LexerState::EnvVar => {
-                    self.state = LexerState::General;
-                    if !self.acc.is_empty() {
-                        self.lexed
-                            .push(TokenShush::new(Token::EnvVar, Some(self.acc.clone())));
-                    }
+                self.quote = Some(next_char);
+                self.quoted = true;
+            }
+            '\\' => {
+                self.start_item();
+                if self.acc.is_empty() && self.start_quote.is_none() {
+                    self.start_quote = Some('\\');
                 }
-            },
+                self.escaped = true;
+            }
+            ' ' | '\t' => self.flush_acc(),
             '\n' => {
-                match self.state {
-                    LexerState::General => (),
-                    LexerState::InsideItem => {
-                        if !self.acc.is_empty() {
-                            self.lexed
-                                .push(TokenShush::new(Token::Item, Some(self.acc.clone())));
-                        }
-                    }
-                    LexerState::EnvVar => {
-                        if !self.acc.is_empty() {
-                            self.lexed
-                                .push(TokenShush::new(Token::EnvVar, Some(self.acc.clone())));
-                        }
-                    }
-                };
-                self.lexed.push(TokenShush::new(Token::EOF, None));
+                self.flush_acc();
+                self.push(Token::Eof, None, self.offset..self.offset);
             }
             _ => match self.state {
-                LexerState::General => {
+                LexerState::General | LexerState::Operator(_) => {
                     self.state = LexerState::InsideItem;
                     self.acc = String::new();
                     self.acc.push(next_char);
+                    self.start = self.offset;
                 }
                 LexerState::InsideItem => {
                     self.acc.push(next_char);
@@ -106,6 +241,48 @@ impl Lexer {
             },
         };
     }
+
+    /// Starts a word unless one is being lexed already
+    fn start_item(&mut self) {
+        if let LexerState::General | LexerState::Operator(_) = self.state {
+            self.state = LexerState::InsideItem;
+            self.acc = String::new();
+            self.start = self.offset;
+        }
+    }
+
+    /// Pushes the word being accumulated, if any, and goes back to the general state.
+    fn flush_acc(&mut self) {
+        let token = match self.state {
+            LexerState::InsideItem => Token::Item,
+            LexerState::EnvVar => Token::EnvVar,
+            LexerState::General | LexerState::Operator(_) => return,
+        };
+        self.state = LexerState::General;
+        let quoted = std::mem::take(&mut self.quoted);
+        let start_quote = self.start_quote.take();
+        if !self.acc.is_empty() || quoted {
+            let content = std::mem::take(&mut self.acc);
+            let span = self.start..self.offset;
+            self.lexed.push(TokenShush {
+                quote: start_quote,
+                ..TokenShush::new(token, Some(content), span)
+            });
+        }
+    }
+
+    fn push_operator(&mut self, operator: char) {
+        let token = match operator {
+            '>' => Token::OutGreaterThan,
+            '&' => Token::Ampersand,
+            _ => Token::Pipe,
+        };
+        self.push(token, None, self.start..self.start + 1);
+    }
+
+    fn push(&mut self, token: Token, content: Option<String>, span: Range<usize>) {
+        self.lexed.push(TokenShush::new(token, content, span));
+    }
 }
 
 #[cfg(test)]
@@ -128,6 +305,57 @@ mod tests {
         let second_token = &iter_lexed.next().unwrap();
         assert_eq!(second_token.content.as_ref().unwrap(), "PWD");
         let third_token = &iter_lexed.next().unwrap().token;
-        assert_eq!(third_token, &Token::EOF);
+        assert_eq!(third_token, &Token::Eof);
+    }
+
+    #[test]
+    fn it_lexes_operators() {
+        let tokens: Vec<Token> = Lexer::tokenize("(cd dir && make)>>out; { a || b; }")
+            .into_iter()
+            .map(|token| token.token)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::OpenParen,
+                Token::Item,
+                Token::Item,
+                Token::And,
+                Token::Item,
+                Token::CloseParen,
+                Token::AppendGreaterThan,
+                Token::Item,
+                Token::Semicolon,
+                Token::Item,
+                Token::Item,
+                Token::Or,
+                Token::Item,
+                Token::Semicolon,
+                Token::Item,
+                Token::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn it_lexes_quotes_and_escapes() {
+        let line = r#"echo 'a  b'"c\"d\e" '' x\ y\;z \"#;
+        let words: Vec<String> = Lexer::tokenize(&format!("{}\nw", line))
+            .into_iter()
+            .filter_map(|token| token.content().map(str::to_string))
+            .collect();
+        assert_eq!(words, vec!["echo", "a  bc\"d\\e", "", "x y;z", "w"]);
+    }
+
+    #[test]
+    fn it_records_where_tokens_are() {
+        let spans: Vec<_> = Lexer::tokenize("cd 'a b'&&$HOME >>é")
+            .iter()
+            .map(|token| token.span())
+            .collect();
+        assert_eq!(
+            spans,
+            vec![0..2, 3..8, 8..10, 10..15, 16..18, 18..20, 20..20]
+        );
     }
 }
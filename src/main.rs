@@ -1,9 +1,17 @@
 use std::error;
 use std::io;
+use std::path::Path;
 
-use evaluator::ShushCmd;
+use config::{Config, Options, RcError};
+use parser::SyntaxTree;
+use prompt::{Prompt, PromptContext};
 use read_input::IO;
-use editor::Editor;
+use editor::{EditAction, Editor};
+use keymap::{Action, Lookup};
+use keys::{Key, KeyPress};
+use shell_options::EditingMode;
+use vi::{Vi, ViKey};
+use completion::Completion;
 
 mod gap_buffer;
 mod evaluator;
@@ -13,49 +21,297 @@ mod parser;
 mod builtin;
 mod timestamps;
 mod editor;
+mod config;
+mod history;
+mod prompt;
+mod keymap;
+mod keys;
+mod shell_options;
+mod vi;
+mod render;
+mod completion;
+mod manpages;
+mod matcher;
+mod path_cache;
+mod not_found;
+mod aliases;
+mod abbreviations;
+mod dir_stack;
+mod frecency;
 
 fn main() {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!(
+                "shush: {}\nusage: shush [--norc] [--rcfile <path>] [--generate-completions]",
+                error
+            );
+            std::process::exit(2);
+        }
+    };
+    if options.generate_completions {
+        let cache_dir = manpages::cache_dir();
+        match manpages::generate(&cache_dir) {
+            Ok(count) => {
+                println!("wrote completions of {} commands to {}", count, cache_dir.display());
+                std::process::exit(0);
+            }
+            Err(error) => {
+                eprintln!("shush: error while generating completions: {}", error);
+                std::process::exit(1);
+            }
+        }
+    }
+    let config = Config::load().unwrap_or_else(|error| {
+        eprintln!("shush: {}", error);
+        Config::default()
+    });
+    not_found::set_handler(config.command_not_found_handler.clone());
+    frecency::set_database(Some(config::data_dir().join("z")));
+    for (name, expansion) in &config.abbreviations {
+        if let Err(error) = abbreviations::set(name, expansion) {
+            eprintln!("shush: {}", error);
+        }
+    }
+    if let Err(error) = history::trim(&config.history.path, config.history.size) {
+        eprintln!("shush: error while trimming the histfile: {}", error);
+    }
+    if let Err(error) = history::load(&config.history.path) {
+        eprintln!("shush: error while reading the histfile: {}", error);
+    }
+    for error in keymap::bind_all(&config.key_bindings) {
+        eprintln!("shush: key_bindings: {}", error);
+    }
+    let inputrc = config::config_dir().join("inputrc");
+    if inputrc.exists() {
+        report_rc_errors(&inputrc, keymap::read_file(&inputrc));
+    }
+    if let Some(rcfile) = options.rcfile() {
+        // the default rc file is optional, one given with --rcfile is not
+        if rcfile.exists() || options.rcfile.is_some() {
+            report_rc_errors(&rcfile, config::source(&rcfile));
+        }
+    }
+
     let mut io = IO::build_io().expect("Should able to build io");
     let og_termios = io
         .get_termios()
         .expect("Should be able to get termios struct");
-    let mut termios = og_termios.clone();
+    let mut termios = og_termios;
     if let Err(error) = io.enable_raw_mode(&mut termios) {
         io.write_to_stderr(format!("Error while enabling raw mode: {:?}\n", error).as_bytes())
             .unwrap();
     };
-    repl(&mut io);
+    if let Err(error) = io.watch_window_size() {
+        io.write_to_stderr(
+            format!("Error while reading the window size: {:?}\n", error).as_bytes(),
+        )
+        .unwrap();
+    }
+    if let Err(error) = io.enable_bracketed_paste() {
+        io.write_to_stderr(
+            format!("Error while enabling bracketed paste: {:?}\n", error).as_bytes(),
+        )
+        .unwrap();
+    }
+    if let Err(error) = repl(&mut io, &config) {
+        io.write_to_stderr(format!("Error in the repl: {:?}\n", error).as_bytes())
+            .unwrap();
+    }
+    // the shell is exiting and the terminal may be gone, there is nothing left to do on errors
+    let _ = io.disable_bracketed_paste();
     io.change_termios(&og_termios).unwrap();
 }
 
-enum SpecialKey {
-    LeftArrow,
-    RightArrow,
-    UpArrow,
-    DownArrow,
-    Home,
-    End,
-    Backspace,
-    Delete,
-    Enter,
-    CtrlC,
-    CtrlU,
-    CtrlD,
-    Quit,
-    EOT,
-    VTab,
+fn report_rc_errors(path: &Path, result: anyhow::Result<Vec<RcError>>) {
+    match result {
+        Ok(errors) => {
+            for rc_error in errors {
+                eprintln!(
+                    "shush: {}:{}: {}",
+                    path.display(),
+                    rc_error.line,
+                    rc_error.error
+                );
+            }
+        }
+        Err(error) => eprintln!("shush: {}", error),
+    }
+}
+
+/// Keys for vi mode from a sequence that the keymap does not handle. Alt plus a key is Esc typed
+/// quickly before the key.
+fn vi_keys(sequence: &[KeyPress]) -> Vec<ViKey> {
+    let mut vi_keys = Vec::new();
+    for key in sequence {
+        if key.modifiers.alt {
+            vi_keys.push(ViKey::Escape);
+        }
+        match key.key {
+            Key::Char(char) if key.modifiers.ctrl => vi_keys.push(ViKey::Ctrl(char)),
+            Key::Char(char) => vi_keys.push(ViKey::Char(char)),
+            Key::Escape => vi_keys.push(ViKey::Escape),
+            Key::Backspace => vi_keys.push(ViKey::Backspace),
+            _ => (),
+        }
+    }
+    vi_keys
+}
+
+fn vi_mode() -> bool {
+    shell_options::editing_mode() == EditingMode::Vi
+}
+
+fn vi_edit(io: &mut IO, editor: &mut Editor, vi: &mut Vi, keys: Vec<ViKey>) -> io::Result<()> {
+    for key in keys {
+        vi.handle_key(key, editor);
+    }
+    editor.write_line(io)?;
+    io.write_to_stdout(vi.cursor_shape().as_bytes())
 }
 
-fn repl(io: &mut IO) -> Result<(), Box<dyn error::Error>> {
-    let mut editor = Editor::new();
-    if let Err(error) = prompt(io) {
+/// Completes the word before the cursor. When the candidates have nothing more in common than
+/// the word, they are listed below the line.
+fn complete(io: &mut IO, editor: &mut Editor, config: &Config) -> io::Result<()> {
+    let text = editor.get_buffer_text();
+    let cursor = editor.cursor();
+    let completions = completion::complete(&text[..cursor], config.matching);
+    match completions.completion(&text[completions.start..cursor]) {
+        Completion::Replace(replacement) => {
+            editor.begin_undo(None);
+            editor.delete_range(completions.start, cursor);
+            editor.insert(&replacement);
+            editor.write_line(io)
+        }
+        Completion::List => {
+            editor.print_below(&completion::menu(&completions.candidates, io.columns()), io)
+        }
+        Completion::Nothing => Ok(()),
+    }
+}
+
+fn starts_with_space(sequence: &[KeyPress]) -> bool {
+    sequence.first().and_then(KeyPress::text) == Some(' ')
+}
+
+/// Replaces the abbreviation before the cursor with its expansion
+fn expand_abbreviation(editor: &mut Editor) {
+    let text = editor.get_buffer_text();
+    if let Some((range, expansion)) = abbreviations::expansion_at(&text, editor.cursor()) {
+        editor.begin_undo(None);
+        editor.delete_range(range.start, range.end);
+        editor.insert(&expansion);
+    }
+}
+
+/// Prompt of the history search, showing what was typed
+fn search_prompt(query: &str, failed: bool) -> Prompt {
+    let text = format!(
+        "({}reverse-i-search)`{}': ",
+        if failed { "failed " } else { "" },
+        query
+    );
+    Prompt {
+        width: editor::text_width(&text),
+        text,
+    }
+}
+
+/// Searches the history for what is typed after Ctrl-R, showing the best match in the line.
+/// Ctrl-R again goes to the next match, Ctrl-G and Esc give the line back as it was, and any
+/// other key keeps the match and is returned to be handled as usual.
+fn search_history(
+    io: &mut IO,
+    editor: &mut Editor,
+    config: &Config,
+    context: &PromptContext,
+) -> io::Result<Option<KeyPress>> {
+    let original = editor.get_buffer_text();
+    let original_cursor = editor.cursor();
+    let mut query = String::new();
+    let mut index = 0;
+    editor.begin_undo(None);
+    let pending = loop {
+        let found = history::search(&query, config.matching);
+        index = index.min(found.len().saturating_sub(1));
+        match found.get(index).filter(|_| !query.is_empty()) {
+            Some(found) => {
+                let cursor = found.positions.first().copied().unwrap_or(0);
+                editor.set_text(&found.command, cursor);
+                editor.set_highlight(Some(found.positions.clone()));
+            }
+            None if query.is_empty() => {
+                editor.set_text(&original, original_cursor);
+                editor.set_highlight(None);
+            }
+            // the line keeps the last match while nothing matches
+            None => (),
+        }
+        let failed = !query.is_empty() && found.is_empty();
+        editor.set_prompt(
+            search_prompt(&query, failed),
+            Prompt::ps2(config, context),
+            None,
+        );
+        editor.write_line(io)?;
+        let Some(key) = io.read_key()? else {
+            break None;
+        };
+        match key.key {
+            Key::Resize => editor.resize(io)?,
+            Key::Backspace => {
+                query.pop();
+                index = 0;
+            }
+            Key::Char('r') if key.modifiers.ctrl => index += 1,
+            Key::Char('g') if key.modifiers.ctrl => {
+                editor.set_text(&original, original_cursor);
+                break None;
+            }
+            Key::Escape => {
+                editor.set_text(&original, original_cursor);
+                break None;
+            }
+            _ => match key.text() {
+                Some(char) => {
+                    query.push(char);
+                    index = 0;
+                }
+                None => break Some(key),
+            },
+        }
+    };
+    editor.set_highlight(None);
+    editor.set_prompt(
+        Prompt::ps1(config, context),
+        Prompt::ps2(config, context),
+        Prompt::rprompt(config, context),
+    );
+    editor.write_line(io)?;
+    Ok(pending)
+}
+
+fn repl(io: &mut IO, config: &Config) -> Result<(), Box<dyn error::Error>> {
+    // shush has no job control yet, so there are never jobs to show
+    let mut context = PromptContext::default();
+    let mut editor = Editor::new(Prompt::ps1(config, &context));
+    editor.set_theme(config.theme.clone());
+    let mut vi = Vi::default();
+    // key that ended a history search, handled as if it was just read
+    let mut pending: Option<KeyPress> = None;
+    if let Err(error) = prompt(io, &mut editor, &mut vi, config, &context) {
         io.write_to_stderr(format!("Error while showing the prompt: {:?}\n", error).as_bytes())
             .unwrap();
     }
     loop {
-        let cmd: ShushCmd;
-        let bytes = match io.read_keypress() {
-            Ok(key) => key,
+        let read = match pending.take() {
+            Some(key) => Ok(Some(key)),
+            None => io.read_key(),
+        };
+        let key = match read {
+            Ok(Some(key)) => key,
+            Ok(None) => break Ok(()),
             Err(error) => {
                 io.write_to_stderr(
                     format!("Error while reading line from stdin: {:?}\n", error).as_bytes(),
@@ -64,109 +320,176 @@ fn repl(io: &mut IO) -> Result<(), Box<dyn error::Error>> {
                 panic!();
             }
         };
-
-        let key = match bytes.first() {
-            Some(b'q') => Some(SpecialKey::Quit),
-            Some(b'\n') => Some(SpecialKey::Enter),
-            Some(4) => Some(SpecialKey::EOT),
-            Some(11) => Some(SpecialKey::VTab),
-            Some(126) => Some(SpecialKey::Home),
-            Some(127) => Some(SpecialKey::Backspace),
-            Some(32..=255) => {
-                editor.write_to_buffer(bytes.as_slice(), io);
-                None
-            }
-            // Escape sequence
-            Some(b'\x1B') => match io.read_keypress()?.first() {
-                Some(b'[') => {
-                    let first_escape_char = io.read_keypress()?.first().copied();
-                    match first_escape_char {
-                        Some(0..=9) => match io.read_keypress()?.first() {
-                            Some(b'~') => match first_escape_char {
-                                Some(b'1') => Some(SpecialKey::Home),
-                                Some(b'7') => Some(SpecialKey::Home),
-                                _ => None,
-                            },
-                            _ => None,
-                        },
-                        Some(b'A') => Some(SpecialKey::UpArrow),
-                        Some(b'B') => Some(SpecialKey::DownArrow),
-                        Some(b'C') => Some(SpecialKey::RightArrow),
-                        Some(b'D') => Some(SpecialKey::LeftArrow),
-                        Some(b'H') => Some(SpecialKey::Home),
-                        Some(b'F') => Some(SpecialKey::End),
-                        _ => None,
-                    }
-                }
-                _ => None,
-            },
-            _ => None,
+        if key.key == Key::Resize {
+            editor.resize(io)?;
+            continue;
+        }
+        if let Key::Paste(text) = &key.key {
+            if vi_mode() {
+                vi.paste(text, &mut editor);
+                editor.write_line(io)?;
+            } else {
+                editor.paste(text, io)?;
+            }
+            continue;
+        }
+        // keys are read until they match a binding, for bindings like `Ctrl-X Ctrl-E`
+        let mut sequence = vec![key];
+        let lookup = loop {
+            match keymap::lookup(&sequence) {
+                Lookup::Prefix => match io.read_key()? {
+                    Some(key) => sequence.push(key),
+                    None => break Lookup::Unbound,
+                },
+                lookup => break lookup,
+            }
+        };
+        let action = match lookup {
+            Lookup::Bound(action) => action,
+            _ if sequence.iter().all(|key| key.text().is_some()) => Action::SelfInsert,
+            // Esc followed by a key that is not bound switches vi to normal mode
+            _ if vi_mode() => {
+                vi_edit(io, &mut editor, &mut vi, vi_keys(&sequence))?;
+                continue;
+            }
+            _ => continue,
         };
 
-        match key {
-            Some(SpecialKey::Quit) | Some(SpecialKey::EOT) => {
+        // the command runs with its abbreviation expanded, as it is saved to the history
+        if action == Action::AcceptLine {
+            expand_abbreviation(&mut editor);
+        }
+        match action {
+            // Ctrl-D on an empty line ends the shell, otherwise it deletes forward
+            Action::DeleteCharOrEof if editor.is_empty() => {
+                if vi_mode() {
+                    io.write_to_stdout(vi::DEFAULT_CURSOR.as_bytes())?;
+                }
                 io.write_to_stdout("shushing...\n".as_bytes()).unwrap();
                 break Ok(());
             }
-            Some(SpecialKey::LeftArrow) => {
-                if let Some(move_left) = editor.move_cursor_left() {
-                    io.write_to_stdout(move_left.as_bytes())?;
-                };
+            Action::DeleteCharOrEof => editor.edit(EditAction::DeleteForwardChar, io)?,
+            Action::Complete => complete(io, &mut editor, config)?,
+            // in vi normal mode Ctrl-R redoes, as in vi
+            Action::ReverseSearchHistory if vi_mode() && !vi.in_insert_mode() => {
+                vi_edit(io, &mut editor, &mut vi, vi_keys(&sequence))?
             }
-            Some(SpecialKey::RightArrow) => {
-                if let Some(move_right) = editor.move_cursor_right() {
-                    io.write_to_stdout(move_right.as_bytes())?;
-                };
+            Action::ReverseSearchHistory => {
+                pending = search_history(io, &mut editor, config, &context)?;
             }
-            Some(SpecialKey::Home) => {
-                let move_to_start = editor.move_cursor_to_start();
-                io.write_to_stdout(move_to_start.as_bytes())?;
+            Action::SelfInsert if vi_mode() => {
+                if vi.in_insert_mode() && starts_with_space(&sequence) {
+                    expand_abbreviation(&mut editor);
+                }
+                vi_edit(io, &mut editor, &mut vi, vi_keys(&sequence))?
             }
-            Some(SpecialKey::End) => {
-                let move_to_start = editor.move_cursor_to_start();
-                io.write_to_stdout(move_to_start.as_bytes())?;
+            Action::SelfInsert => {
+                if starts_with_space(&sequence) {
+                    expand_abbreviation(&mut editor);
+                }
+                let text: String = sequence.iter().filter_map(KeyPress::text).collect();
+                editor.write_to_buffer(&text, io)?;
             }
-            Some(SpecialKey::Backspace) => {
-                editor.delete_backwards(io);
+            Action::Edit(EditAction::DeleteBackwardChar) if vi_mode() => {
+                vi_edit(io, &mut editor, &mut vi, vec![ViKey::Backspace])?
             }
-            Some(SpecialKey::VTab) => {
-                // autocomplete here, GL :)
+            Action::Edit(action) => editor.edit(action, io)?,
+            Action::AcceptLine if parser::needs_more_input(&editor.get_buffer_text()) => {
+                editor.write_to_buffer("\n", io)?
             }
-            Some(SpecialKey::Enter) => {
-                io.write_to_stdout(b"\n");
-                let cmd = match ShushCmd::parse_command(editor.get_buffer_text()?) {
-                    Some(cmd) => cmd,
-                    None => {
-                        io.write_to_stderr("No command found\n".as_bytes()).unwrap();
+            Action::AcceptLine => {
+                editor.finish_line(io)?;
+                let line = editor.get_buffer_text();
+                let tree = match SyntaxTree::parse(&line) {
+                    Ok(Some(tree)) => tree,
+                    Ok(None) => {
+                        editor.clean_buffer();
+                        next_prompt(io, &mut editor, &mut vi, config, &context)?;
+                        continue;
+                    }
+                    Err(error) => {
+                        io.write_to_stderr(format!("shush: {}\n", error).as_bytes())
+                            .unwrap();
+                        context.last_status = 2;
+                        editor.clean_buffer();
+                        next_prompt(io, &mut editor, &mut vi, config, &context)?;
                         continue;
                     }
                 };
-                let finished_cmd = match cmd.execute_command() {
+                // programs get pasted text as typed
+                io.disable_bracketed_paste()?;
+                let result = tree.execute();
+                io.enable_bracketed_paste()?;
+                // the window could have been resized while the command ran
+                io.update_size();
+                let finished_cmd = match result {
                     Ok(finished_cmd) => finished_cmd,
                     Err(error) => {
                         io.write_to_stderr(format!("Command failed: {:?}\n", error).as_bytes())
                             .unwrap();
+                        context.last_status = 1;
                         editor.clean_buffer();
-                        editor.new_line(io);
+                        next_prompt(io, &mut editor, &mut vi, config, &context)?;
                         continue;
                     }
                 };
-                if let Err(error) = finished_cmd.append_to_histfile() {
+                context.last_status = finished_cmd.exit_code();
+                history::push(finished_cmd.history_entry(&line));
+                if let Err(error) = finished_cmd.append_to_histfile(&line, &config.history.path) {
                     io.write_to_stderr(
                         format!("Error while saving command to histfile: {:?}\n", error).as_bytes(),
                     )
                     .unwrap();
                 };
                 editor.clean_buffer();
-                editor.new_line(io);
+                next_prompt(io, &mut editor, &mut vi, config, &context)?;
             }
-            None => (),
-            _ => (),
         }
     }
 }
 
-fn prompt(io: &mut IO) -> io::Result<()> {
-    io.write_to_stdout("🤫> ".as_bytes())?;
-    Ok(())
+fn prompt(
+    io: &mut IO,
+    editor: &mut Editor,
+    vi: &mut Vi,
+    config: &Config,
+    context: &PromptContext,
+) -> io::Result<()> {
+    editor.set_prompt(
+        Prompt::ps1(config, context),
+        Prompt::ps2(config, context),
+        Prompt::rprompt(config, context),
+    );
+    editor.write_line(io)?;
+    reset_vi(io, editor, vi)
+}
+
+/// Renders the prompts again, since the last command can change what they show, and starts a
+/// new line with them.
+fn next_prompt(
+    io: &mut IO,
+    editor: &mut Editor,
+    vi: &mut Vi,
+    config: &Config,
+    context: &PromptContext,
+) -> io::Result<()> {
+    editor.set_prompt(
+        Prompt::ps1(config, context),
+        Prompt::ps2(config, context),
+        Prompt::rprompt(config, context),
+    );
+    editor.new_line(io)?;
+    reset_vi(io, editor, vi)
+}
+
+/// Lines start in insert mode. The cursor shape is set on every line since the last command
+/// could have turned vi mode on or off.
+fn reset_vi(io: &mut IO, editor: &mut Editor, vi: &mut Vi) -> io::Result<()> {
+    vi.reset(editor);
+    let shape = if vi_mode() {
+        vi.cursor_shape()
+    } else {
+        vi::DEFAULT_CURSOR
+    };
+    io.write_to_stdout(shape.as_bytes())
 }
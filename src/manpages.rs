@@ -0,0 +1,495 @@
+//! Completions generated from man pages, like fish does.
+//!
+//! `shush --generate-completions` reads the man pages of sections 1 and 8 from `MANPATH`, finds
+//! the options they document and writes them to a file per command in the cache directory. Each
+//! line of a file is an option and the first sentence of its description, separated by a tab,
+//! like `--directory<TAB>list directories themselves, not their contents`.
+//!
+//! Completion reads the file of a command when a word that starts with `-` is completed.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use flate2::read::GzDecoder;
+
+use crate::completion::Candidate;
+use crate::config;
+
+/// Man page directories searched when `MANPATH` is not set, or has an empty entry
+const DEFAULT_MANPATH: &[&str] = &["/usr/local/share/man", "/usr/share/man"];
+
+/// Sections with commands: user commands and administration commands
+const SECTIONS: &[&str] = &["man1", "man8"];
+
+/// Longest description kept, in chars
+const DESCRIPTION_LENGTH: usize = 80;
+
+pub fn cache_dir() -> PathBuf {
+    config::cache_dir().join("completions")
+}
+
+/// Writes the options of every man page to the cache and returns how many commands have them.
+/// A command in an earlier directory of `MANPATH` hides the one in later directories.
+pub fn generate(cache_dir: &Path) -> io::Result<usize> {
+    fs::create_dir_all(cache_dir)?;
+    let mut done = HashSet::new();
+    for dir in manpath() {
+        for section in SECTIONS {
+            let Ok(entries) = fs::read_dir(dir.join(section)) else {
+                continue;
+            };
+            for entry in entries.filter_map(Result::ok) {
+                let path = entry.path();
+                let Some(name) = command_name(&path) else {
+                    continue;
+                };
+                if done.contains(&name) {
+                    continue;
+                }
+                // pages that can not be read, like broken links, are skipped
+                let Ok(page) = read_page(&path) else {
+                    continue;
+                };
+                let options = parse_options(&page);
+                if options.is_empty() {
+                    continue;
+                }
+                let mut contents = String::new();
+                for (option, description) in options {
+                    contents.push_str(&format!("{}\t{}\n", option, description));
+                }
+                fs::write(cache_dir.join(&name), contents)?;
+                done.insert(name);
+            }
+        }
+    }
+    Ok(done.len())
+}
+
+/// Options of a command read from the cache, with their descriptions
+pub fn options(name: &str) -> Vec<Candidate> {
+    // names with a slash would read outside the cache
+    if name.contains('/') {
+        return Vec::new();
+    }
+    let contents = fs::read_to_string(cache_dir().join(name)).unwrap_or_default();
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (option, description) = line.split_once('\t')?;
+            let mut candidate = Candidate::new(option);
+            candidate.description = (!description.is_empty()).then(|| description.to_string());
+            Some(candidate)
+        })
+        .collect()
+}
+
+fn manpath() -> Vec<PathBuf> {
+    let manpath = std::env::var("MANPATH").unwrap_or_default();
+    if manpath.is_empty() {
+        return DEFAULT_MANPATH.iter().map(PathBuf::from).collect();
+    }
+    let mut dirs = Vec::new();
+    for dir in manpath.split(':') {
+        if dir.is_empty() {
+            dirs.extend(DEFAULT_MANPATH.iter().map(PathBuf::from));
+        } else {
+            dirs.push(PathBuf::from(dir));
+        }
+    }
+    dirs
+}
+
+/// Name of the command of a page like `ls.1.gz` or `openssl.1ssl`
+fn command_name(path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_str()?;
+    let file_name = file_name.strip_suffix(".gz").unwrap_or(file_name);
+    let (name, section) = file_name.rsplit_once('.')?;
+    if name.is_empty() || !section.starts_with(|char: char| char.is_ascii_digit()) {
+        return None;
+    }
+    Some(name.to_string())
+}
+
+fn read_page(path: &Path) -> io::Result<String> {
+    let bytes = fs::read(path)?;
+    if path.extension().is_some_and(|extension| extension == "gz") {
+        let mut page = String::new();
+        GzDecoder::new(bytes.as_slice()).read_to_string(&mut page)?;
+        Ok(page)
+    } else {
+        String::from_utf8(bytes).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+/// Macros that change the font of their arguments, which are text of the page
+const FONT_MACROS: &[&str] = &[
+    "B", "I", "R", "SM", "SB", "BR", "BI", "IR", "IB", "RB", "RI",
+];
+
+/// Inline macros of mdoc pages, whose arguments are text of the page
+const MDOC_MACROS: &[&str] = &[
+    "Aq", "Ar", "Cm", "Dq", "Em", "Ev", "Fl", "Ic", "Li", "Nm", "No", "Ns", "Op", "Pa", "Pq", "Ql",
+    "Sq", "Sy", "Va", "Xr",
+];
+
+/// Options documented in a man page, in order and with the first sentence of their
+/// description. Options are the terms of tagged paragraphs (`.TP`, `.IP`, the mdoc `.It`, or a
+/// `.PP` followed by a line starting with `-`) that start with a dash.
+pub fn parse_options(page: &str) -> Vec<(String, String)> {
+    let mut options = Vec::new();
+    let mut terms: Vec<String> = Vec::new();
+    let mut description = String::new();
+    // the next line is the term of a paragraph
+    let mut term_next = false;
+    // `.Nm` without arguments is the name given to the first one
+    let mut page_name = None;
+    for line in page.lines() {
+        let named;
+        let line = match (line.trim_end(), &page_name) {
+            (".Nm", Some(name)) => {
+                named = format!(".Nm {}", name);
+                named.as_str()
+            }
+            (line, None) if line.starts_with(".Nm ") => {
+                page_name = Some(line[4..].trim().to_string());
+                line
+            }
+            _ => line,
+        };
+        let macro_name = line
+            .strip_prefix('.')
+            .or(line.strip_prefix('\''))
+            .map(|rest| rest.split_whitespace().next().unwrap_or(""));
+        match macro_name {
+            Some(name) if name.starts_with("\\\"") => continue,
+            Some(name) if is_text_macro(name) && !term_next => {
+                push_description(&mut description, &text(line), &terms)
+            }
+            Some(name) => {
+                // a new paragraph ends the description of the last one
+                if !description.is_empty()
+                    || matches!(name, "TP" | "IP" | "PP" | "P" | "LP" | "It" | "SH" | "SS")
+                {
+                    finish_item(&mut options, &mut terms, &mut description);
+                }
+                match name {
+                    "TP" | "PP" | "P" | "LP" => term_next = true,
+                    "IP" => {
+                        let term = text(&format!(".B {}", quoted_arg(line)));
+                        terms = option_names(&term);
+                    }
+                    "It" => terms = option_names(&mdoc_term(line)),
+                    _ if is_text_macro(name) => {
+                        terms = option_names(&text(line));
+                        term_next = false;
+                    }
+                    _ => (),
+                }
+            }
+            None if term_next => {
+                terms = option_names(&text(line));
+                term_next = false;
+            }
+            None => push_description(&mut description, &text(line), &terms),
+        }
+    }
+    finish_item(&mut options, &mut terms, &mut description);
+    options
+}
+
+fn is_text_macro(name: &str) -> bool {
+    FONT_MACROS.contains(&name) || MDOC_MACROS.contains(&name)
+}
+
+fn push_description(description: &mut String, text: &str, terms: &[String]) {
+    if terms.is_empty() || text.is_empty() {
+        return;
+    }
+    if !description.is_empty() {
+        description.push(' ');
+    }
+    description.push_str(text);
+}
+
+fn finish_item(
+    options: &mut Vec<(String, String)>,
+    terms: &mut Vec<String>,
+    description: &mut String,
+) {
+    let sentence = first_sentence(description);
+    for term in terms.drain(..) {
+        options.push((term, sentence.clone()));
+    }
+    description.clear();
+}
+
+/// The description up to the end of its first sentence, cut to `DESCRIPTION_LENGTH` chars
+fn first_sentence(description: &str) -> String {
+    // dots after a space, like in `implied . and ..`, or in `e.g.` do not end sentences
+    let ends_sentence = |index: usize| {
+        let before = &description[..index];
+        !before.ends_with(char::is_whitespace)
+            && !before.ends_with('.')
+            && !before.ends_with("e.g")
+            && !before.ends_with("i.e")
+    };
+    let end = description
+        .match_indices(". ")
+        .map(|(index, _)| index)
+        .chain(description.strip_suffix('.').map(str::len))
+        .find(|index| ends_sentence(*index))
+        .unwrap_or(description.len());
+    let sentence = description[..end].trim_end();
+    let cut = sentence.chars().count() > DESCRIPTION_LENGTH;
+    let mut sentence: String = sentence.chars().take(DESCRIPTION_LENGTH).collect();
+    if cut {
+        sentence.push('…');
+    }
+    sentence
+}
+
+/// The options in a term like `-a, --all` or `--color[=WHEN]`
+fn option_names(term: &str) -> Vec<String> {
+    term.split(|char: char| char == ',' || char.is_whitespace())
+        .filter(|word| word.starts_with('-') && word.len() > 1 && word != &"--")
+        .map(|word| {
+            let end = word.find(['=', '[', '<', '(']).unwrap_or(word.len());
+            word[..end].trim_end_matches([':', '.', ';']).to_string()
+        })
+        .filter(|option| option.len() > 1 && option.chars().skip(1).any(|char| char != '-'))
+        .collect()
+}
+
+/// The first argument of a macro line, which can be quoted
+fn quoted_arg(line: &str) -> &str {
+    let args = line
+        .split_once(char::is_whitespace)
+        .map_or("", |(_, args)| args.trim_start());
+    match args.strip_prefix('"') {
+        Some(rest) => rest.split('"').next().unwrap_or(""),
+        None => args.split_whitespace().next().unwrap_or(""),
+    }
+}
+
+/// The term of an mdoc `.It` line
+fn mdoc_term(line: &str) -> String {
+    mdoc_text(line.split_whitespace().skip(1))
+}
+
+/// Text of the words of an mdoc line without its macros, where `Fl x` is the flag `-x`
+fn mdoc_text<'a>(words: impl Iterator<Item = &'a str>) -> String {
+    let mut text = String::new();
+    let mut words = words.peekable();
+    while let Some(word) = words.next() {
+        let word = match word {
+            "Fl" => match words.next_if(|next| !MDOC_MACROS.contains(next)) {
+                Some(flag) => format!("-{}", flag),
+                None => "-".to_string(),
+            },
+            _ if MDOC_MACROS.contains(&word) => continue,
+            word => word.to_string(),
+        };
+        // punctuation is written right after the word before it
+        let punctuation = word.len() == 1 && ",.;:)]".contains(word.as_str());
+        if !text.is_empty() && !punctuation {
+            text.push(' ');
+        }
+        text.push_str(&word);
+    }
+    unescape(&text)
+}
+
+/// Text of a line without its macro and escapes
+fn text(line: &str) -> String {
+    let Some(rest) = line.strip_prefix('.').or(line.strip_prefix('\'')) else {
+        return unescape(line);
+    };
+    let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    if MDOC_MACROS.contains(&name) {
+        return mdoc_text(rest.split_whitespace());
+    }
+    let args = split_args(args);
+    // alternating macros like `.BR` join their arguments without spaces
+    let separator = if name.len() == 2 && FONT_MACROS.contains(&name) {
+        ""
+    } else {
+        " "
+    };
+    unescape(&args.join(separator))
+}
+
+/// Arguments of a macro, which are separated by spaces unless they are quoted
+fn split_args(args: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut rest = args.trim_start();
+    while !rest.is_empty() {
+        let (arg, next) = match rest.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => rest.split_once(char::is_whitespace).unwrap_or((rest, "")),
+        };
+        result.push(arg);
+        rest = next.trim_start();
+    }
+    result
+}
+
+/// Removes the font and size escapes of roff text and replaces the special chars that matter in
+/// options, like `\-`
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            result.push(char);
+            continue;
+        }
+        match chars.next() {
+            Some('-') => result.push('-'),
+            Some('e') | Some('\\') => result.push('\\'),
+            Some(' ') | Some('~') => result.push(' '),
+            // a comment runs to the end of the line
+            Some('"') => break,
+            Some('(') => {
+                let name: String = chars.by_ref().take(2).collect();
+                result.push_str(special_char(&name));
+            }
+            Some('[') => {
+                let name: String = chars.by_ref().take_while(|char| *char != ']').collect();
+                result.push_str(special_char(&name));
+            }
+            Some('f') | Some('*') | Some('n') => match chars.next() {
+                Some('(') => {
+                    chars.by_ref().take(2).for_each(drop);
+                }
+                Some('[') => {
+                    chars
+                        .by_ref()
+                        .take_while(|char| *char != ']')
+                        .for_each(drop);
+                }
+                _ => (),
+            },
+            Some('s') => {
+                chars.next_if(|char| *char == '+' || *char == '-');
+                while chars.next_if(char::is_ascii_digit).is_some() {}
+            }
+            // zero width escapes like `\&` or `\,`
+            Some(_) | None => (),
+        }
+    }
+    result.trim().to_string()
+}
+
+fn special_char(name: &str) -> &'static str {
+    match name {
+        "aq" | "cq" | "oq" => "'",
+        "dq" | "lq" | "rq" => "\"",
+        "em" | "en" | "hy" | "mi" => "-",
+        "bu" => "•",
+        "co" => "©",
+        "ga" => "`",
+        "ti" => "~",
+        "ha" => "^",
+        "rs" => "\\",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{command_name, first_sentence, parse_options, unescape, DESCRIPTION_LENGTH};
+    use std::path::Path;
+
+    #[test]
+    fn removes_escapes() {
+        assert_eq!(
+            unescape("\\fB\\-\\-color\\fR[=\\fI\\,WHEN\\/\\fR]"),
+            "--color[=WHEN]"
+        );
+        assert_eq!(
+            unescape("it\\(aqs \\s-1SMALL\\s0 \\\" comment"),
+            "it's SMALL"
+        );
+    }
+
+    #[test]
+    fn names_commands_of_pages() {
+        assert_eq!(
+            command_name(Path::new("/usr/share/man/man1/ls.1.gz")),
+            Some("ls".to_string())
+        );
+        assert_eq!(
+            command_name(Path::new("CA.pl.1ssl")),
+            Some("CA.pl".to_string())
+        );
+        assert_eq!(command_name(Path::new("README")), None);
+    }
+
+    #[test]
+    fn parses_tagged_paragraphs() {
+        let page = r#".TH LS "1"
+.SH DESCRIPTION
+.PP
+List information about the FILEs.
+.TP
+\fB\-a\fR, \fB\-\-all\fR
+do not ignore entries starting with .
+.TP
+\fB\-\-color\fR[=\fI\,WHEN\/\fR]
+color the output WHEN; more info below
+.IP "\-v" 4
+Print
+.B more
+details. And even more.
+.SH SEE ALSO
+"#;
+        assert_eq!(
+            parse_options(page),
+            owned(&[
+                ("-a", "do not ignore entries starting with ."),
+                ("--all", "do not ignore entries starting with ."),
+                ("--color", "color the output WHEN; more info below"),
+                ("-v", "Print more details"),
+            ])
+        );
+    }
+
+    #[test]
+    fn parses_mdoc_and_indented_paragraphs() {
+        let page = ".Nm ssh\n.Bl -tag -width Ds\n.It Fl 4\nForces\n.Nm\n\
+                    to use IPv4 addresses only, like\n.Fl o Cm AddressFamily .\n.El\n\
+                    .PP\n\\-\\-dry\\-run\n.RS 4\nShow what would be done.\n.RE\n";
+        assert_eq!(
+            parse_options(page),
+            owned(&[
+                (
+                    "-4",
+                    "Forces ssh to use IPv4 addresses only, like -o AddressFamily"
+                ),
+                ("--dry-run", "Show what would be done"),
+            ])
+        );
+    }
+
+    fn owned(options: &[(&str, &str)]) -> Vec<(String, String)> {
+        options
+            .iter()
+            .map(|(option, description)| (option.to_string(), description.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn cuts_long_sentences_by_chars() {
+        assert_eq!(
+            first_sentence("list all entries. Do not ignore entries starting with ."),
+            "list all entries"
+        );
+        let long = "é".repeat(DESCRIPTION_LENGTH);
+        assert_eq!(first_sentence(&long), long);
+        assert_eq!(first_sentence(&format!("{}. More", long)), long);
+        assert_eq!(first_sentence(&format!("{}é", long)), format!("{}…", long));
+    }
+}
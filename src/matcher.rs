@@ -0,0 +1,276 @@
+//! Matching what was typed against candidates, for completion and history search.
+//!
+//! Prefix matching is exact. Substring and fuzzy matching ignore case unless the pattern has an
+//! uppercase letter. Fuzzy matching accepts the chars of the pattern in order with anything
+//! between them, like `mnrs` for `main.rs`, and scores the matches so that the ones at the start
+//! of words and in runs rank first.
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    #[default]
+    Prefix,
+    Substring,
+    Fuzzy,
+}
+
+/// Where the pattern matched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// Higher is better
+    pub score: i64,
+    /// Byte offsets of the chars of the text that matched
+    pub positions: Vec<usize>,
+}
+
+/// Score of each matched char
+const MATCH: i64 = 16;
+/// Extra score of a char at the start of a word, like `m` in `src/main.rs` or `B` in `GapBuffer`
+const BOUNDARY: i64 = 8;
+/// Extra score of a char right after the previous match
+const CONSECUTIVE: i64 = 8;
+/// Cost of skipping chars between two matches, and of each char skipped
+const GAP_OPEN: i64 = 3;
+const GAP: i64 = 1;
+
+pub fn find(mode: MatchMode, pattern: &str, text: &str) -> Option<Match> {
+    match mode {
+        MatchMode::Prefix => text.starts_with(pattern).then(|| Match {
+            score: 0,
+            positions: pattern.char_indices().map(|(index, _)| index).collect(),
+        }),
+        MatchMode::Substring => substring(pattern, text),
+        MatchMode::Fuzzy => fuzzy(pattern, text),
+    }
+}
+
+/// Compares chars ignoring case unless the pattern has uppercase letters
+fn char_eq(ignore_case: bool) -> impl Fn(char, char) -> bool {
+    move |pattern: char, text: char| {
+        if ignore_case {
+            pattern == text || text.to_lowercase().eq(pattern.to_lowercase())
+        } else {
+            pattern == text
+        }
+    }
+}
+
+fn ignores_case(pattern: &str) -> bool {
+    !pattern.chars().any(char::is_uppercase)
+}
+
+/// The first occurrence of the pattern, scored higher the closer it is to the start
+fn substring(pattern: &str, text: &str) -> Option<Match> {
+    let eq = char_eq(ignores_case(pattern));
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<(usize, char)> = text.char_indices().collect();
+    if pattern.is_empty() {
+        return Some(Match {
+            score: 0,
+            positions: Vec::new(),
+        });
+    }
+    let start = text.windows(pattern.len()).position(|window| {
+        window
+            .iter()
+            .zip(&pattern)
+            .all(|((_, text), pattern)| eq(*pattern, *text))
+    })?;
+    Some(Match {
+        score: -(start as i64),
+        positions: text[start..start + pattern.len()]
+            .iter()
+            .map(|(index, _)| *index)
+            .collect(),
+    })
+}
+
+/// The best scoring way of matching the chars of the pattern in order, found with dynamic
+/// programming over the pattern and the text
+fn fuzzy(pattern: &str, text: &str) -> Option<Match> {
+    let eq = char_eq(ignores_case(pattern));
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<(usize, char)> = text.char_indices().collect();
+    let (rows, columns) = (pattern.len(), text.len());
+    if rows == 0 {
+        return Some(Match {
+            score: 0,
+            positions: Vec::new(),
+        });
+    }
+    if rows > columns {
+        return None;
+    }
+    let bonus: Vec<i64> = (0..columns)
+        .map(|column| {
+            let char = text[column].1;
+            let boundary = match column.checked_sub(1).map(|previous| text[previous].1) {
+                None => true,
+                Some(previous) => {
+                    !previous.is_alphanumeric() && char.is_alphanumeric()
+                        || previous.is_lowercase() && char.is_uppercase()
+                }
+            };
+            if boundary {
+                BOUNDARY
+            } else {
+                0
+            }
+        })
+        .collect();
+    // scores[row][column] is the best score with the char `row` of the pattern matched at
+    // `column`, and from[row][column] the column where the char before it matched
+    let mut scores: Vec<Vec<Option<i64>>> = vec![vec![None; columns]; rows];
+    let mut from = vec![vec![0; columns]; rows];
+    for row in 0..rows {
+        // best score of the previous row at least two columns back, plus GAP for each column
+        // before it, so that subtracting GAP for the columns before this one leaves the cost of
+        // the chars skipped
+        let mut gapped: Option<(i64, usize)> = None;
+        for column in row..columns {
+            if row > 0 && column >= 2 {
+                if let Some(score) = scores[row - 1][column - 2] {
+                    let score = score + GAP * (column - 2) as i64;
+                    if gapped.is_none_or(|(best, _)| score > best) {
+                        gapped = Some((score, column - 2));
+                    }
+                }
+            }
+            if !eq(pattern[row], text[column].1) {
+                continue;
+            }
+            let score = MATCH + bonus[column];
+            if row == 0 {
+                scores[row][column] = Some(score);
+                continue;
+            }
+            let consecutive =
+                scores[row - 1][column - 1].map(|previous| (previous + CONSECUTIVE, column - 1));
+            let gapped = gapped
+                .map(|(best, previous)| (best - GAP_OPEN - GAP * (column - 1) as i64, previous));
+            let previous = match (consecutive, gapped) {
+                (Some(consecutive), Some(gapped)) if gapped.0 > consecutive.0 => Some(gapped),
+                (Some(consecutive), _) => Some(consecutive),
+                (None, gapped) => gapped,
+            };
+            if let Some((previous_score, previous_column)) = previous {
+                scores[row][column] = Some(score + previous_score);
+                from[row][column] = previous_column;
+            }
+        }
+    }
+    let (mut column, score) = scores[rows - 1]
+        .iter()
+        .enumerate()
+        .filter_map(|(column, score)| score.map(|score| (column, score)))
+        .fold(
+            None,
+            |best: Option<(usize, i64)>, (column, score)| match best {
+                Some((_, best_score)) if best_score >= score => best,
+                _ => Some((column, score)),
+            },
+        )?;
+    let mut positions = vec![0; rows];
+    for row in (0..rows).rev() {
+        positions[row] = text[column].0;
+        column = from[row][column];
+    }
+    Some(Match { score, positions })
+}
+
+/// Number of chars to insert, delete, replace or swap with the next one to turn `a` into `b`
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // distances[i][j] is the distance between the first i chars of `a` and the first j of `b`
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edit_distance, find, MatchMode};
+
+    fn positions(mode: MatchMode, pattern: &str, text: &str) -> Option<Vec<usize>> {
+        find(mode, pattern, text).map(|found| found.positions)
+    }
+
+    #[test]
+    fn matches_prefixes_exactly() {
+        assert_eq!(
+            positions(MatchMode::Prefix, "ma", "main.rs"),
+            Some(vec![0, 1])
+        );
+        assert_eq!(positions(MatchMode::Prefix, "Ma", "main.rs"), None);
+    }
+
+    #[test]
+    fn matches_substrings_with_smart_case() {
+        assert_eq!(
+            positions(MatchMode::Substring, "rs", "main.rs"),
+            Some(vec![5, 6])
+        );
+        assert_eq!(
+            positions(MatchMode::Substring, "rs", "README.RS"),
+            Some(vec![7, 8])
+        );
+        assert_eq!(positions(MatchMode::Substring, "Rs", "main.rs"), None);
+    }
+
+    #[test]
+    fn matches_subsequences() {
+        assert_eq!(
+            positions(MatchMode::Fuzzy, "mnrs", "main.rs"),
+            Some(vec![0, 3, 5, 6])
+        );
+        assert_eq!(positions(MatchMode::Fuzzy, "sm", "main.rs"), None);
+        // the start of words is preferred to the first occurrence
+        assert_eq!(
+            positions(MatchMode::Fuzzy, "gb", "gap_buffer"),
+            Some(vec![0, 4])
+        );
+        assert_eq!(
+            positions(MatchMode::Fuzzy, "gb", "GapBuffer"),
+            Some(vec![0, 3])
+        );
+        assert_eq!(positions(MatchMode::Fuzzy, "é", "café"), Some(vec![3]));
+    }
+
+    #[test]
+    fn ranks_word_starts_and_runs_first() {
+        let score = |text| find(MatchMode::Fuzzy, "comp", text).unwrap().score;
+        assert!(score("completion.rs") > score("xcompletion.rs"));
+        assert!(score("xcompletion.rs") > score("c_o_m_p.rs"));
+        assert!(score("c_o_m_p.rs") > score("cxoxmxp.rs"));
+    }
+
+    #[test]
+    fn counts_edits() {
+        assert_eq!(edit_distance("cargo", "cargo"), 0);
+        assert_eq!(edit_distance("carg", "cargo"), 1);
+        assert_eq!(edit_distance("cagro", "cargo"), 1);
+        assert_eq!(edit_distance("sl", "ls"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "ls"), 2);
+    }
+}
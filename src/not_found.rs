@@ -0,0 +1,111 @@
+//! What happens when a command is not a builtin or a program in `PATH`.
+//!
+//! The `command_not_found_handler` of the config is a command that runs instead, with the name
+//! and the arguments of the command after it, like the function of the same name in zsh. Its exit
+//! status is the status of the command. Without a handler the shell says that the command was not
+//! found and suggests the commands and aliases with the closest names, for typos like `carg` or
+//! `gti`.
+
+use std::cell::RefCell;
+use std::process::Command;
+
+use crate::aliases;
+use crate::builtin::BUILTIN_NAMES;
+use crate::matcher::edit_distance;
+use crate::path_cache;
+
+/// Exit status of a command that was not found
+pub const NOT_FOUND: i32 = 127;
+
+/// Most suggestions shown for a command that was not found
+const MAX_SUGGESTIONS: usize = 3;
+
+thread_local! {
+    static HANDLER: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+pub fn set_handler(handler: Option<String>) {
+    HANDLER.with(|current| *current.borrow_mut() = handler);
+}
+
+/// Runs the handler for `program`, or reports it and suggests commands, and returns the exit
+/// status of the command
+pub fn handle(program: &str, args: &[String]) -> anyhow::Result<i32> {
+    let handler = HANDLER.with(|handler| handler.borrow().clone());
+    let Some(handler) = handler else {
+        eprintln!("shush: {}: command not found", program);
+        let names: Vec<String> = BUILTIN_NAMES
+            .iter()
+            .map(|name| name.to_string())
+            .chain(aliases::names())
+            .chain(path_cache::names())
+            .collect();
+        let suggestions = suggestions(program, &names);
+        if !suggestions.is_empty() {
+            let quoted: Vec<String> = suggestions
+                .iter()
+                .map(|name| format!("`{}`", name))
+                .collect();
+            eprintln!("shush: did you mean {}?", quoted.join(" or "));
+        }
+        return Ok(NOT_FOUND);
+    };
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", handler))
+        .arg("sh")
+        .arg(program)
+        .args(args)
+        .status()?;
+    Ok(status.code().unwrap_or(NOT_FOUND))
+}
+
+/// The names closest to `program`, if they are close enough to be typos of it: one edit away
+/// for short names and two for names of six chars or more
+fn suggestions(program: &str, names: &[String]) -> Vec<String> {
+    let max_distance = (program.chars().count() / 3).clamp(1, 2);
+    let mut close: Vec<(usize, &String)> = names
+        .iter()
+        .map(|name| (edit_distance(program, name), name))
+        .filter(|(distance, _)| (1..=max_distance).contains(distance))
+        .collect();
+    close.sort();
+    close.dedup();
+    let Some(&(best, _)) = close.first() else {
+        return Vec::new();
+    };
+    close
+        .into_iter()
+        .take_while(|(distance, _)| *distance == best)
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, name)| name.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{handle, set_handler, suggestions};
+
+    #[test]
+    fn suggests_the_closest_names() {
+        let names: Vec<String> = ["cargo", "cat", "cd", "git", "gitk", "ls", "python3"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        assert_eq!(suggestions("carg", &names), ["cargo"]);
+        assert_eq!(suggestions("gti", &names), ["git"]);
+        assert_eq!(suggestions("sl", &names), ["ls"]);
+        assert_eq!(suggestions("pyhton3", &names), ["python3"]);
+        assert!(suggestions("make", &names).is_empty());
+    }
+
+    #[test]
+    fn runs_the_handler_with_the_command() {
+        set_handler(Some(
+            r#"f() { [ "$1 $2" = "shush-missing arg" ] && exit 42; }; f"#.to_string(),
+        ));
+        assert_eq!(handle("shush-missing", &["arg".to_string()]).unwrap(), 42);
+        assert_eq!(handle("shush-missing", &[]).unwrap(), 1);
+        set_handler(None);
+    }
+}
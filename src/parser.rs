@@ -1,8 +1,396 @@
-/// Defining the grammar
-///
-/// The grammar of the Shush Shell is as follows:
-///
+//! Defining the grammar
+//!
+//! The grammar of the Shush Shell is as follows:
+//!
+//! ```text
+//! list        := and_or ((';' | '\n') and_or)* [';']
+//! and_or      := command (('&&' | '||') command)*
+//! command     := simple | subshell redirect* | group redirect*
+//! subshell    := '(' list ')'
+//! group       := '{' list '}'
+//! simple      := (word | redirect)+
+//! redirect    := ('>' | '>>') word
+//! ```
+//!
+//! `{` and `}` are reserved words, not operators: they are only recognized when they appear as a
+//! whole word, `{` in command position and `}` where a command could start.
 
-pub struct SyntaxTree {
+use std::fmt;
+use std::iter::Peekable;
+use std::vec::IntoIter;
 
+use anyhow::anyhow;
+
+use crate::aliases;
+use crate::evaluator::ShushCmd;
+use crate::lexer::{Lexer, Token, TokenShush};
+
+#[derive(Debug)]
+pub enum SyntaxTree {
+    Command(ShushCmd),
+    /// `( list )`, runs in a forked child so that changes to the shell state do not leak
+    Subshell(Box<SyntaxTree>, Vec<Redirect>),
+    /// `{ list; }`, runs in the current shell sharing its redirections
+    Group(Box<SyntaxTree>, Vec<Redirect>),
+    Sequence(Vec<SyntaxTree>),
+    And(Box<SyntaxTree>, Box<SyntaxTree>),
+    Or(Box<SyntaxTree>, Box<SyntaxTree>),
+}
+
+/// Output redirection to a file, `> path` truncates it and `>> path` appends to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub path: Word,
+    pub append: bool,
+}
+
+/// A word of a command, with its quotes removed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Word {
+    pub text: String,
+    /// Quote of the first char of the word, `\\` if it is escaped. A quoted `~` is not
+    /// expanded, and a `$` only in double quotes.
+    pub quote: Option<char>,
+}
+
+impl SyntaxTree {
+    /// Parses a full input line, with its aliases expanded. Returns `None` if the line has no
+    /// commands.
+    pub fn parse(line: &str) -> anyhow::Result<Option<SyntaxTree>> {
+        let tokens = aliases::expand(line, Lexer::tokenize(line));
+        let mut parser = Parser {
+            tokens: tokens.into_iter().peekable(),
+        };
+        parser.skip_separators();
+        if parser.at_end() {
+            return Ok(None);
+        }
+        let tree = parser.list()?;
+        match parser.tokens.next() {
+            None => Ok(Some(tree)),
+            Some(token) => Err(anyhow!("syntax error near unexpected {}", describe(&token))),
+        }
+    }
+}
+
+/// Whether `text` stops in the middle of a command, so Enter starts a continuation line instead
+/// of running it: inside quotes, after a trailing `\\`, `|`, `&&` or `||`, or with a subshell,
+/// group or `if` that is not closed yet.
+pub fn needs_more_input(text: &str) -> bool {
+    let mut lexer = Lexer::new();
+    text.chars().for_each(|char| lexer.lex(char));
+    if lexer.in_word() {
+        return true;
+    }
+    let mut depth = 0;
+    let mut command_start = true;
+    let mut last = None;
+    for token in Lexer::tokenize(text) {
+        let reserved_word = match token.content() {
+            Some(word) if command_start && token.token() == &Token::Item => word,
+            _ => "",
+        };
+        match (token.token(), reserved_word) {
+            (Token::OpenParen, _) | (_, "{" | "if") => depth += 1,
+            (Token::CloseParen, _) | (_, "}" | "fi") => depth -= 1,
+            _ => (),
+        }
+        command_start = token.starts_command(command_start);
+        if token.token() != &Token::Eof {
+            last = Some(token);
+        }
+    }
+    let continues = matches!(
+        last.as_ref().map(TokenShush::token),
+        Some(Token::Pipe | Token::And | Token::Or)
+    );
+    continues || depth > 0
+}
+
+struct Parser {
+    tokens: Peekable<IntoIter<TokenShush>>,
+}
+
+impl Parser {
+    fn list(&mut self) -> anyhow::Result<SyntaxTree> {
+        // a group or subshell can start with a newline, as in `{` on a line of its own
+        self.skip_separators();
+        let mut commands = vec![self.and_or()?];
+        loop {
+            if !self.skip_separators() || self.at_list_end() {
+                break;
+            }
+            commands.push(self.and_or()?);
+        }
+        if commands.len() == 1 {
+            Ok(commands.remove(0))
+        } else {
+            Ok(SyntaxTree::Sequence(commands))
+        }
+    }
+
+    fn and_or(&mut self) -> anyhow::Result<SyntaxTree> {
+        let mut tree = self.command()?;
+        loop {
+            let is_and = match self.peek_token() {
+                Some(Token::And) => true,
+                Some(Token::Or) => false,
+                _ => break,
+            };
+            self.tokens.next();
+            let right = Box::new(self.command()?);
+            tree = if is_and {
+                SyntaxTree::And(Box::new(tree), right)
+            } else {
+                SyntaxTree::Or(Box::new(tree), right)
+            };
+        }
+        Ok(tree)
+    }
+
+    fn command(&mut self) -> anyhow::Result<SyntaxTree> {
+        match self.tokens.peek() {
+            Some(token) if token.token() == &Token::OpenParen => {
+                self.tokens.next();
+                let body = self.list()?;
+                self.expect(Token::CloseParen, "`)`")?;
+                Ok(SyntaxTree::Subshell(Box::new(body), self.redirects()?))
+            }
+            Some(token) if is_word(token, "{") => {
+                self.tokens.next();
+                let body = self.list()?;
+                match self.tokens.next() {
+                    Some(token) if is_word(&token, "}") => (),
+                    _ => return Err(anyhow!("syntax error: expected `}}`")),
+                }
+                Ok(SyntaxTree::Group(Box::new(body), self.redirects()?))
+            }
+            _ => self.simple(),
+        }
+    }
+
+    fn simple(&mut self) -> anyhow::Result<SyntaxTree> {
+        let mut words = Vec::new();
+        let mut redirects = Vec::new();
+        loop {
+            match self.peek_token() {
+                Some(Token::Item) | Some(Token::EnvVar) => {
+                    words.push(self.word()?);
+                }
+                Some(Token::OutGreaterThan) | Some(Token::AppendGreaterThan) => {
+                    redirects.push(self.redirect()?);
+                }
+                Some(Token::Pipe) => return Err(anyhow!("pipelines are not supported yet")),
+                _ => break,
+            }
+        }
+        match ShushCmd::parse_command(words, redirects) {
+            Some(cmd) => Ok(SyntaxTree::Command(cmd)),
+            None => match self.tokens.next() {
+                Some(token) => Err(anyhow!("syntax error near unexpected {}", describe(&token))),
+                None => Err(anyhow!("syntax error: unexpected end of input")),
+            },
+        }
+    }
+
+    fn redirects(&mut self) -> anyhow::Result<Vec<Redirect>> {
+        let mut redirects = Vec::new();
+        while let Some(Token::OutGreaterThan) | Some(Token::AppendGreaterThan) = self.peek_token()
+        {
+            redirects.push(self.redirect()?);
+        }
+        Ok(redirects)
+    }
+
+    fn redirect(&mut self) -> anyhow::Result<Redirect> {
+        let append = matches!(
+            self.tokens.next().as_ref().map(TokenShush::token),
+            Some(Token::AppendGreaterThan)
+        );
+        match self.peek_token() {
+            Some(Token::Item) | Some(Token::EnvVar) => Ok(Redirect {
+                path: self.word()?,
+                append,
+            }),
+            _ => Err(anyhow!("syntax error: expected a file name after redirection")),
+        }
+    }
+
+    fn word(&mut self) -> anyhow::Result<Word> {
+        let token = self
+            .tokens
+            .next()
+            .ok_or_else(|| anyhow!("syntax error: expected a word"))?;
+        let content = token.content().unwrap_or_default();
+        let text = match token.token() {
+            Token::EnvVar => format!("${}", content),
+            _ => content.to_string(),
+        };
+        Ok(Word {
+            text,
+            quote: token.quote(),
+        })
+    }
+
+    fn expect(&mut self, expected: Token, name: &str) -> anyhow::Result<()> {
+        match self.tokens.next() {
+            Some(token) if token.token() == &expected => Ok(()),
+            _ => Err(anyhow!("syntax error: expected {}", name)),
+        }
+    }
+
+    /// Skips `;` and newlines. Returns whether there was any separator.
+    fn skip_separators(&mut self) -> bool {
+        let mut skipped = false;
+        while let Some(Token::Semicolon) | Some(Token::Eof) = self.peek_token() {
+            self.tokens.next();
+            skipped = true;
+        }
+        skipped
+    }
+
+    /// A list ends at the end of input or before the token that closes a subshell or group.
+    fn at_list_end(&mut self) -> bool {
+        match self.tokens.peek() {
+            None => true,
+            Some(token) => token.token() == &Token::CloseParen || is_word(token, "}"),
+        }
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.tokens.peek().is_none()
+    }
+
+    fn peek_token(&mut self) -> Option<&Token> {
+        self.tokens.peek().map(|token| token.token())
+    }
+}
+
+fn is_word(token: &TokenShush, word: &str) -> bool {
+    token.token() == &Token::Item && token.content() == Some(word)
+}
+
+fn describe(token: &TokenShush) -> String {
+    match token.token() {
+        Token::Item | Token::EnvVar => format!("word `{}`", token.content().unwrap_or_default()),
+        Token::OutGreaterThan => "`>`".to_string(),
+        Token::AppendGreaterThan => "`>>`".to_string(),
+        Token::OpenParen => "`(`".to_string(),
+        Token::CloseParen => "`)`".to_string(),
+        Token::Semicolon => "`;`".to_string(),
+        Token::Pipe => "`|`".to_string(),
+        Token::Ampersand => "`&`".to_string(),
+        Token::And => "`&&`".to_string(),
+        Token::Or => "`||`".to_string(),
+        Token::Eof => "newline".to_string(),
+    }
+}
+
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operator = if self.append { ">>" } else { ">" };
+        write!(f, "{} {}", operator, self.path)
+    }
+}
+
+impl fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyntaxTree::Command(cmd) => write!(f, "{}", cmd),
+            SyntaxTree::Subshell(body, redirects) => {
+                write!(f, "( {} )", body)?;
+                for redirect in redirects {
+                    write!(f, " {}", redirect)?;
+                }
+                Ok(())
+            }
+            SyntaxTree::Group(body, redirects) => {
+                write!(f, "{{ {}; }}", body)?;
+                for redirect in redirects {
+                    write!(f, " {}", redirect)?;
+                }
+                Ok(())
+            }
+            SyntaxTree::Sequence(commands) => {
+                for (i, cmd) in commands.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}", cmd)?;
+                }
+                Ok(())
+            }
+            SyntaxTree::And(left, right) => write!(f, "{} && {}", left, right),
+            SyntaxTree::Or(left, right) => write!(f, "{} || {}", left, right),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{needs_more_input, SyntaxTree};
+
+    #[test]
+    fn parses_subshell_and_group() {
+        let tree = SyntaxTree::parse("( cd dir && make ); { a; b; } > out")
+            .unwrap()
+            .unwrap();
+        assert_eq!(tree.to_string(), "( cd dir && make ); { a; b; } > out");
+        let tree = SyntaxTree::parse("{\n  a\n  b\n}").unwrap().unwrap();
+        assert_eq!(tree.to_string(), "{ a; b; }");
+    }
+
+    #[test]
+    fn rejects_unclosed_group() {
+        assert!(SyntaxTree::parse("{ a; b").is_err());
+        assert!(SyntaxTree::parse("( a").is_err());
+        assert!(SyntaxTree::parse("a )").is_err());
+    }
+
+    #[test]
+    fn rejects_pipelines() {
+        for line in ["ls | wc", "ls |"] {
+            let error = SyntaxTree::parse(line).unwrap_err().to_string();
+            assert_eq!(error, "pipelines are not supported yet");
+        }
+    }
+
+    #[test]
+    fn detects_unfinished_input() {
+        for text in [
+            "echo 'a",
+            "echo \"a\nb",
+            "ls \\",
+            "ls |",
+            "make &&",
+            "false ||\n",
+            "{ a; b",
+            "( a\n",
+            "if true; then",
+        ] {
+            assert!(needs_more_input(text), "{:?}", text);
+        }
+        for text in [
+            "echo 'a' \"b\"",
+            "ls \\\nfoo",
+            "echo {",
+            "{ a; }",
+            "( a )",
+            "echo if",
+            "if true; then a; fi",
+        ] {
+            assert!(!needs_more_input(text), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn empty_line_has_no_commands() {
+        assert!(SyntaxTree::parse("  ; ").unwrap().is_none());
+    }
 }
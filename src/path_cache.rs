@@ -0,0 +1,226 @@
+//! Where the programs run by name are, looked up in `PATH` once and remembered, like the hash
+//! table of bash.
+//!
+//! A program is looked up the first time it runs and its path is reused after that, until `PATH`
+//! changes or `hash -r` forgets it. The names of all the programs in `PATH` are also kept, for
+//! completion and to show unknown commands in the line.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::aliases;
+use crate::builtin::BUILTIN_NAMES;
+
+/// A program that was run, with how many times
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hashed {
+    pub path: PathBuf,
+    pub hits: usize,
+}
+
+#[derive(Debug, Default)]
+struct PathCache {
+    /// `PATH` when the cache was filled
+    path: Option<OsString>,
+    hashed: BTreeMap<String, Hashed>,
+    /// Names of the programs in `PATH`, read the first time they are needed
+    names: Option<HashSet<String>>,
+}
+
+impl PathCache {
+    /// Empties the cache if `PATH` changed since it was filled
+    fn update(&mut self, path: Option<OsString>) {
+        if self.path != path {
+            *self = PathCache {
+                path,
+                ..PathCache::default()
+            };
+        }
+    }
+
+    fn search_path(&self) -> &OsStr {
+        self.path.as_deref().unwrap_or_default()
+    }
+
+    fn resolve(&mut self, name: &str) -> Option<PathBuf> {
+        let hashed = match self.hashed.get(name) {
+            Some(hashed) if is_executable(&hashed.path) => self.hashed.get_mut(name)?,
+            _ => {
+                let path = find_in(name, self.search_path()).into_iter().next()?;
+                let hashed = Hashed { path, hits: 0 };
+                self.hashed.insert(name.to_string(), hashed);
+                self.hashed.get_mut(name)?
+            }
+        };
+        hashed.hits += 1;
+        Some(hashed.path.clone())
+    }
+
+    fn remember(&mut self, name: &str) -> Option<PathBuf> {
+        let path = find_in(name, self.search_path()).into_iter().next()?;
+        let hits = self.hashed.get(name).map_or(0, |hashed| hashed.hits);
+        let hashed = Hashed {
+            path: path.clone(),
+            hits,
+        };
+        self.hashed.insert(name.to_string(), hashed);
+        Some(path)
+    }
+
+    fn names(&mut self) -> &HashSet<String> {
+        let search_path = self.search_path().to_os_string();
+        self.names.get_or_insert_with(|| read_names(&search_path))
+    }
+
+    fn is_command(&mut self, name: &str) -> bool {
+        self.hashed.contains_key(name) || self.names().contains(name)
+    }
+}
+
+thread_local! {
+    static CACHE: RefCell<PathCache> = RefCell::new(PathCache::default());
+}
+
+fn with_cache<T>(f: impl FnOnce(&mut PathCache) -> T) -> T {
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        cache.update(std::env::var_os("PATH"));
+        f(&mut cache)
+    })
+}
+
+/// Path of the program to run for `name`, counting it as a hit. Names with a `/` are paths
+/// already. A remembered path that is gone is looked up again.
+pub fn resolve(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        return Some(PathBuf::from(name));
+    }
+    with_cache(|cache| cache.resolve(name))
+}
+
+/// Looks up `name` and remembers its path without counting a hit, for `hash name`
+pub fn remember(name: &str) -> Option<PathBuf> {
+    with_cache(|cache| cache.remember(name))
+}
+
+/// The remembered path of `name`, if it ran before
+pub fn hashed(name: &str) -> Option<PathBuf> {
+    with_cache(|cache| cache.hashed.get(name).map(|hashed| hashed.path.clone()))
+}
+
+/// The remembered programs, sorted by name
+pub fn list() -> Vec<(String, Hashed)> {
+    with_cache(|cache| {
+        cache
+            .hashed
+            .iter()
+            .map(|(name, hashed)| (name.clone(), hashed.clone()))
+            .collect()
+    })
+}
+
+/// Forgets every path, for `hash -r`
+pub fn clear() {
+    with_cache(|cache| {
+        cache.hashed.clear();
+        cache.names = None;
+    });
+}
+
+/// Every program called `name` in `PATH`, in the order they are searched
+pub fn find_all(name: &str) -> Vec<PathBuf> {
+    find_in(name, &std::env::var_os("PATH").unwrap_or_default())
+}
+
+fn find_in(name: &str, search_path: &OsStr) -> Vec<PathBuf> {
+    if name.is_empty() || name.contains('/') {
+        return Vec::new();
+    }
+    std::env::split_paths(search_path)
+        .map(|dir| dir.join(name))
+        .filter(|path| is_executable(path))
+        .collect()
+}
+
+/// Names of the programs in `PATH`
+pub fn names() -> Vec<String> {
+    with_cache(|cache| cache.names().iter().cloned().collect())
+}
+
+fn read_names(search_path: &OsStr) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for dir in std::env::split_paths(search_path) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if is_executable(&entry.path()) {
+                names.insert(name);
+            }
+        }
+    }
+    names
+}
+
+/// Whether `name` runs something: an alias, a builtin, a program in `PATH` or the path of a
+/// program
+pub fn is_command(name: &str) -> bool {
+    if BUILTIN_NAMES.contains(&name) || aliases::get(name).is_some() {
+        return true;
+    }
+    if name.contains('/') {
+        return is_executable(Path::new(name));
+    }
+    with_cache(|cache| cache.is_command(name))
+}
+
+pub fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::PathCache;
+
+    #[test]
+    fn remembers_programs_until_path_changes() {
+        let dir = std::env::temp_dir().join("shush_path_cache_test");
+        let (first, second) = (dir.join("first"), dir.join("second"));
+        for bin in [&first, &second] {
+            std::fs::create_dir_all(bin).unwrap();
+            let program = bin.join("shush-test-program");
+            std::fs::write(&program, "#!/bin/sh\n").unwrap();
+            std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let mut cache = PathCache::default();
+        cache.update(Some(std::env::join_paths([&first, &second]).unwrap()));
+
+        let program = first.join("shush-test-program");
+        assert_eq!(cache.resolve("shush-test-program"), Some(program.clone()));
+        assert_eq!(cache.resolve("shush-test-program"), Some(program.clone()));
+        assert_eq!(cache.hashed["shush-test-program"].hits, 2);
+        assert!(cache.is_command("shush-test-program"));
+        assert!(!cache.is_command("shush-no-such-program"));
+        // a remembered program that was removed is looked up again
+        std::fs::remove_file(&program).unwrap();
+        let program = second.join("shush-test-program");
+        assert_eq!(cache.resolve("shush-test-program"), Some(program.clone()));
+        assert_eq!(cache.hashed["shush-test-program"].hits, 1);
+
+        cache.update(Some(std::env::join_paths([&first]).unwrap()));
+        assert!(cache.hashed.is_empty());
+        assert_eq!(cache.resolve("shush-test-program"), None);
+        assert!(!cache.is_command("shush-test-program"));
+    }
+}
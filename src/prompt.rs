@@ -0,0 +1,261 @@
+//! Prompt rendering from `PS1`-like templates.
+//!
+//! | Escape | Expands to                                                 |
+//! |--------|------------------------------------------------------------|
+//! | `\u`   | user name                                                  |
+//! | `\h`   | host name up to the first `.`, `\H` for the full host name |
+//! | `\w`   | current directory with `$HOME` abbreviated to `~`          |
+//! | `\W`   | last component of the current directory                    |
+//! | `\g`   | current git branch, empty outside of a repository          |
+//! | `\?`   | exit status of the last command                            |
+//! | `\t`   | time as `HH:MM:SS`, `\A` for `HH:MM`                       |
+//! | `\j`   | number of jobs                                             |
+//! | `\$`   | `#` for root, `$` otherwise                                |
+//! | `\e`   | escape char, to start ANSI color sequences like `\e[32m`   |
+//! | `\n`   | newline                                                    |
+//! | `\[`   | starts a sequence of chars that take no space on screen    |
+//! | `\]`   | ends it                                                    |
+//! | `\\`   | a backslash                                                |
+
+use std::ffi::CStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::config::{home_dir, Config};
+
+/// State of the shell that prompts can show.
+#[derive(Debug, Default)]
+pub struct PromptContext {
+    pub last_status: i32,
+    pub jobs: usize,
+}
+
+/// A prompt ready to be written to the terminal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prompt {
+    pub text: String,
+    /// Columns taken by the last line of the prompt, escape sequences take none
+    pub width: usize,
+}
+
+impl Prompt {
+    pub fn new(template: &str, context: &PromptContext) -> Prompt {
+        let mut text = String::new();
+        // the text inside `\[ \]` is left out to compute the width
+        let mut visible = String::new();
+        let mut invisible = false;
+        let mut chars = template.chars();
+        while let Some(char) = chars.next() {
+            if char != '\\' {
+                text.push(char);
+                if !invisible {
+                    visible.push(char);
+                }
+                continue;
+            }
+            let expansion = match chars.next() {
+                Some('u') => user(),
+                Some('h') => host().split('.').next().unwrap_or_default().to_string(),
+                Some('H') => host(),
+                Some('w') => abbreviate_home(&current_dir()),
+                Some('W') => current_dir()
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or("/".to_string()),
+                Some('g') => git_branch(&current_dir()).unwrap_or_default(),
+                Some('?') => context.last_status.to_string(),
+                Some('t') => local_time("%H:%M:%S"),
+                Some('A') => local_time("%H:%M"),
+                Some('j') => context.jobs.to_string(),
+                Some('$') => {
+                    let root = unsafe { libc::geteuid() } == 0;
+                    if root { "#" } else { "$" }.to_string()
+                }
+                Some('e') => "\x1B".to_string(),
+                Some('n') => "\n".to_string(),
+                Some('\\') => "\\".to_string(),
+                Some('[') => {
+                    invisible = true;
+                    continue;
+                }
+                Some(']') => {
+                    invisible = false;
+                    continue;
+                }
+                Some(other) => format!("\\{}", other),
+                None => "\\".to_string(),
+            };
+            text.push_str(&expansion);
+            if !invisible {
+                visible.push_str(&expansion);
+            }
+        }
+        Prompt {
+            text,
+            width: display_width(&visible),
+        }
+    }
+
+    /// `PS1` from the environment, or the prompt from the config.
+    pub fn ps1(config: &Config, context: &PromptContext) -> Prompt {
+        let template = std::env::var("PS1").unwrap_or(config.prompt.clone());
+        Prompt::new(&template, context)
+    }
+
+    /// `PS2` from the environment, or the continuation prompt from the config.
+    pub fn ps2(config: &Config, context: &PromptContext) -> Prompt {
+        let template = std::env::var("PS2").unwrap_or(config.continuation_prompt.clone());
+        Prompt::new(&template, context)
+    }
+
+    /// `RPROMPT` from the environment, or the right prompt from the config.
+    pub fn rprompt(config: &Config, context: &PromptContext) -> Option<Prompt> {
+        let template = std::env::var("RPROMPT")
+            .ok()
+            .or(config.right_prompt.clone())?;
+        Some(Prompt::new(&template, context))
+    }
+}
+
+/// Columns taken by the last line of `text` on the terminal. ANSI escape sequences take none.
+pub fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars().peekable();
+    while let Some(char) = chars.next() {
+        match char {
+            '\n' => width = 0,
+            // CSI sequences end with a char in the range `@` to `~`
+            '\x1B' if chars.peek() == Some(&'[') => {
+                chars.next();
+                for char in chars.by_ref() {
+                    if ('@'..='~').contains(&char) {
+                        break;
+                    }
+                }
+            }
+            _ => width += char.width().unwrap_or(0),
+        }
+    }
+    width
+}
+
+fn current_dir() -> PathBuf {
+    std::env::current_dir().unwrap_or_default()
+}
+
+fn abbreviate_home(path: &Path) -> String {
+    let home = home_dir();
+    if home.as_os_str().is_empty() {
+        return path.display().to_string();
+    }
+    match path.strip_prefix(&home) {
+        Ok(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Ok(rest) => format!("~/{}", rest.display()),
+        Err(_) => path.display().to_string(),
+    }
+}
+
+fn user() -> String {
+    if let Ok(user) = std::env::var("USER") {
+        return user;
+    }
+    let passwd = unsafe { libc::getpwuid(libc::getuid()) };
+    if passwd.is_null() {
+        return String::new();
+    }
+    unsafe { CStr::from_ptr((*passwd).pw_name) }
+        .to_string_lossy()
+        .to_string()
+}
+
+fn host() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return String::new();
+    }
+    CStr::from_bytes_until_nul(&buf)
+        .map(|host| host.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Finds the branch checked out in the repository containing `dir` by reading `.git/HEAD`, so
+/// no process has to be spawned on each prompt. A detached HEAD shows the short commit hash.
+fn git_branch(dir: &Path) -> Option<String> {
+    let git = dir.ancestors().map(|dir| dir.join(".git")).find(|git| git.exists())?;
+    let git_dir = if git.is_file() {
+        // worktrees and submodules have a `gitdir: <path>` file instead of a directory
+        let contents = fs::read_to_string(&git).ok()?;
+        let path = PathBuf::from(contents.strip_prefix("gitdir:")?.trim());
+        git.parent()?.join(path)
+    } else {
+        git
+    };
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: refs/heads/") {
+        Some(branch) => Some(branch.to_string()),
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+fn local_time(format: &str) -> String {
+    let mut buf = [0u8; 64];
+    let format = std::ffi::CString::new(format).unwrap_or_default();
+    let written = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        libc::strftime(
+            buf.as_mut_ptr() as *mut libc::c_char,
+            buf.len(),
+            format.as_ptr(),
+            &tm,
+        )
+    };
+    String::from_utf8_lossy(&buf[..written]).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{git_branch, Prompt, PromptContext};
+
+    #[test]
+    fn expands_escapes() {
+        let context = PromptContext {
+            last_status: 127,
+            jobs: 2,
+        };
+        let prompt = Prompt::new(r"[\?] \j \\ \x", &context);
+        assert_eq!(prompt.text, r"[127] 2 \ \x");
+        assert_eq!(prompt.width, 12);
+    }
+
+    #[test]
+    fn colors_take_no_space() {
+        let context = PromptContext::default();
+        let prompt = Prompt::new(r"\[\e[1;32m\]ok\e[0m 🤫> ", &context);
+        assert_eq!(prompt.text, "\x1B[1;32mok\x1B[0m 🤫> ");
+        assert_eq!(prompt.width, 7);
+    }
+
+    #[test]
+    fn width_counts_last_line() {
+        let prompt = Prompt::new(r"long first line\n> ", &PromptContext::default());
+        assert_eq!(prompt.width, 2);
+    }
+
+    #[test]
+    fn reads_git_branch() {
+        let repo = std::env::temp_dir().join("shush_prompt_git_test");
+        let nested = repo.join("src").join("deep");
+        std::fs::create_dir_all(repo.join(".git")).unwrap();
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(repo.join(".git").join("HEAD"), "ref: refs/heads/feature/x\n").unwrap();
+        assert_eq!(git_branch(&nested).as_deref(), Some("feature/x"));
+
+        std::fs::write(repo.join(".git").join("HEAD"), "0123456789abcdef\n").unwrap();
+        assert_eq!(git_branch(&repo).as_deref(), Some("0123456"));
+    }
+}
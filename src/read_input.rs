@@ -1,43 +1,105 @@
-use std::io::Error;
 use std::os::fd::{IntoRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use std::{
     error,
     io::{self, Write},
 };
 use termios::*;
 
+use crate::keys::{Decoder, Key, KeyPress};
+
+/// Set by the `SIGWINCH` handler, the size is read again before the next key
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigwinch(_signal: libc::c_int) {
+    RESIZED.store(true, Ordering::Relaxed);
+}
+
+/// Size of the terminal window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowSize {
+    pub columns: usize,
+    pub lines: usize,
+}
+
+impl WindowSize {
+    /// The size reported by `TIOCGWINSZ`, or the default for terminals that report zero
+    fn from_winsize(winsize: &libc::winsize) -> WindowSize {
+        if winsize.ws_col == 0 || winsize.ws_row == 0 {
+            return WindowSize::default();
+        }
+        WindowSize {
+            columns: winsize.ws_col as usize,
+            lines: winsize.ws_row as usize,
+        }
+    }
+}
+
+impl Default for WindowSize {
+    fn default() -> Self {
+        Self {
+            columns: 80,
+            lines: 24,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct IO {
     stdin_raw: RawFd,
-    stdin: io::Stdin,
+    decoder: Decoder,
     stdout: io::Stdout,
     stderr: io::Stderr,
+    size: WindowSize,
 }
 
 impl IO {
-    /// Read byte to byte using libc::read()
-    pub fn read_keypress(&mut self) -> Result<Vec<u8>, Error> {
-        let mut vec = Vec::<u8>::new();
+    /// Reads the next key press. Returns `None` at the end of the input, and `Key::Resize` when
+    /// the terminal changed size.
+    pub fn read_key(&mut self) -> io::Result<Option<KeyPress>> {
         loop {
-            let mut buf = [0; 1];
-            let res =
-                unsafe { libc::read(self.stdin_raw, buf.as_mut_ptr() as *mut libc::c_void, 1) };
-            if res == 0 {
-                break;
-            } else if res == 1 {
-                vec.extend_from_slice(&buf);
-                break;
-            } else {
-                let error = io::Error::last_os_error();
-                return Err(error);
+            if RESIZED.swap(false, Ordering::Relaxed) && self.update_size() {
+                return Ok(Some(KeyPress::new(Key::Resize)));
+            }
+            if let Some(key) = self.decoder.next_key() {
+                return Ok(Some(key));
+            }
+            // a pending `ESC` is the Esc key if nothing follows it soon
+            match self.read_bytes(self.decoder.timeout_after()) {
+                Ok(Some(bytes)) if bytes.is_empty() => return Ok(None),
+                Ok(Some(bytes)) => self.decoder.feed(&bytes),
+                Ok(None) => self.decoder.timeout(),
+                // a signal like `SIGWINCH` stopped the wait
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
             }
         }
-        Ok(vec)
     }
 
-    pub fn read_from_stdin(&self) -> io::Result<String> {
-        let buffer = String::new();
-        Ok(buffer)
+    /// Reads the bytes available, waiting for them up to `timeout` or forever if there is none.
+    /// Returns `None` if the timeout passed, and no bytes at the end of the input.
+    fn read_bytes(&mut self, timeout: Option<Duration>) -> io::Result<Option<Vec<u8>>> {
+        let mut poll_fd = libc::pollfd {
+            fd: self.stdin_raw,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.map_or(-1, |timeout| timeout.as_millis() as libc::c_int);
+        let res = unsafe { libc::poll(&mut poll_fd, 1, timeout) };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        } else if res == 0 {
+            return Ok(None);
+        }
+        let mut buf = [0; 256];
+        let res =
+            unsafe { libc::read(self.stdin_raw, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if res < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(Some(buf[..res as usize].to_vec()))
+        }
     }
 
     pub fn write_to_stdout(&mut self, output: &[u8]) -> io::Result<()> {
@@ -55,16 +117,13 @@ impl IO {
     pub fn build_io() -> Result<Self, std::io::Error> {
         Ok(Self {
             stdin_raw: Self::tty_fd()?,
-            stdin: io::stdin(),
+            decoder: Decoder::default(),
             stdout: io::stdout(),
             stderr: io::stderr(),
+            size: WindowSize::default(),
         })
     }
 
-    pub fn flush_stdout(&mut self) -> io::Result<()> {
-        self.stdout.flush()
-    }
-
     pub fn get_termios(&mut self) -> Result<Termios, Box<dyn error::Error>> {
         let og_termios = Termios::from_fd(self.stdin_raw)?;
         Ok(og_termios)
@@ -76,6 +135,53 @@ impl IO {
         Ok(())
     }
 
+    /// With bracketed paste the terminal wraps pasted text in `ESC[200~` and `ESC[201~`, so it is
+    /// inserted as is instead of being read as keys
+    pub fn enable_bracketed_paste(&mut self) -> io::Result<()> {
+        self.write_to_stdout(b"\x1b[?2004h")
+    }
+
+    pub fn disable_bracketed_paste(&mut self) -> io::Result<()> {
+        self.write_to_stdout(b"\x1b[?2004l")
+    }
+
+    /// Reads the window size and keeps it up to date by handling `SIGWINCH`
+    pub fn watch_window_size(&mut self) -> io::Result<()> {
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = on_sigwinch as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        // `SA_RESTART` keeps the signal from breaking the wait for a command, `poll` returns
+        // early anyway so that the line is drawn again
+        action.sa_flags = libc::SA_RESTART;
+        unsafe { libc::sigemptyset(&mut action.sa_mask) };
+        if unsafe { libc::sigaction(libc::SIGWINCH, &action, std::ptr::null_mut()) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        self.update_size();
+        Ok(())
+    }
+
+    /// Reads the window size with `TIOCGWINSZ` and exports it as `COLUMNS` and `LINES`, so
+    /// programs started by the shell get it too. Returns whether it changed.
+    pub fn update_size(&mut self) -> bool {
+        let mut winsize: libc::winsize = unsafe { std::mem::zeroed() };
+        let res = unsafe { libc::ioctl(self.stdin_raw, libc::TIOCGWINSZ, &mut winsize) };
+        let size = if res == -1 {
+            WindowSize::default()
+        } else {
+            WindowSize::from_winsize(&winsize)
+        };
+        std::env::set_var("COLUMNS", size.columns.to_string());
+        std::env::set_var("LINES", size.lines.to_string());
+        let changed = size != self.size;
+        self.size = size;
+        changed
+    }
+
+    /// Width of the terminal in columns, 80 if it cannot be queried
+    pub fn columns(&self) -> usize {
+        self.size.columns
+    }
+
     fn tty_fd() -> io::Result<RawFd> {
         let fd: RawFd = if unsafe { libc::isatty(libc::STDIN_FILENO) == 1 } {
             libc::STDIN_FILENO
@@ -95,3 +201,63 @@ impl IO {
         Ok(tcsetattr(self.stdin_raw, TCSANOW, termios)?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::atomic::Ordering;
+
+    use super::{WindowSize, IO, RESIZED};
+    use crate::keys::{Decoder, Key, KeyPress};
+
+    fn winsize(columns: u16, lines: u16) -> libc::winsize {
+        libc::winsize {
+            ws_row: lines,
+            ws_col: columns,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        }
+    }
+
+    #[test]
+    fn falls_back_to_80_by_24() {
+        let size = WindowSize::from_winsize(&winsize(120, 40));
+        assert_eq!((size.columns, size.lines), (120, 40));
+        assert_eq!(
+            WindowSize::from_winsize(&winsize(0, 40)),
+            WindowSize::default()
+        );
+        assert_eq!(
+            WindowSize::default(),
+            WindowSize::from_winsize(&winsize(80, 24))
+        );
+    }
+
+    #[test]
+    fn reports_a_resize_before_the_next_key_and_exports_the_size() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut io = IO {
+            stdin_raw: fds[0],
+            decoder: Decoder::default(),
+            stdout: io::stdout(),
+            stderr: io::stderr(),
+            size: WindowSize {
+                columns: 100,
+                lines: 50,
+            },
+        };
+        assert_eq!(unsafe { libc::write(fds[1], b"a".as_ptr().cast(), 1) }, 1);
+        RESIZED.store(true, Ordering::Relaxed);
+        // a pipe has no size, so it is the default one
+        assert_eq!(io.read_key().unwrap(), Some(KeyPress::new(Key::Resize)));
+        assert_eq!(io.columns(), 80);
+        assert_eq!(std::env::var("COLUMNS").unwrap(), "80");
+        assert_eq!(std::env::var("LINES").unwrap(), "24");
+        assert_eq!(io.read_key().unwrap(), Some(KeyPress::new(Key::Char('a'))));
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+}
@@ -0,0 +1,463 @@
+//! Drawing the line without flicker. The editor describes the rows it wants on screen in a
+//! `Frame`, and the `Renderer` compares it with the frame on screen and writes only the cells
+//! that changed, with all the escape sequences of a frame in a single write.
+//!
+//! Frames start at the first column of a row, and rows are filled explicitly instead of letting
+//! the terminal wrap, so the renderer always knows where the terminal cursor is.
+
+use std::cmp;
+use std::fmt::Write;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// A row and column on the terminal, counting from the first row of the frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Position {
+    pub row: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Cell {
+    Blank,
+    Char {
+        /// SGR sequences in effect since the last reset, like `\e[1m\e[32m`
+        style: String,
+        /// Other escape sequences written right before the char, like setting the window title
+        raw: String,
+        text: String,
+    },
+    /// Second column of a wide char
+    Continuation,
+}
+
+/// The rows of the line as they should look on the terminal
+#[derive(Debug, Clone)]
+pub struct Frame {
+    columns: usize,
+    rows: Vec<Vec<Cell>>,
+    /// Where the next char goes. The column is `columns` right after a row is filled.
+    position: Position,
+    cursor: Option<Position>,
+    style: String,
+    raw: String,
+}
+
+impl Frame {
+    pub fn new(columns: usize) -> Frame {
+        Frame {
+            columns: cmp::max(columns, 1),
+            rows: vec![Vec::new()],
+            position: Position::default(),
+            cursor: None,
+            style: String::new(),
+            raw: String::new(),
+        }
+    }
+
+    /// Adds a prompt, whose escape sequences change the style of the chars after them
+    pub fn push_prompt(&mut self, text: &str) {
+        let mut chars = text.char_indices().peekable();
+        let mut start = 0;
+        while let Some((index, char)) = chars.next() {
+            if char != '\x1b' && char != '\n' {
+                continue;
+            }
+            self.push_text(&text[start..index]);
+            if char == '\n' {
+                self.new_row();
+                start = index + 1;
+                continue;
+            }
+            let end = match chars.next() {
+                // CSI sequences end with a char in the range `@` to `~`
+                Some((_, '[')) => chars.find(|(_, char)| ('@'..='~').contains(char)),
+                // OSC sequences end with BEL or `ESC \`
+                Some((_, ']')) => loop {
+                    match chars.next() {
+                        Some((index, '\x07')) => break Some((index, '\x07')),
+                        Some((_, '\x1b')) => break chars.next(),
+                        Some(_) => (),
+                        None => break None,
+                    }
+                },
+                next => next,
+            };
+            let end = end.map_or(text.len(), |(index, char)| index + char.len_utf8());
+            let sequence = &text[index..end];
+            if sequence.starts_with("\x1b[") && sequence.ends_with('m') {
+                if sequence == "\x1b[m" || sequence == "\x1b[0m" {
+                    self.style.clear();
+                } else {
+                    self.style.push_str(sequence);
+                }
+            } else {
+                self.raw.push_str(sequence);
+            }
+            start = end;
+        }
+        self.push_text(&text[start..]);
+    }
+
+    /// Adds text as is. Control chars are shown like `^C`, so they can not move the cursor.
+    pub fn push_text(&mut self, text: &str) {
+        for grapheme in text.graphemes(true) {
+            match grapheme.chars().next() {
+                Some(char @ ('\0'..='\x1f' | '\x7f')) => {
+                    let caret = if char == '\x7f' {
+                        '?'
+                    } else {
+                        (char as u8 + b'@') as char
+                    };
+                    self.push_grapheme("^");
+                    self.push_grapheme(&caret.to_string());
+                }
+                _ => self.push_grapheme(grapheme),
+            }
+        }
+    }
+
+    /// Adds text as is in the given style, instead of the style of the prompt before it
+    pub fn push_styled(&mut self, text: &str, style: &str) {
+        let previous = std::mem::replace(&mut self.style, style.to_string());
+        self.push_text(text);
+        self.style = previous;
+    }
+
+    fn push_grapheme(&mut self, grapheme: &str) {
+        // each grapheme cluster takes at most two columns, as terminals draw emoji sequences
+        // joined with ZWJ as a single wide char
+        let width = cmp::min(grapheme.width(), 2);
+        if width == 0 {
+            return;
+        }
+        // a wide char that does not fit at the end of a row goes to the next one
+        if self.position.column + width > self.columns {
+            self.new_row();
+        }
+        let cell = Cell::Char {
+            style: self.style.clone(),
+            raw: std::mem::take(&mut self.raw),
+            text: grapheme.to_string(),
+        };
+        let column = self.position.column;
+        let row = &mut self.rows[self.position.row];
+        row.resize(cmp::max(row.len(), column + width), Cell::Blank);
+        row[column] = cell;
+        if width == 2 {
+            row[column + 1] = Cell::Continuation;
+        }
+        self.position.column += width;
+    }
+
+    fn new_row(&mut self) {
+        self.position = Position {
+            row: self.position.row + 1,
+            column: 0,
+        };
+        if self.rows.len() == self.position.row {
+            self.rows.push(Vec::new());
+        }
+    }
+
+    /// Puts the cursor where the next char goes
+    pub fn set_cursor(&mut self) {
+        self.cursor = Some(self.wrapped_position());
+    }
+
+    /// Where the cursor goes, at the end of the frame if it was not set
+    pub fn cursor(&self) -> Position {
+        self.cursor.unwrap_or(self.wrapped_position())
+    }
+
+    fn wrapped_position(&self) -> Position {
+        if self.position.column >= self.columns {
+            Position {
+                row: self.position.row + 1,
+                column: 0,
+            }
+        } else {
+            self.position
+        }
+    }
+
+    /// Adds a prompt at the right end of the row, if the frame has a single row with room for
+    /// it and a space before it
+    pub fn push_right_prompt(&mut self, text: &str, width: usize) {
+        if self.rows.len() > 1 || self.position.column + width >= self.columns {
+            return;
+        }
+        let position = self.position;
+        let style = std::mem::take(&mut self.style);
+        self.position.column = self.columns - width;
+        self.push_prompt(text);
+        self.position = position;
+        self.style = style;
+    }
+
+    /// Rows the frame takes, including the row of the cursor
+    pub fn rows(&self) -> usize {
+        cmp::max(self.rows.len(), self.cursor().row + 1)
+    }
+}
+
+/// Writes frames, keeping track of the one on screen
+#[derive(Debug)]
+pub struct Renderer {
+    /// Rows of the frame on screen
+    screen: Vec<Vec<Cell>>,
+    /// Row of the terminal cursor
+    row: usize,
+    /// Column of the terminal cursor, unknown after filling a row since terminals wait for the
+    /// next char to wrap
+    column: Option<usize>,
+    /// Rows that exist on the terminal, moving the cursor down does not add rows at the bottom
+    /// of the screen
+    rows: usize,
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Renderer {
+            screen: Vec::new(),
+            row: 0,
+            column: Some(0),
+            rows: 1,
+        }
+    }
+}
+
+impl Renderer {
+    /// Escape sequences and text that turn the frame on screen into `frame`
+    pub fn render(&mut self, frame: &Frame) -> String {
+        let mut output = String::new();
+        let mut hidden = false;
+        let rows = frame.rows();
+        let screen = std::mem::take(&mut self.screen);
+        for row in 0..rows {
+            let new = frame.rows.get(row).map_or(&[][..], Vec::as_slice);
+            let old = screen.get(row).map_or(&[][..], Vec::as_slice);
+            let len = cmp::max(new.len(), old.len());
+            let mut column = 0;
+            while column < len {
+                if cell_at(new, column) == cell_at(old, column) {
+                    column += 1;
+                    continue;
+                }
+                if !hidden {
+                    output.push_str("\x1b[?25l");
+                    hidden = true;
+                }
+                self.move_to(&mut output, row, column);
+                if new[cmp::min(column, new.len())..]
+                    .iter()
+                    .all(|cell| *cell == Cell::Blank)
+                {
+                    output.push_str("\x1b[K");
+                    break;
+                }
+                let mut end = column;
+                while end < len && cell_at(new, end) != cell_at(old, end) {
+                    end += 1;
+                }
+                // a wide char can not be written in halves
+                if end < len && *cell_at(new, end) == Cell::Continuation {
+                    end += 1;
+                }
+                write_cells(&mut output, &new[column..cmp::min(end, new.len())]);
+                for _ in new.len()..end {
+                    output.push(' ');
+                }
+                self.column = (end < frame.columns).then_some(end);
+                column = end;
+            }
+        }
+        if screen.len() > rows {
+            if !hidden {
+                output.push_str("\x1b[?25l");
+                hidden = true;
+            }
+            self.move_to(&mut output, rows, 0);
+            output.push_str("\x1b[J");
+        }
+        let cursor = frame.cursor();
+        self.move_to(&mut output, cursor.row, cursor.column);
+        if hidden {
+            output.push_str("\x1b[?25h");
+        }
+        self.screen = frame.rows.clone();
+        self.screen.resize(rows, Vec::new());
+        output
+    }
+
+    /// Moves below the frame on screen and forgets it, so the next frame starts on a new row
+    pub fn finish(&mut self) -> String {
+        let mut output = String::new();
+        let last = self.screen.len().saturating_sub(1);
+        if last > self.row {
+            write!(output, "\x1b[{}B", last - self.row).unwrap();
+        }
+        output.push_str("\r\n");
+        self.reset();
+        output
+    }
+
+    /// Erases the frame on screen, which starts `row` rows above the terminal cursor
+    pub fn clear(&mut self, row: usize) -> String {
+        let mut output = String::new();
+        if row > 0 {
+            write!(output, "\x1b[{}A", row).unwrap();
+        }
+        output.push_str("\r\x1b[J");
+        self.reset();
+        output
+    }
+
+    /// Forgets the frame on screen, for when the terminal cursor is at the start of an empty row
+    pub fn reset(&mut self) {
+        *self = Renderer::default();
+    }
+
+    fn move_to(&mut self, output: &mut String, row: usize, column: usize) {
+        if row > self.row {
+            let last = self.rows - 1;
+            if last > self.row {
+                let down = cmp::min(row, last) - self.row;
+                write!(output, "\x1b[{}B", down).unwrap();
+                self.row += down;
+            }
+            while self.row < row {
+                output.push_str("\r\n");
+                self.row += 1;
+                self.column = Some(0);
+            }
+            self.rows = cmp::max(self.rows, row + 1);
+        } else if row < self.row {
+            write!(output, "\x1b[{}A", self.row - row).unwrap();
+            self.row = row;
+        }
+        if self.column != Some(column) {
+            write!(output, "\x1b[{}G", column + 1).unwrap();
+            self.column = Some(column);
+        }
+    }
+}
+
+static BLANK: Cell = Cell::Blank;
+
+/// The cell at `column`, blank past the end of the row
+fn cell_at(cells: &[Cell], column: usize) -> &Cell {
+    cells.get(column).unwrap_or(&BLANK)
+}
+
+/// Writes a run of cells, with the style of each one
+fn write_cells(output: &mut String, cells: &[Cell]) {
+    let mut current = "";
+    for cell in cells {
+        let (style, raw, text) = match cell {
+            Cell::Char { style, raw, text } => (style.as_str(), raw.as_str(), text.as_str()),
+            Cell::Blank => ("", "", " "),
+            Cell::Continuation => continue,
+        };
+        if style != current {
+            match style.strip_prefix(current) {
+                Some(added) => output.push_str(added),
+                None => {
+                    output.push_str("\x1b[0m");
+                    output.push_str(style);
+                }
+            }
+            current = style;
+        }
+        output.push_str(raw);
+        output.push_str(text);
+    }
+    if !current.is_empty() {
+        output.push_str("\x1b[0m");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Frame, Position, Renderer};
+
+    fn frame(prompt: &str, text: &str, columns: usize) -> Frame {
+        let mut frame = Frame::new(columns);
+        frame.push_prompt(prompt);
+        frame.push_text(text);
+        frame
+    }
+
+    #[test]
+    fn wraps_rows() {
+        let position = |row, column| Position { row, column };
+        let frame = frame("> ", "abcdef", 5);
+        assert_eq!(frame.cursor(), position(1, 3));
+        assert_eq!(frame.rows(), 2);
+        // the cursor goes to the next row once a row is filled
+        let frame = self::frame("> ", "abc", 5);
+        assert_eq!(frame.cursor(), position(1, 0));
+        assert_eq!(frame.rows(), 2);
+        // a wide char that does not fit goes to the next row
+        let frame = self::frame("> ", "ab日", 5);
+        assert_eq!(frame.cursor(), position(1, 2));
+        let frame = self::frame("\x1b[1;32m~\x1b[0m\n$ ", "x", 80);
+        assert_eq!(frame.cursor(), position(1, 3));
+    }
+
+    #[test]
+    fn renders_only_changes() {
+        let mut renderer = Renderer::default();
+        assert_eq!(
+            renderer.render(&frame("> ", "ls", 80)),
+            "\x1b[?25l> ls\x1b[?25h"
+        );
+        assert_eq!(
+            renderer.render(&frame("> ", "ls -", 80)),
+            "\x1b[?25l -\x1b[?25h"
+        );
+        assert_eq!(
+            renderer.render(&frame("> ", "l", 80)),
+            "\x1b[?25l\x1b[4G\x1b[K\x1b[?25h"
+        );
+        // moving the cursor writes no chars
+        let mut moved = self::frame("> ", "", 80);
+        moved.set_cursor();
+        moved.push_text("l");
+        assert_eq!(renderer.render(&moved), "\x1b[3G");
+    }
+
+    #[test]
+    fn renders_wrapped_rows() {
+        let mut renderer = Renderer::default();
+        renderer.render(&frame("> ", "abcd", 5));
+        // the new row is added with a newline, since moving down does not scroll
+        assert_eq!(
+            renderer.render(&frame("> ", "abcdefgh", 5)),
+            "\x1b[?25lefgh\r\n\x1b[?25h"
+        );
+        assert_eq!(
+            renderer.render(&frame("> ", "ab", 5)),
+            "\x1b[?25l\x1b[2A\x1b[5G\x1b[K\x1b[1B\x1b[1G\x1b[J\x1b[1A\x1b[5G\x1b[?25h"
+        );
+        assert_eq!(renderer.finish(), "\r\n");
+    }
+
+    #[test]
+    fn keeps_styles_of_prompts() {
+        let mut renderer = Renderer::default();
+        renderer.render(&frame("\x1b[32m>\x1b[0m ", "", 80));
+        let mut frame = frame("\x1b[32m>\x1b[0m ", "", 80);
+        frame.push_right_prompt("\x1b[2m12:00\x1b[0m", 5);
+        assert_eq!(
+            renderer.render(&frame),
+            "\x1b[?25l\x1b[76G\x1b[2m12:00\x1b[0m\x1b[3G\x1b[?25h"
+        );
+        let mut renderer = Renderer::default();
+        assert_eq!(
+            renderer.render(&self::frame("\x1b[1m\x1b[32m>", "\x1b", 80)),
+            "\x1b[?25l\x1b[1m\x1b[32m>^[\x1b[0m\x1b[?25h"
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! Options changed at runtime with the `set` builtin, like `set -o vi`.
+//!
+//! They live in the shell process, so a subshell gets a copy that it can change without affecting
+//! its parent.
+
+use std::cell::RefCell;
+
+use anyhow::anyhow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EditingMode {
+    #[default]
+    Emacs,
+    Vi,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ShellOptions {
+    pub editing_mode: EditingMode,
+    /// `cd` saves the directory it leaves on the directory stack, like `pushd`
+    pub auto_pushd: bool,
+}
+
+thread_local! {
+    static OPTIONS: RefCell<ShellOptions> = RefCell::new(ShellOptions::default());
+}
+
+/// Names accepted by `set -o`
+pub const NAMES: &[&str] = &["auto_pushd", "emacs", "vi"];
+
+pub fn get() -> ShellOptions {
+    OPTIONS.with(|options| options.borrow().clone())
+}
+
+pub fn editing_mode() -> EditingMode {
+    OPTIONS.with(|options| options.borrow().editing_mode)
+}
+
+pub fn auto_pushd() -> bool {
+    OPTIONS.with(|options| options.borrow().auto_pushd)
+}
+
+/// Turns an option on (`set -o name`) or off (`set +o name`)
+pub fn set(name: &str, on: bool) -> anyhow::Result<()> {
+    OPTIONS.with(|options| {
+        let mut options = options.borrow_mut();
+        match (name, on) {
+            ("vi", true) | ("emacs", false) => options.editing_mode = EditingMode::Vi,
+            ("emacs", true) | ("vi", false) => options.editing_mode = EditingMode::Emacs,
+            ("auto_pushd", on) => options.auto_pushd = on,
+            _ => return Err(anyhow!("{}: invalid option name", name)),
+        }
+        Ok(())
+    })
+}
+
+/// Whether an option is on, for `set -o` to list them
+pub fn is_on(name: &str) -> bool {
+    let options = get();
+    match name {
+        "vi" => options.editing_mode == EditingMode::Vi,
+        "emacs" => options.editing_mode == EditingMode::Emacs,
+        "auto_pushd" => options.auto_pushd,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{auto_pushd, editing_mode, is_on, set, EditingMode};
+
+    #[test]
+    fn switches_editing_mode() {
+        set("vi", true).unwrap();
+        assert_eq!(editing_mode(), EditingMode::Vi);
+        assert!(!is_on("emacs"));
+        set("vi", false).unwrap();
+        assert_eq!(editing_mode(), EditingMode::Emacs);
+        assert!(set("nope", true).is_err());
+        set("auto_pushd", true).unwrap();
+        assert!(auto_pushd() && is_on("auto_pushd"));
+        set("auto_pushd", false).unwrap();
+        assert!(!auto_pushd());
+    }
+}
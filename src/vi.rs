@@ -0,0 +1,634 @@
+//! Vi editing mode, turned on with `set -o vi`.
+//!
+//! Lines start in insert mode, where keys insert text as in emacs mode, and `Esc` switches to
+//! normal mode. Normal mode supports the motions `h l w b e 0 $ f t F T`, the operators `d c y`
+//! followed by a motion or doubled to act on the whole line, counts before commands and motions,
+//! `x X D C i a I A p P`, `u` and `Ctrl-R` to undo and redo and `.` to repeat the last change.
+//! Changes go to the undo list of the editor, so a change made in vi mode can be undone in emacs
+//! mode and the other way round.
+
+use std::mem;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::editor::{normalize_newlines, Editor};
+
+/// Cursor shapes, a block in normal mode and a bar in insert mode like in most editors
+const NORMAL_CURSOR: &str = "\x1b[2 q";
+const INSERT_CURSOR: &str = "\x1b[6 q";
+/// Goes back to the cursor shape set by the terminal
+pub const DEFAULT_CURSOR: &str = "\x1b[0 q";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViKey {
+    Char(char),
+    Escape,
+    Backspace,
+    /// A char typed with `Ctrl`, like `Ctrl-R` to redo
+    Ctrl(char),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Insert,
+    Normal,
+}
+
+/// What a key did in normal mode
+#[derive(Debug, PartialEq, Eq)]
+enum Status {
+    /// The command needs more keys, like `d` waiting for its motion
+    Pending,
+    /// The command finished without changing the text
+    Done,
+    /// The command changed the text, or entered insert mode to change it
+    Changed,
+}
+
+#[derive(Debug)]
+pub struct Vi {
+    mode: Mode,
+    /// Count typed before a command or motion, 0 if there is none
+    count: usize,
+    /// Operator waiting for a motion and the count typed before it
+    operator: Option<(char, usize)>,
+    /// `f`, `t`, `F` or `T` waiting for the char to look for
+    find: Option<char>,
+    /// Text deleted or yanked by the operators, put back by `p`
+    register: String,
+    /// Keys of the change being typed, kept while in insert mode until `Esc`
+    keys: Vec<ViKey>,
+    /// Keys of the last finished change, replayed by `.`
+    last_change: Vec<ViKey>,
+    replaying: bool,
+}
+
+impl Default for Vi {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Insert,
+            count: 0,
+            operator: None,
+            find: None,
+            register: String::new(),
+            keys: Vec::new(),
+            last_change: Vec::new(),
+            replaying: false,
+        }
+    }
+}
+
+impl Vi {
+    /// Starts a new line in insert mode. The register and the last change are kept.
+    pub fn reset(&mut self, editor: &mut Editor) {
+        self.mode = Mode::Insert;
+        self.count = 0;
+        self.operator = None;
+        self.find = None;
+        self.keys.clear();
+        editor.begin_undo(None);
+    }
+
+    /// Escape sequence that sets the cursor shape for the current mode
+    pub fn cursor_shape(&self) -> &'static str {
+        match self.mode {
+            Mode::Insert => INSERT_CURSOR,
+            Mode::Normal => NORMAL_CURSOR,
+        }
+    }
+
+    pub fn in_insert_mode(&self) -> bool {
+        self.mode == Mode::Insert
+    }
+
+    pub fn handle_key(&mut self, key: ViKey, editor: &mut Editor) {
+        match self.mode {
+            Mode::Insert => self.insert_key(key, editor),
+            Mode::Normal => {
+                if !self.replaying {
+                    self.keys.push(key);
+                }
+                match self.normal_key(key, editor) {
+                    Status::Pending => (),
+                    Status::Done => self.keys.clear(),
+                    Status::Changed => {
+                        // changes that enter insert mode are recorded until `Esc`
+                        if self.mode == Mode::Normal {
+                            self.finish_change(editor);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn insert_key(&mut self, key: ViKey, editor: &mut Editor) {
+        // only insertions started by a command are recorded for `.`
+        if !self.replaying && !self.keys.is_empty() {
+            self.keys.push(key);
+        }
+        match key {
+            ViKey::Char(char) => editor.insert(char.encode_utf8(&mut [0; 4])),
+            ViKey::Backspace => {
+                editor.delete_grapheme_backwards();
+            }
+            ViKey::Ctrl(_) => (),
+            ViKey::Escape => {
+                self.mode = Mode::Normal;
+                let text = editor.get_buffer_text();
+                let index = grapheme_index(&text, editor.cursor());
+                editor.set_cursor(byte_offset(&text, index.saturating_sub(1)));
+                self.finish_change(editor);
+            }
+        }
+    }
+
+    /// Saves the keys of a finished change for `.`, and ends its group of the undo list
+    fn finish_change(&mut self, editor: &mut Editor) {
+        if !self.replaying && !self.keys.is_empty() {
+            self.last_change = mem::take(&mut self.keys);
+        }
+        editor.end_undo();
+    }
+
+    fn normal_key(&mut self, key: ViKey, editor: &mut Editor) -> Status {
+        let char = match key {
+            ViKey::Char(char) => char,
+            ViKey::Backspace => 'h',
+            ViKey::Ctrl('r') => {
+                for _ in 0..self.take_count() {
+                    editor.redo();
+                }
+                self.clamp_cursor(editor);
+                return Status::Done;
+            }
+            ViKey::Ctrl(_) => {
+                self.count = 0;
+                return Status::Done;
+            }
+            ViKey::Escape => {
+                self.count = 0;
+                self.operator = None;
+                self.find = None;
+                return Status::Done;
+            }
+        };
+        if let Some(find) = self.find.take() {
+            return self.motion(find, Some(char), editor);
+        }
+        match char {
+            '1'..='9' => {
+                self.count = self.count * 10 + char.to_digit(10).unwrap_or(0) as usize;
+                return Status::Pending;
+            }
+            '0' if self.count > 0 => {
+                self.count *= 10;
+                return Status::Pending;
+            }
+            'f' | 't' | 'F' | 'T' => {
+                self.find = Some(char);
+                return Status::Pending;
+            }
+            _ => (),
+        }
+        if let Some((operator, _)) = self.operator {
+            return match char {
+                _ if char == operator => {
+                    // a count is accepted, but a single line has nothing more to act on
+                    self.take_count();
+                    let end = editor.get_buffer_text().len();
+                    self.operate(operator, 0, end, editor);
+                    self.status_after(operator)
+                }
+                'h' | 'l' | 'w' | 'b' | 'e' | '0' | '$' => self.motion(char, None, editor),
+                _ => {
+                    self.operator = None;
+                    self.count = 0;
+                    Status::Done
+                }
+            };
+        }
+        match char {
+            'h' | 'l' | 'w' | 'b' | 'e' | '0' | '$' => self.motion(char, None, editor),
+            'd' | 'c' | 'y' => {
+                self.operator = Some((char, mem::take(&mut self.count)));
+                Status::Pending
+            }
+            'D' | 'C' => {
+                self.operator = Some((char.to_ascii_lowercase(), 0));
+                self.motion('$', None, editor)
+            }
+            'x' | 'X' => {
+                self.operator = Some(('d', 0));
+                self.motion(if char == 'x' { 'l' } else { 'h' }, None, editor)
+            }
+            'i' | 'a' | 'I' | 'A' => {
+                self.count = 0;
+                let text = editor.get_buffer_text();
+                let cursor = match char {
+                    'a' if !text.is_empty() => {
+                        byte_offset(&text, grapheme_index(&text, editor.cursor()) + 1)
+                    }
+                    'I' => 0,
+                    'A' => text.len(),
+                    _ => editor.cursor(),
+                };
+                editor.set_cursor(cursor);
+                editor.begin_undo(None);
+                self.mode = Mode::Insert;
+                Status::Changed
+            }
+            'p' | 'P' => self.put(char == 'p', editor),
+            'u' => {
+                for _ in 0..self.take_count() {
+                    editor.undo();
+                }
+                self.clamp_cursor(editor);
+                Status::Done
+            }
+            '.' => {
+                let count = self.take_count();
+                let keys = self.last_change.clone();
+                self.replaying = true;
+                for _ in 0..count {
+                    for &key in &keys {
+                        self.handle_key(key, editor);
+                    }
+                }
+                self.replaying = false;
+                Status::Done
+            }
+            _ => {
+                self.count = 0;
+                Status::Done
+            }
+        }
+    }
+
+    /// Inserts pasted text at the cursor as a single change, in either mode
+    pub fn paste(&mut self, text: &str, editor: &mut Editor) {
+        editor.begin_undo(None);
+        editor.insert(&normalize_newlines(text));
+        editor.end_undo();
+        self.clamp_cursor(editor);
+    }
+
+    /// Count of the command, multiplied by the count typed before the operator as in `2d3w`
+    fn take_count(&mut self) -> usize {
+        let operator_count = self.operator.map_or(0, |(_, count)| count);
+        mem::take(&mut self.count).max(1) * operator_count.max(1)
+    }
+
+    /// Moves the cursor, or applies the pending operator to the text between the cursor and
+    /// the target of the motion
+    fn motion(&mut self, motion: char, find: Option<char>, editor: &mut Editor) -> Status {
+        let count = self.take_count();
+        let text = editor.get_buffer_text();
+        let graphemes: Vec<&str> = text.graphemes(true).collect();
+        let position = grapheme_index(&text, editor.cursor());
+        // `cw` changes up to the end of the word, like `ce`
+        let change_word = matches!(self.operator, Some(('c', _)))
+            && motion == 'w'
+            && graphemes
+                .get(position)
+                .is_some_and(|g| class(g) != Class::Blank);
+        let target = if change_word {
+            Some((change_word_end(&graphemes, position, count), true))
+        } else {
+            target(&graphemes, position, motion, find, count)
+        };
+        let Some((target, inclusive)) = target else {
+            self.operator = None;
+            return Status::Done;
+        };
+        match self.operator.take() {
+            Some((operator, _)) => {
+                let (start, end) = if target < position {
+                    (target, position)
+                } else {
+                    (position, target + inclusive as usize)
+                };
+                let end = end.min(graphemes.len());
+                self.operate(
+                    operator,
+                    byte_offset(&text, start),
+                    byte_offset(&text, end),
+                    editor,
+                );
+                self.status_after(operator)
+            }
+            None => {
+                editor.set_cursor(byte_offset(&text, target));
+                self.clamp_cursor(editor);
+                Status::Done
+            }
+        }
+    }
+
+    fn operate(&mut self, operator: char, start: usize, end: usize, editor: &mut Editor) {
+        self.operator = None;
+        match operator {
+            'y' => {
+                let text = editor.get_buffer_text();
+                self.register = text[start..end].to_string();
+                editor.set_cursor(start);
+            }
+            _ => {
+                editor.begin_undo(None);
+                if start != end {
+                    self.register = editor.delete_range(start, end);
+                }
+                editor.set_cursor(start);
+                if operator == 'c' {
+                    self.mode = Mode::Insert;
+                    return;
+                }
+            }
+        }
+        self.clamp_cursor(editor);
+    }
+
+    fn status_after(&self, operator: char) -> Status {
+        match operator {
+            'y' => Status::Done,
+            _ => Status::Changed,
+        }
+    }
+
+    /// Puts the register after the cursor, or before it, leaving the cursor on its last char
+    fn put(&mut self, after: bool, editor: &mut Editor) -> Status {
+        let count = self.take_count();
+        if self.register.is_empty() {
+            return Status::Done;
+        }
+        editor.begin_undo(None);
+        let text = editor.get_buffer_text();
+        if after && !text.is_empty() {
+            editor.set_cursor(byte_offset(
+                &text,
+                grapheme_index(&text, editor.cursor()) + 1,
+            ));
+        }
+        editor.insert(&self.register.repeat(count));
+        let text = editor.get_buffer_text();
+        let index = grapheme_index(&text, editor.cursor());
+        editor.set_cursor(byte_offset(&text, index.saturating_sub(1)));
+        Status::Changed
+    }
+
+    /// In normal mode the cursor sits on a char, never after the last one
+    fn clamp_cursor(&self, editor: &mut Editor) {
+        let text = editor.get_buffer_text();
+        if self.mode == Mode::Normal && editor.cursor() >= text.len() {
+            let last = text.graphemes(true).count().saturating_sub(1);
+            editor.set_cursor(byte_offset(&text, last));
+        }
+    }
+}
+
+/// Index of the grapheme at byte `offset`
+fn grapheme_index(text: &str, offset: usize) -> usize {
+    text.grapheme_indices(true)
+        .take_while(|(index, _)| *index < offset)
+        .count()
+}
+
+/// Byte offset of the grapheme at `index`, or the length of the text past the last one
+fn byte_offset(text: &str, index: usize) -> usize {
+    text.grapheme_indices(true)
+        .nth(index)
+        .map_or(text.len(), |(offset, _)| offset)
+}
+
+/// Vi words are runs of letters, digits and underscores, or runs of other non blank chars
+#[derive(Debug, PartialEq, Eq)]
+enum Class {
+    Blank,
+    Word,
+    Punctuation,
+}
+
+fn class(grapheme: &str) -> Class {
+    match grapheme.chars().next() {
+        Some(char) if char.is_whitespace() => Class::Blank,
+        Some(char) if char.is_alphanumeric() || char == '_' => Class::Word,
+        _ => Class::Punctuation,
+    }
+}
+
+/// Grapheme that a motion moves to from `position`, and whether an operator includes it
+fn target(
+    graphemes: &[&str],
+    position: usize,
+    motion: char,
+    find: Option<char>,
+    count: usize,
+) -> Option<(usize, bool)> {
+    let len = graphemes.len();
+    let repeat =
+        |step: fn(&[&str], usize) -> usize| (0..count).fold(position, |i, _| step(graphemes, i));
+    let target = match motion {
+        'h' => (position.saturating_sub(count), false),
+        'l' => ((position + count).min(len), false),
+        'w' => (repeat(next_word_start), false),
+        'b' => (repeat(previous_word_start), false),
+        'e' => (repeat(word_end), true),
+        '0' => (0, false),
+        '$' => (len.saturating_sub(1), len > 0),
+        'f' | 't' | 'F' | 'T' => {
+            let find = find?.to_string();
+            let mut found = position;
+            for _ in 0..count {
+                found = if motion.is_ascii_lowercase() {
+                    found
+                        + 1
+                        + graphemes
+                            .get(found + 1..)?
+                            .iter()
+                            .position(|g| *g == find)?
+                } else {
+                    graphemes[..found].iter().rposition(|g| *g == find)?
+                };
+            }
+            match motion {
+                'f' => (found, true),
+                't' => (found - 1, true),
+                'F' => (found, false),
+                _ => (found + 1, false),
+            }
+        }
+        _ => return None,
+    };
+    Some(target)
+}
+
+fn next_word_start(graphemes: &[&str], position: usize) -> usize {
+    let mut i = position;
+    let Some(start) = graphemes.get(i) else {
+        return i;
+    };
+    let start = class(start);
+    if start != Class::Blank {
+        while graphemes.get(i).is_some_and(|g| class(g) == start) {
+            i += 1;
+        }
+    }
+    while graphemes.get(i).is_some_and(|g| class(g) == Class::Blank) {
+        i += 1;
+    }
+    i
+}
+
+fn previous_word_start(graphemes: &[&str], position: usize) -> usize {
+    let mut i = position.min(graphemes.len());
+    while i > 0 && class(graphemes[i - 1]) == Class::Blank {
+        i -= 1;
+    }
+    let Some(word) = i.checked_sub(1).map(|i| class(graphemes[i])) else {
+        return 0;
+    };
+    while i > 0 && class(graphemes[i - 1]) == word {
+        i -= 1;
+    }
+    i
+}
+
+/// Like `word_end`, but a cursor already at the end of a word stays there on the first step
+fn change_word_end(graphemes: &[&str], position: usize, count: usize) -> usize {
+    let same_word = |i: usize| {
+        graphemes
+            .get(i + 1)
+            .is_some_and(|next| class(next) == class(graphemes[i]))
+    };
+    let mut i = if same_word(position) {
+        word_end(graphemes, position)
+    } else {
+        position
+    };
+    for _ in 1..count {
+        i = word_end(graphemes, i);
+    }
+    i
+}
+
+fn word_end(graphemes: &[&str], position: usize) -> usize {
+    let mut i = position + 1;
+    while graphemes.get(i).is_some_and(|g| class(g) == Class::Blank) {
+        i += 1;
+    }
+    let Some(word) = graphemes.get(i).map(|g| class(g)) else {
+        return graphemes.len().saturating_sub(1).max(position);
+    };
+    while graphemes.get(i + 1).is_some_and(|g| class(g) == word) {
+        i += 1;
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Vi, ViKey};
+    use crate::editor::Editor;
+    use crate::prompt::{Prompt, PromptContext};
+
+    /// Types `keys` in a new line, where `<` stands for `Esc` and `^` for `Ctrl-R`
+    fn type_keys(vi: &mut Vi, editor: &mut Editor, keys: &str) {
+        for char in keys.chars() {
+            let key = match char {
+                '<' => ViKey::Escape,
+                '^' => ViKey::Ctrl('r'),
+                _ => ViKey::Char(char),
+            };
+            vi.handle_key(key, editor);
+        }
+    }
+
+    fn run(keys: &str) -> (String, usize) {
+        let mut editor = Editor::new(Prompt::new("> ", &PromptContext::default()));
+        let mut vi = Vi::default();
+        vi.reset(&mut editor);
+        type_keys(&mut vi, &mut editor, keys);
+        (editor.get_buffer_text(), editor.cursor())
+    }
+
+    #[test]
+    fn moves_with_motions() {
+        assert_eq!(run("echo foo.bar baz<0w").1, 5);
+        assert_eq!(run("echo foo.bar baz<02w").1, 8);
+        assert_eq!(run("echo foo.bar baz<b").1, 13);
+        assert_eq!(run("echo foo.bar baz<0e").1, 3);
+        assert_eq!(run("echo foo.bar baz<0$").1, 15);
+        assert_eq!(run("echo foo.bar baz<0fa").1, 10);
+        assert_eq!(run("echo foo.bar baz<02fa").1, 14);
+        assert_eq!(run("echo foo.bar baz<0tb").1, 8);
+        assert_eq!(run("echo foo.bar baz<Fo").1, 7);
+        assert_eq!(run("echo foo.bar baz<3h").1, 12);
+    }
+
+    #[test]
+    fn applies_operators() {
+        assert_eq!(run("echo foo bar<0dw").0, "foo bar");
+        assert_eq!(run("echo foo bar<0d2w").0, "bar");
+        assert_eq!(run("echo foo bar<02dw").0, "bar");
+        assert_eq!(run("echo foo bar<0cwprintf<").0, "printf foo bar");
+        assert_eq!(run("echo foo bar<0wD").0, "echo ");
+        assert_eq!(run("echo foo bar<dd").0, "");
+        assert_eq!(run("echo foo bar<0dfo").0, " foo bar");
+        assert_eq!(run("echo foo bar<0x3x").0, " foo bar");
+        assert_eq!(run("echo foo bar<db").0, "echo foo r");
+    }
+
+    #[test]
+    fn yanks_and_puts() {
+        assert_eq!(run("ls -la<0ywP").0, "ls ls -la");
+        assert_eq!(run("ls -la<0ywwp").0, "ls -ls la");
+        assert_eq!(run("ab<0x2p").0, "baa");
+        assert_eq!(run("ab<yyP").0, "abab");
+    }
+
+    #[test]
+    fn undoes_and_repeats_changes() {
+        assert_eq!(run("echo foo bar<0dwu").0, "echo foo bar");
+        assert_eq!(run("echo foo bar<0dw.").0, "bar");
+        assert_eq!(run("echo foo bar<0dwuu").0, "");
+        assert_eq!(run("ls<Aa<..").0, "lsaaa");
+        assert_eq!(run("ls<A -l<u").0, "ls");
+        assert_eq!(run("a b c<0cwx<w.").0, "x x c");
+        assert_eq!(run("echo foo bar<0dwu^").0, "foo bar");
+        assert_eq!(run("echo foo bar<0dwdwuu2^").0, "bar");
+        assert_eq!(run("ls<A -l<u^").0, "ls -l");
+    }
+
+    #[test]
+    fn shares_the_undo_list_with_emacs_mode() {
+        let mut editor = Editor::new(Prompt::new("> ", &PromptContext::default()));
+        let mut vi = Vi::default();
+        vi.reset(&mut editor);
+        type_keys(&mut vi, &mut editor, "echo foo bar<0dw");
+        assert!(editor.undo());
+        assert_eq!(editor.get_buffer_text(), "echo foo bar");
+        editor.begin_undo(None);
+        editor.insert("ls; ");
+        type_keys(&mut vi, &mut editor, "u");
+        assert_eq!(editor.get_buffer_text(), "echo foo bar");
+    }
+
+    #[test]
+    fn undoes_paste_as_one_change() {
+        let mut editor = Editor::new(Prompt::new("> ", &PromptContext::default()));
+        let mut vi = Vi::default();
+        vi.reset(&mut editor);
+        type_keys(&mut vi, &mut editor, "ls ");
+        vi.paste("a\r\nb", &mut editor);
+        assert_eq!(editor.get_buffer_text(), "ls a\nb");
+        type_keys(&mut vi, &mut editor, "<u");
+        assert_eq!(editor.get_buffer_text(), "ls ");
+    }
+
+    #[test]
+    fn edits_wide_graphemes() {
+        assert_eq!(run("日本語<0x").0, "本語");
+        assert_eq!(run("日本語<0l").1, 3);
+        assert_eq!(run("e\u{301}a<0x").0, "a");
+    }
+}